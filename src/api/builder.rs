@@ -1,7 +1,8 @@
-use std::{env, error::Error, path::PathBuf, sync::Arc};
+use std::{env, error::Error, path::PathBuf, sync::Arc, time::Duration};
 
 use crate::{
-    config::ProtofetchConfig, engine::ParallelConfig, git::cache::ProtofetchGitCache, Protofetch,
+    config::ProtofetchConfig, engine::ParallelConfig, git::cache::ProtofetchGitCache,
+    BreakingChangePolicy, FileDiffMode, OutputFormat, Protofetch,
 };
 
 #[derive(Default)]
@@ -14,6 +15,11 @@ pub struct ProtofetchBuilder {
     output_directory_name: Option<PathBuf>,
     jobs: Option<usize>,
     copy_jobs: Option<usize>,
+    lock_timeout: Option<Duration>,
+    strict_imports: bool,
+    breaking_changes: BreakingChangePolicy,
+    file_diff: FileDiffMode,
+    format: OutputFormat,
 }
 
 impl ProtofetchBuilder {
@@ -63,13 +69,53 @@ impl ProtofetchBuilder {
         self
     }
 
-    /// Maximum number of in-flight disk jobs (worktree + copy). Defaults to
+    /// Maximum number of in-flight disk jobs (tree listing + copy). Defaults to
     /// `max(4, num_cpus / 2)`.
     pub fn copy_jobs(mut self, jobs: usize) -> Self {
         self.copy_jobs = Some(jobs.max(1));
         self
     }
 
+    /// How long to wait for other protofetch processes holding a lock on the cache.
+    ///
+    /// Defaults to 300 seconds.
+    pub fn lock_timeout(mut self, timeout: Duration) -> Self {
+        self.lock_timeout = Some(timeout);
+        self
+    }
+
+    /// Fail fetching when imports reached by pruning cannot be resolved, as if `strict_imports`
+    /// was set in the module toml config.
+    pub fn strict_imports(mut self, strict: bool) -> Self {
+        self.strict_imports = strict;
+        self
+    }
+
+    /// Whether to check dependencies that move to a new commit on update for breaking changes.
+    ///
+    /// Defaults to [`BreakingChangePolicy::Ignore`].
+    pub fn breaking_changes(mut self, policy: BreakingChangePolicy) -> Self {
+        self.breaking_changes = policy;
+        self
+    }
+
+    /// Whether to print which files of the output change for dependencies that move to a new
+    /// commit on update. Only files that are copied under the current rules are compared.
+    ///
+    /// Defaults to [`FileDiffMode::Off`].
+    pub fn file_diff(mut self, mode: FileDiffMode) -> Self {
+        self.file_diff = mode;
+        self
+    }
+
+    /// Whether `fetch`, `lock`, `update` and `clean` print their results as JSON on stdout.
+    ///
+    /// Defaults to [`OutputFormat::Text`].
+    pub fn output_format(mut self, format: OutputFormat) -> Self {
+        self.format = format;
+        self
+    }
+
     pub fn try_build(self) -> Result<Protofetch, Box<dyn Error>> {
         let config = ProtofetchConfig::load()?;
 
@@ -81,6 +127,11 @@ impl ProtofetchBuilder {
             cache_directory_path,
             jobs,
             copy_jobs,
+            lock_timeout,
+            strict_imports,
+            breaking_changes,
+            file_diff,
+            format,
         } = self;
         let root = match root {
             Some(root) => root,
@@ -98,6 +149,7 @@ impl ProtofetchBuilder {
             config.default_protocol,
             config.git_backend,
             config.git_executable,
+            lock_timeout.unwrap_or(config.lock_timeout),
         )?;
 
         // Build the effective ParallelConfig: defaults < config < explicit builder calls.
@@ -122,6 +174,10 @@ impl ProtofetchBuilder {
             lock_file_name,
             output_directory_name,
             parallel,
+            strict_imports,
+            breaking_changes,
+            file_diff,
+            format,
         })
     }
 }
@@ -2,18 +2,43 @@ use std::{
     error::Error,
     path::{Path, PathBuf},
     sync::Arc,
+    time::Duration,
 };
 
 use crate::{
-    cli::command_handlers::{do_clean, do_fetch, do_init, do_lock, do_migrate},
+    cli::{
+        command_handlers::{
+            do_add, do_cache_gc, do_cache_info, do_cache_list, do_cache_verify, do_check_compile,
+            do_clean, do_fetch, do_init, do_lock, do_migrate, do_remove, LockOptions,
+        },
+        report,
+    },
+    config,
     engine::ParallelConfig,
     git::cache::ProtofetchGitCache,
+    model::protofetch::{lock, schema},
 };
 
 mod builder;
 
 pub use builder::ProtofetchBuilder;
 
+/// Returns a stable identifier of the kind of an error returned by [`Protofetch`], such as
+/// `lock_file_missing` or `unresolved_imports`, for consumption by other tools.
+pub fn error_code(error: &(dyn Error + 'static)) -> &'static str {
+    report::error_code(error)
+}
+
+/// Returns the JSON Schema of a configuration file, e.g. for editors to complete and validate it.
+pub fn json_schema(kind: SchemaKind) -> String {
+    let schema = match kind {
+        SchemaKind::Descriptor => schema::schema(),
+        SchemaKind::LockFile => lock::schema(),
+        SchemaKind::Config => config::schema(),
+    };
+    serde_json::to_string_pretty(&schema).expect("a JSON Schema is valid JSON")
+}
+
 pub struct Protofetch {
     cache: Arc<ProtofetchGitCache>,
     root: PathBuf,
@@ -21,6 +46,10 @@ pub struct Protofetch {
     lock_file_name: PathBuf,
     output_directory_name: Option<PathBuf>,
     parallel: ParallelConfig,
+    strict_imports: bool,
+    breaking_changes: BreakingChangePolicy,
+    file_diff: FileDiffMode,
+    format: OutputFormat,
 }
 
 #[allow(dead_code)]
@@ -51,6 +80,66 @@ pub enum LockUpdateMode {
     Full,
 }
 
+/// What to do about breaking changes of dependencies that move to a new commit on update.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum BreakingChangePolicy {
+    /// Do not compare the previously locked and the updated commits.
+    #[default]
+    Ignore,
+    /// Warn about breaking changes.
+    Report,
+    /// Fail the update without writing the lock file if there are breaking changes.
+    Deny,
+}
+
+/// Which changes of the output to print for dependencies that move to a new commit on update.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum FileDiffMode {
+    /// Do not print changes.
+    #[default]
+    Off,
+    /// Print the added, removed and changed files.
+    Files,
+    /// Print the unified diff of every added, removed and changed file.
+    Unified,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum OutputFormat {
+    /// Human-readable text.
+    #[default]
+    Text,
+    /// JSON, for consumption by other tools.
+    Json,
+}
+
+/// A configuration file described by [`json_schema`].
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SchemaKind {
+    /// The module descriptor, `protofetch.toml` by default.
+    Descriptor,
+    /// The lock file, `protofetch.lock` by default.
+    LockFile,
+    /// The user configuration, `config.toml`.
+    Config,
+}
+
+/// A dependency to add to the module descriptor with [`Protofetch::add`].
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct NewDependency {
+    /// Url of the repository, e.g. `github.com/org/repo`.
+    pub url: String,
+    /// Name of the dependency. Defaults to the name of the repository.
+    pub name: Option<String>,
+    pub revision: Option<String>,
+    pub branch: Option<String>,
+    /// `https` or `ssh`. Defaults to the protocol of the user configuration.
+    pub protocol: Option<String>,
+    pub prune: bool,
+    /// Policies of the files to copy, e.g. `/proto/*`.
+    pub allow_policies: Vec<String>,
+}
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum DependencyUpdate {
     Latest { name: String },
@@ -87,6 +176,59 @@ impl Protofetch {
             &self.lock_file_name,
             self.output_directory_name.as_deref(),
             self.parallel,
+            self.strict_imports,
+            self.format,
+        )
+    }
+
+    /// Adds a dependency to the toml configuration file, keeping its formatting and comments.
+    ///
+    /// The dependency is resolved and added to the lock file first, so that the configuration
+    /// file is left unchanged if its revision or branch does not exist.
+    pub fn add(&self, dependency: NewDependency) -> Result<(), Box<dyn Error>> {
+        do_add(
+            dependency,
+            self.cache.clone(),
+            &self.root,
+            &self.module_file_name,
+            &self.lock_file_name,
+            self.parallel,
+            self.format,
+        )
+    }
+
+    /// Removes a dependency from the toml configuration file, keeping its formatting and comments.
+    ///
+    /// Lock file entries that no other dependency needs are dropped, and if the output directory
+    /// exists, the files that are no longer copied are removed from it.
+    pub fn remove(&self, name: &str) -> Result<(), Box<dyn Error>> {
+        do_remove(
+            name,
+            self.cache.clone(),
+            &self.root,
+            &self.module_file_name,
+            &self.lock_file_name,
+            self.output_directory_name.as_deref(),
+            self.parallel,
+            self.format,
+        )
+    }
+
+    /// Compiles the fetched proto files, without using protoc, and optionally writes their
+    /// `FileDescriptorSet` to `descriptor_set`.
+    ///
+    /// Imports are also resolved against `includes`, e.g. for imports provided by the toolchain.
+    pub fn check_compile(
+        &self,
+        includes: &[PathBuf],
+        descriptor_set: Option<&Path>,
+    ) -> Result<(), Box<dyn Error>> {
+        do_check_compile(
+            &self.root,
+            &self.module_file_name,
+            self.output_directory_name.as_deref(),
+            includes,
+            descriptor_set,
         )
     }
 
@@ -97,7 +239,28 @@ impl Protofetch {
     }
 
     /// Creates, updates or verifies a lock file based on the toml configuration file.
+    ///
+    /// Dependencies that move to a new commit are checked for breaking changes according to the
+    /// configured [`BreakingChangePolicy`], and their changes of the output are printed according
+    /// to the configured [`FileDiffMode`].
     pub fn update(&self, lock_update_mode: LockUpdateMode) -> Result<(), Box<dyn Error>> {
+        self.do_lock(lock_update_mode, false)?;
+        Ok(())
+    }
+
+    /// Resolves the dependencies like [`Protofetch::update`], but prints the changes to the lock
+    /// file instead of writing it.
+    ///
+    /// Returns whether the lock file would change.
+    pub fn update_dry_run(&self, lock_update_mode: LockUpdateMode) -> Result<bool, Box<dyn Error>> {
+        self.do_lock(lock_update_mode, true)
+    }
+
+    fn do_lock(
+        &self,
+        lock_update_mode: LockUpdateMode,
+        dry_run: bool,
+    ) -> Result<bool, Box<dyn Error>> {
         do_lock(
             lock_update_mode,
             self.cache.clone(),
@@ -105,8 +268,13 @@ impl Protofetch {
             &self.module_file_name,
             &self.lock_file_name,
             self.parallel,
-        )?;
-        Ok(())
+            LockOptions {
+                breaking_changes: self.breaking_changes,
+                file_diff: self.file_diff,
+                dry_run,
+                format: self.format,
+            },
+        )
     }
 
     /// Migrates a protodep.toml file to the protofetch format
@@ -130,6 +298,7 @@ impl Protofetch {
             &self.module_file_name,
             &self.lock_file_name,
             self.output_directory_name.as_deref(),
+            self.format,
         )
     }
 
@@ -137,4 +306,37 @@ impl Protofetch {
         self.cache.clear()?;
         Ok(())
     }
+
+    /// Removes cached repositories and worktrees not used within `max_age` and compacts the
+    /// remaining repositories.
+    ///
+    /// If `projects` is not empty, cached repositories that are not referenced by the lock file
+    /// of any of these project directories are removed as well.
+    pub fn gc_cache(&self, max_age: Duration, projects: &[PathBuf]) -> Result<(), Box<dyn Error>> {
+        do_cache_gc(
+            &self.cache,
+            &self.root,
+            &self.lock_file_name,
+            max_age,
+            projects,
+        )
+    }
+
+    /// Prints the cached repositories with their size and last use.
+    pub fn list_cache(&self, format: OutputFormat) -> Result<(), Box<dyn Error>> {
+        do_cache_list(&self.cache, format)
+    }
+
+    /// Prints details about the cached repository for the given dependency url or remote url.
+    pub fn cache_info(&self, url: &str, format: OutputFormat) -> Result<(), Box<dyn Error>> {
+        do_cache_info(&self.cache, url, format)
+    }
+
+    /// Checks the integrity of all cached repositories and worktrees.
+    ///
+    /// With `repair`, broken repositories are quarantined and re-cloned on the next fetch,
+    /// otherwise finding any broken entry is an error.
+    pub fn verify_cache(&self, repair: bool) -> Result<(), Box<dyn Error>> {
+        do_cache_verify(&self.cache, repair)
+    }
 }
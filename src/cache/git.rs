@@ -1,6 +1,7 @@
 use std::path::PathBuf;
 
 use crate::{
+    flock::LockKind,
     git::cache::ProtofetchGitCache,
     model::protofetch::{Coordinate, RevisionSpecification},
 };
@@ -14,19 +15,34 @@ impl RepositoryCache for ProtofetchGitCache {
         specification: &RevisionSpecification,
         commit_hash: &str,
     ) -> anyhow::Result<()> {
+        let _lock = self.lock_coordinate(coordinate, LockKind::Exclusive)?;
         let repository = self.repository(coordinate)?;
         repository.fetch_commit(specification, commit_hash)?;
         Ok(())
     }
 
-    fn create_worktree(
+    fn list_files(
         &self,
         coordinate: &Coordinate,
         commit_hash: &str,
-    ) -> anyhow::Result<PathBuf> {
-        let path = self
-            .repository(coordinate)?
-            .create_worktree(coordinate, commit_hash)?;
-        Ok(path)
+    ) -> anyhow::Result<Vec<PathBuf>> {
+        let _lock = self.lock_coordinate(coordinate, LockKind::Shared)?;
+        let files = self
+            .fetched_repository(coordinate)?
+            .list_files(commit_hash)?;
+        Ok(files)
+    }
+
+    fn read_files(
+        &self,
+        coordinate: &Coordinate,
+        commit_hash: &str,
+        paths: &[PathBuf],
+    ) -> anyhow::Result<Vec<Vec<u8>>> {
+        let _lock = self.lock_coordinate(coordinate, LockKind::Shared)?;
+        let contents = self
+            .fetched_repository(coordinate)?
+            .read_files(commit_hash, paths)?;
+        Ok(contents)
     }
 }
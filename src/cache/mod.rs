@@ -12,11 +12,20 @@ pub trait RepositoryCache: Send + Sync {
         commit_hash: &str,
     ) -> anyhow::Result<()>;
 
-    fn create_worktree(
+    /// Lists the paths of all files in an already fetched commit, relative to the repository root.
+    fn list_files(
         &self,
         coordinate: &Coordinate,
         commit_hash: &str,
-    ) -> anyhow::Result<PathBuf>;
+    ) -> anyhow::Result<Vec<PathBuf>>;
+
+    /// Reads files from an already fetched commit, in the order of `paths`.
+    fn read_files(
+        &self,
+        coordinate: &Coordinate,
+        commit_hash: &str,
+        paths: &[PathBuf],
+    ) -> anyhow::Result<Vec<Vec<u8>>>;
 }
 
 impl<T> RepositoryCache for Arc<T>
@@ -32,11 +41,20 @@ where
         T::fetch(self, coordinate, specification, commit_hash)
     }
 
-    fn create_worktree(
+    fn list_files(
+        &self,
+        coordinate: &Coordinate,
+        commit_hash: &str,
+    ) -> anyhow::Result<Vec<PathBuf>> {
+        T::list_files(self, coordinate, commit_hash)
+    }
+
+    fn read_files(
         &self,
         coordinate: &Coordinate,
         commit_hash: &str,
-    ) -> anyhow::Result<PathBuf> {
-        T::create_worktree(self, coordinate, commit_hash)
+        paths: &[PathBuf],
+    ) -> anyhow::Result<Vec<Vec<u8>>> {
+        T::read_files(self, coordinate, commit_hash, paths)
     }
 }
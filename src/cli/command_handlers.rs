@@ -1,12 +1,29 @@
-use log::{debug, info};
+use log::{debug, info, warn};
+use thiserror::Error;
 
 use crate::{
-    api::{DependencyUpdate, LockMode, LockUpdateMode},
-    engine::{self, model::ResolvedRootModule, ParallelConfig},
-    git::cache::ProtofetchGitCache,
+    api::{
+        BreakingChangePolicy, DependencyUpdate, FileDiffMode, LockMode, LockUpdateMode,
+        NewDependency, OutputFormat,
+    },
+    cache::RepositoryCache,
+    cli::report::{
+        self, AddReport, BreakingChangeReport, CleanReport, FetchReport, FileDiffReport,
+        LockChangeReport, LockReport, ModuleDiffReport, OutputSummary, RemoveReport, WarningReport,
+    },
+    engine::{
+        self,
+        model::{ResolvedModule, ResolvedRootModule},
+        BreakingChange, FileDiff, OutputFiles, ParallelConfig,
+    },
+    git::cache::{CacheEntry, ProtofetchGitCache},
     model::{
         protodep::ProtodepDescriptor,
-        protofetch::{lock::LockFile, Descriptor, ModuleName},
+        protofetch::{
+            edit::{DependencyTable, DescriptorDocument},
+            lock::{LockChange, LockFile, LockedCoordinate, LockedDependency},
+            Coordinate, Descriptor, ImportRules, ModuleName,
+        },
     },
     resolver::{LockFileModuleResolver, ModuleResolver},
 };
@@ -15,11 +32,13 @@ use std::{
     error::Error,
     path::{Path, PathBuf},
     sync::Arc,
+    time::{Duration, SystemTime},
 };
 
 const DEFAULT_OUTPUT_DIRECTORY_NAME: &str = "proto_src";
 
 /// Handler to fetch command
+#[allow(clippy::too_many_arguments)]
 pub fn do_fetch(
     lock_mode: LockMode,
     cache: Arc<ProtofetchGitCache>,
@@ -28,22 +47,31 @@ pub fn do_fetch(
     lock_file_name: &Path,
     output_directory_name: Option<&Path>,
     parallel: ParallelConfig,
+    strict_imports: bool,
+    format: OutputFormat,
 ) -> Result<(), Box<dyn Error>> {
     let module_descriptor = load_module_descriptor(root, module_file_name)?;
-    let output_directory_name = output_directory_name
-        .map(Path::to_path_buf)
-        .or_else(|| module_descriptor.proto_out_dir.as_ref().map(PathBuf::from))
-        .unwrap_or_else(|| PathBuf::from(DEFAULT_OUTPUT_DIRECTORY_NAME));
-    let proto_out = root.join(output_directory_name);
+    let proto_out = output_directory(root, output_directory_name, &module_descriptor)?;
 
-    let resolved = do_lock_inner(
+    let LockOutcome {
+        resolved,
+        lockfile,
+        changed,
+        ..
+    } = do_lock_inner(
         lock_mode.into(),
         cache.clone(),
+        &module_descriptor,
         root,
-        module_file_name,
         lock_file_name,
         parallel,
+        LockOptions::default(),
     )?;
+    let mut warnings = resolved
+        .warnings
+        .iter()
+        .map(WarningReport::from)
+        .collect::<Vec<_>>();
 
     engine::fetch(
         cache.clone(),
@@ -52,19 +80,121 @@ pub fn do_fetch(
         parallel.network_jobs,
     )?;
 
-    engine::copy(
+    let report = engine::copy(
         cache.clone(),
         resolved,
-        proto_out,
+        proto_out.clone(),
+        module_descriptor.output_layout,
+        &ImportRules {
+            strict_imports: strict_imports || module_descriptor.imports.strict_imports,
+            ..module_descriptor.imports.clone()
+        },
         parallel.copy_jobs,
-        cache.coord_locks().clone(),
     )?;
+    info!(
+        "Synchronized {}: {} added, {} changed, {} removed, {} unchanged",
+        proto_out.display(),
+        report.added,
+        report.changed,
+        report.removed,
+        report.unchanged
+    );
+
+    if format == OutputFormat::Json {
+        warnings.extend(report.warnings.iter().map(WarningReport::from));
+        let report = FetchReport {
+            modules: &lockfile.dependencies,
+            lock_file_changed: changed,
+            output: OutputSummary {
+                directory: proto_out,
+                added: report.added,
+                changed: report.changed,
+                removed: report.removed,
+                unchanged: report.unchanged,
+            },
+            warnings,
+        };
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    }
 
     Ok(())
 }
 
+/// Options of the lock and update commands.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LockOptions {
+    pub breaking_changes: BreakingChangePolicy,
+    pub file_diff: FileDiffMode,
+    /// Print the changes to the lock file instead of writing it.
+    pub dry_run: bool,
+    pub format: OutputFormat,
+}
+
+/// Errors of the commands that do not come from resolving, fetching or copying.
+#[derive(Error, Debug)]
+pub enum CommandError {
+    #[error("Lock file does not exist")]
+    LockFileMissing,
+    #[error("No dependency named {0}")]
+    UnknownDependency(String),
+    #[error("Dependency {0} selected more than once")]
+    DependencySelectedTwice(String),
+    #[error("Found {0} breaking changes, the lock file was not updated")]
+    BreakingChanges(usize),
+    #[error("{} does not exist, fetch the dependencies first", .0.display())]
+    OutputMissing(PathBuf),
+    #[error("Dependency {0} already exists")]
+    DependencyExists(String),
+    #[error("{} contains the project and cannot be used as the output directory", .0.display())]
+    OutputContainsProject(PathBuf),
+}
+
+impl CommandError {
+    /// A stable identifier of the kind of the error, for consumption by other tools.
+    pub fn code(&self) -> &'static str {
+        match self {
+            CommandError::LockFileMissing => "lock_file_missing",
+            CommandError::UnknownDependency(_) => "unknown_dependency",
+            CommandError::DependencySelectedTwice(_) => "dependency_selected_twice",
+            CommandError::BreakingChanges(_) => "breaking_changes",
+            CommandError::OutputMissing(_) => "output_missing",
+            CommandError::DependencyExists(_) => "dependency_exists",
+            CommandError::OutputContainsProject(_) => "output_contains_project",
+        }
+    }
+}
+
+/// The result of resolving the dependencies of the lock file.
+struct LockOutcome {
+    resolved: ResolvedRootModule,
+    lockfile: LockFile,
+    /// The lock file before the update, if it exists and can be read.
+    previous_lock: Option<LockFile>,
+    /// Whether the lock file changed, or would change in a dry run.
+    changed: bool,
+    breaking_changes: Vec<(ModuleName, BreakingChange)>,
+    diffs: Vec<ModuleDiff>,
+}
+
+/// The files of the output that change for a module that moved to a new commit.
+struct ModuleDiff {
+    name: ModuleName,
+    old_commit_hash: String,
+    new_commit_hash: String,
+    files: Vec<FileDiff>,
+}
+
+/// A change of the lock file, with the tags of the old and new commits.
+struct DescribedChange<'a> {
+    change: LockChange<'a>,
+    old_tags: Vec<String>,
+    new_tags: Vec<String>,
+}
+
 /// Handler to lock command. Loads dependency descriptor from protofetch toml
 /// or protodep toml. Generates a lock file based on the protofetch.toml.
+///
+/// Returns whether the lock file changed, or would change in a dry run.
 pub fn do_lock(
     lock_update_mode: LockUpdateMode,
     cache: Arc<ProtofetchGitCache>,
@@ -72,31 +202,80 @@ pub fn do_lock(
     module_file_name: &Path,
     lock_file_name: &Path,
     parallel: ParallelConfig,
-) -> Result<ResolvedRootModule, Box<dyn Error>> {
-    do_lock_inner(
+    options: LockOptions,
+) -> Result<bool, Box<dyn Error>> {
+    let module_descriptor = load_module_descriptor(root, module_file_name)?;
+    let outcome = do_lock_inner(
         lock_update_mode,
-        cache,
+        cache.clone(),
+        &module_descriptor,
         root,
-        module_file_name,
         lock_file_name,
         parallel,
-    )
+        options,
+    )?;
+    let changes = if options.dry_run || options.format == OutputFormat::Json {
+        describe_lock_changes(&cache, &outcome)
+    } else {
+        Vec::new()
+    };
+
+    match options.format {
+        OutputFormat::Text => {
+            if options.dry_run {
+                print_lock_changes(&changes);
+            }
+            print_file_diffs(&outcome.diffs);
+        }
+        OutputFormat::Json => {
+            let report = LockReport {
+                dry_run: options.dry_run,
+                changed: outcome.changed,
+                modules: &outcome.lockfile.dependencies,
+                changes: changes.into_iter().map(lock_change_report).collect(),
+                breaking_changes: outcome
+                    .breaking_changes
+                    .iter()
+                    .map(|(module, change)| BreakingChangeReport {
+                        module: module.clone(),
+                        message: change.to_string(),
+                    })
+                    .collect(),
+                diffs: outcome.diffs.iter().map(module_diff_report).collect(),
+                warnings: outcome
+                    .resolved
+                    .warnings
+                    .iter()
+                    .map(WarningReport::from)
+                    .collect(),
+            };
+            println!("{}", serde_json::to_string_pretty(&report)?);
+        }
+    }
+
+    Ok(outcome.changed)
 }
 
 fn do_lock_inner(
     lock_update_mode: LockUpdateMode,
     cache: Arc<ProtofetchGitCache>,
+    module_descriptor: &Descriptor,
     root: &Path,
-    module_file_name: &Path,
     lock_file_name: &Path,
     parallel: ParallelConfig,
-) -> Result<ResolvedRootModule, Box<dyn Error>> {
-    let module_descriptor = load_module_descriptor(root, module_file_name)?;
+    options: LockOptions,
+) -> Result<LockOutcome, Box<dyn Error>> {
+    let LockOptions {
+        breaking_changes,
+        file_diff,
+        dry_run,
+        ..
+    } = options;
     let lock_file_path = root.join(lock_file_name);
 
     let (old_lock, (resolved, lockfile), selected_names) =
         match (lock_update_mode, lock_file_path.exists()) {
-            (LockUpdateMode::Verify, false) => return Err("Lock file does not exist".into()),
+            (LockUpdateMode::Verify, false) => return Err(CommandError::LockFileMissing.into()),
 
             (LockUpdateMode::Verify, true) => {
                 let old_lock = LockFile::from_file(&lock_file_path)?;
@@ -107,7 +286,7 @@ fn do_lock_inner(
                 ));
                 debug!("Verifying lockfile...");
                 let resolved = engine::resolve(
-                    &module_descriptor,
+                    module_descriptor,
                     resolver,
                     cache.coord_locks().clone(),
                     parallel.network_jobs,
@@ -121,7 +300,7 @@ fn do_lock_inner(
                 (
                     None,
                     engine::resolve(
-                        &module_descriptor,
+                        module_descriptor,
                         resolver,
                         cache.coord_locks().clone(),
                         parallel.network_jobs,
@@ -139,7 +318,7 @@ fn do_lock_inner(
                 ));
                 debug!("Updating lockfile...");
                 let resolved = engine::resolve(
-                    &module_descriptor,
+                    module_descriptor,
                     resolver,
                     cache.coord_locks().clone(),
                     parallel.network_jobs,
@@ -153,7 +332,7 @@ fn do_lock_inner(
                 (
                     None,
                     engine::resolve(
-                        &module_descriptor,
+                        module_descriptor,
                         resolver,
                         cache.coord_locks().clone(),
                         parallel.network_jobs,
@@ -177,7 +356,7 @@ fn do_lock_inner(
                 (
                     None,
                     engine::resolve(
-                        &module_descriptor,
+                        module_descriptor,
                         resolver,
                         cache.coord_locks().clone(),
                         parallel.network_jobs,
@@ -195,7 +374,7 @@ fn do_lock_inner(
                 );
                 debug!("Updating selected lockfile entries...");
                 let resolved = engine::resolve(
-                    &module_descriptor,
+                    module_descriptor,
                     resolver,
                     cache.coord_locks().clone(),
                     parallel.network_jobs,
@@ -214,20 +393,353 @@ fn do_lock_inner(
             .iter()
             .find(|name| !resolved_names.contains(*name))
         {
-            return Err(format!("No dependency named {name}").into());
+            return Err(CommandError::UnknownDependency(name.clone()).into());
         }
     }
 
     debug!("Generated lockfile: {:?}", lockfile);
 
-    if old_lock.is_some_and(|old_lock| old_lock == lockfile) {
+    // A full update does not reconcile with the lock file, but still moves its commits.
+    // It recreates the lock file from scratch, so one that cannot be read is ignored.
+    let previous_lock = match &old_lock {
+        Some(old_lock) => Some(old_lock.clone()),
+        None if lock_file_path.exists() => match LockFile::from_file(&lock_file_path) {
+            Ok(previous_lock) => Some(previous_lock),
+            Err(error) => {
+                debug!("Could not read the previous lock file: {}", error);
+                None
+            }
+        },
+        None => None,
+    };
+    let changed = previous_lock.as_ref() != Some(&lockfile);
+    let moved = previous_lock
+        .as_ref()
+        .map(|previous_lock| moved_modules(previous_lock, &resolved))
+        .unwrap_or_default();
+
+    let breaking = if breaking_changes != BreakingChangePolicy::Ignore {
+        let breaking = check_breaking_changes(cache.as_ref(), &moved)?;
+        if !breaking.is_empty() && breaking_changes == BreakingChangePolicy::Deny {
+            return Err(CommandError::BreakingChanges(breaking.len()).into());
+        }
+        breaking
+    } else {
+        Vec::new()
+    };
+
+    if dry_run {
+        debug!("Not writing the lockfile in a dry run");
+    } else if old_lock.is_some_and(|old_lock| old_lock == lockfile) {
         debug!("Lockfile is up to date");
     } else {
         std::fs::write(&lock_file_path, lockfile.to_string()?)?;
         info!("Wrote lockfile to {}", lock_file_path.display());
     }
 
-    Ok(resolved)
+    let diffs = if file_diff != FileDiffMode::Off {
+        file_diffs(
+            &cache,
+            &moved,
+            &resolved,
+            module_descriptor,
+            parallel,
+            file_diff,
+        )?
+    } else {
+        Vec::new()
+    };
+
+    Ok(LockOutcome {
+        resolved,
+        lockfile,
+        previous_lock,
+        changed,
+        breaking_changes: breaking,
+        diffs,
+    })
+}
+
+/// Returns how the lock file differs from the previous one, with the tags of the locked commits.
+fn describe_lock_changes<'a>(
+    cache: &ProtofetchGitCache,
+    outcome: &'a LockOutcome,
+) -> Vec<DescribedChange<'a>> {
+    static EMPTY: LockFile = LockFile {
+        dependencies: Vec::new(),
+    };
+    let tags = |dependency: &LockedDependency| {
+        outcome
+            .resolved
+            .modules
+            .iter()
+            .find(|module| module.name == dependency.name)
+            .map(|module| {
+                cache
+                    .tags_of(&module.coordinate, &dependency.commit_hash)
+                    .unwrap_or_else(|error| {
+                        warn!(
+                            "Could not list the tags of {}: {}",
+                            module.coordinate, error
+                        );
+                        Vec::new()
+                    })
+            })
+            .unwrap_or_default()
+    };
+    outcome
+        .previous_lock
+        .as_ref()
+        .unwrap_or(&EMPTY)
+        .changes(&outcome.lockfile)
+        .into_iter()
+        .map(|change| {
+            let (old_tags, new_tags) = match &change {
+                LockChange::Added(new) => (Vec::new(), tags(new)),
+                LockChange::Removed(_) => (Vec::new(), Vec::new()),
+                LockChange::Updated { old, new } if old.commit_hash == new.commit_hash => {
+                    (Vec::new(), Vec::new())
+                }
+                LockChange::Updated { old, new } if old.coordinate == new.coordinate => {
+                    (tags(old), tags(new))
+                }
+                LockChange::Updated { new, .. } => (Vec::new(), tags(new)),
+            };
+            DescribedChange {
+                change,
+                old_tags,
+                new_tags,
+            }
+        })
+        .collect()
+}
+
+/// Prints how the lock file would change in a dry run.
+fn print_lock_changes(changes: &[DescribedChange]) {
+    if changes.is_empty() {
+        info!("Lock file is up to date");
+    }
+
+    let describe = |dependency: &LockedDependency, tags: &[String]| {
+        let commit = short_hash(&dependency.commit_hash);
+        if tags.is_empty() {
+            commit.to_owned()
+        } else {
+            format!("{} ({})", commit, tags.join(", "))
+        }
+    };
+    for DescribedChange {
+        change,
+        old_tags,
+        new_tags,
+    } in changes
+    {
+        match change {
+            LockChange::Added(new) => {
+                println!("Would add {} at {}", new.name, describe(new, new_tags))
+            }
+            LockChange::Removed(old) => println!(
+                "Would remove {} at {}",
+                old.name,
+                short_hash(&old.commit_hash)
+            ),
+            LockChange::Updated { old, new } if old.commit_hash != new.commit_hash => println!(
+                "Would update {} from {} to {}",
+                new.name,
+                describe(old, old_tags),
+                describe(new, new_tags)
+            ),
+            LockChange::Updated { new, .. } => {
+                println!(
+                    "Would update the lock entry of {} ({})",
+                    new.name, new.coordinate
+                )
+            }
+        }
+    }
+}
+
+fn lock_change_report(described: DescribedChange) -> LockChangeReport {
+    let DescribedChange {
+        change,
+        old_tags,
+        new_tags,
+    } = described;
+    match change {
+        LockChange::Added(new) => LockChangeReport::Added { new, new_tags },
+        LockChange::Removed(old) => LockChangeReport::Removed { old },
+        LockChange::Updated { old, new } => LockChangeReport::Updated {
+            old,
+            old_tags,
+            new,
+            new_tags,
+        },
+    }
+}
+
+/// Returns the previously locked entries of the modules whose commit changed, along with the
+/// modules. Modules that moved to another repository are skipped.
+fn moved_modules<'a>(
+    previous_lock: &'a LockFile,
+    resolved: &'a ResolvedRootModule,
+) -> Vec<(&'a LockedDependency, &'a ResolvedModule)> {
+    resolved
+        .modules
+        .iter()
+        .filter_map(|module| {
+            let previous = previous_lock
+                .dependencies
+                .iter()
+                .find(|dependency| dependency.name == module.name)?;
+            if previous.commit_hash == module.commit_hash {
+                return None;
+            }
+            if previous.coordinate != LockedCoordinate::from(&module.coordinate) {
+                debug!(
+                    "Not comparing the commits of {}, its repository changed",
+                    module.name
+                );
+                return None;
+            }
+            Some((previous, module))
+        })
+        .collect()
+}
+
+/// Warns about the breaking changes of every module whose locked commit changed, and returns
+/// them.
+fn check_breaking_changes(
+    cache: &ProtofetchGitCache,
+    moved: &[(&LockedDependency, &ResolvedModule)],
+) -> Result<Vec<(ModuleName, BreakingChange)>, Box<dyn Error>> {
+    let mut breaking = Vec::new();
+    for (previous, module) in moved {
+        let changes = engine::breaking_changes(
+            cache,
+            &module.coordinate,
+            &previous.specification,
+            &previous.commit_hash,
+            &module.specification,
+            &module.commit_hash,
+        )?;
+        for change in changes {
+            warn!("Breaking change in {}: {}", module.name, change);
+            breaking.push((module.name.clone(), change));
+        }
+    }
+    Ok(breaking)
+}
+
+/// Returns, for every module whose locked commit changed, the files of the output that the update
+/// adds, removes or changes. Both commits are copied under the current rules.
+fn file_diffs(
+    cache: &Arc<ProtofetchGitCache>,
+    moved: &[(&LockedDependency, &ResolvedModule)],
+    resolved: &ResolvedRootModule,
+    module_descriptor: &Descriptor,
+    parallel: ParallelConfig,
+    mode: FileDiffMode,
+) -> Result<Vec<ModuleDiff>, Box<dyn Error>> {
+    if moved.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    engine::fetch(
+        cache.clone(),
+        resolved,
+        cache.coord_locks().clone(),
+        parallel.network_jobs,
+    )?;
+    let mut previous = resolved.clone();
+    for module in &mut previous.modules {
+        if let Some((locked, _)) = moved.iter().find(|(_, moved)| moved.name == module.name) {
+            cache.fetch(
+                &module.coordinate,
+                &locked.specification,
+                &locked.commit_hash,
+            )?;
+            module.commit_hash = locked.commit_hash.clone();
+            module.specification = locked.specification.clone();
+        }
+    }
+
+    let planned = |resolved: &ResolvedRootModule| {
+        engine::planned_files(
+            cache.clone(),
+            resolved,
+            module_descriptor.output_layout,
+            &module_descriptor.imports,
+            parallel.copy_jobs,
+        )
+    };
+    let old_files = planned(&previous)?;
+    let new_files = planned(resolved)?;
+
+    let none = BTreeMap::new();
+    let mut diffs = Vec::with_capacity(moved.len());
+    for (locked, module) in moved {
+        let files = engine::diff_files(
+            cache.as_ref(),
+            &module.coordinate,
+            OutputFiles {
+                commit_hash: &locked.commit_hash,
+                files: old_files.get(&module.name).unwrap_or(&none),
+            },
+            OutputFiles {
+                commit_hash: &module.commit_hash,
+                files: new_files.get(&module.name).unwrap_or(&none),
+            },
+            mode == FileDiffMode::Unified,
+        )?;
+        diffs.push(ModuleDiff {
+            name: module.name.clone(),
+            old_commit_hash: locked.commit_hash.clone(),
+            new_commit_hash: module.commit_hash.clone(),
+            files,
+        });
+    }
+    Ok(diffs)
+}
+
+fn print_file_diffs(diffs: &[ModuleDiff]) {
+    for diff in diffs {
+        println!(
+            "{} {} -> {}",
+            diff.name,
+            short_hash(&diff.old_commit_hash),
+            short_hash(&diff.new_commit_hash)
+        );
+        if diff.files.is_empty() {
+            println!("  no changes to the output");
+        }
+        for file in &diff.files {
+            println!("  {} {}", file.change, file.path.display());
+            if let Some(unified) = &file.unified {
+                print!("{}", unified);
+            }
+        }
+    }
+}
+
+fn module_diff_report(diff: &ModuleDiff) -> ModuleDiffReport {
+    ModuleDiffReport {
+        module: diff.name.clone(),
+        old_commit_hash: diff.old_commit_hash.clone(),
+        new_commit_hash: diff.new_commit_hash.clone(),
+        files: diff
+            .files
+            .iter()
+            .map(|file| FileDiffReport {
+                path: file.path.clone(),
+                change: report::file_change_name(file.change),
+                unified: file.unified.clone(),
+            })
+            .collect(),
+    }
+}
+
+fn short_hash(commit_hash: &str) -> &str {
+    &commit_hash[..commit_hash.len().min(7)]
 }
 
 fn dependency_updates(
@@ -243,12 +755,194 @@ fn dependency_updates(
             } => (name, Some(precise)),
         };
         if result.insert(name.clone(), precise).is_some() {
-            return Err(format!("Dependency {name} selected more than once").into());
+            return Err(CommandError::DependencySelectedTwice(name).into());
         }
     }
     Ok(result)
 }
 
+/// Handler to add command. Resolves the new dependency and updates the lock file, then adds the
+/// dependency to the module descriptor, keeping its formatting and comments.
+pub fn do_add(
+    dependency: NewDependency,
+    cache: Arc<ProtofetchGitCache>,
+    root: &Path,
+    module_file_name: &Path,
+    lock_file_name: &Path,
+    parallel: ParallelConfig,
+    format: OutputFormat,
+) -> Result<(), Box<dyn Error>> {
+    let module_file_path = root.join(module_file_name);
+    let mut document =
+        DescriptorDocument::from_toml_str(&std::fs::read_to_string(&module_file_path)?)?;
+
+    let NewDependency {
+        url,
+        name,
+        revision,
+        branch,
+        protocol,
+        prune,
+        allow_policies,
+    } = dependency;
+    let name = match name {
+        Some(name) => name,
+        None => url
+            .trim_end_matches('/')
+            .rsplit('/')
+            .next()
+            .map(|repository| repository.trim_end_matches(".git").to_owned())
+            .filter(|repository| !repository.is_empty())
+            .ok_or_else(|| format!("Cannot name a dependency after {url:?}, use --name"))?,
+    };
+    if document.contains_key(&name) {
+        return Err(CommandError::DependencyExists(name).into());
+    }
+    document.add_dependency(
+        &name,
+        &DependencyTable {
+            url,
+            protocol,
+            revision,
+            branch,
+            prune,
+            allow_policies,
+        },
+    );
+    let module_descriptor = document.descriptor()?;
+
+    let outcome = do_lock_inner(
+        LockUpdateMode::Reconcile,
+        cache,
+        &module_descriptor,
+        root,
+        lock_file_name,
+        parallel,
+        LockOptions::default(),
+    )?;
+    std::fs::write(&module_file_path, document.to_string())?;
+
+    let added = outcome
+        .lockfile
+        .dependencies
+        .iter()
+        .find(|dependency| dependency.name.to_string() == name)
+        .ok_or_else(|| CommandError::UnknownDependency(name.clone()))?;
+    info!(
+        "Added {} at {} to {}",
+        name,
+        short_hash(&added.commit_hash),
+        module_file_path.display()
+    );
+
+    if format == OutputFormat::Json {
+        let report = AddReport {
+            added,
+            lock_file_changed: outcome.changed,
+        };
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    }
+
+    Ok(())
+}
+
+/// Handler to remove command. Removes a dependency from the module descriptor, keeping its
+/// formatting and comments, drops the lock file entries no other dependency needs, and removes
+/// the files they contributed from the output directory if it exists.
+#[allow(clippy::too_many_arguments)]
+pub fn do_remove(
+    name: &str,
+    cache: Arc<ProtofetchGitCache>,
+    root: &Path,
+    module_file_name: &Path,
+    lock_file_name: &Path,
+    output_directory_name: Option<&Path>,
+    parallel: ParallelConfig,
+    format: OutputFormat,
+) -> Result<(), Box<dyn Error>> {
+    let module_file_path = root.join(module_file_name);
+    let mut document =
+        DescriptorDocument::from_toml_str(&std::fs::read_to_string(&module_file_path)?)?;
+    if !document.remove_dependency(name) {
+        return Err(CommandError::UnknownDependency(name.to_owned()).into());
+    }
+    let module_descriptor = document.descriptor()?;
+
+    let outcome = do_lock_inner(
+        LockUpdateMode::Reconcile,
+        cache.clone(),
+        &module_descriptor,
+        root,
+        lock_file_name,
+        parallel,
+        LockOptions::default(),
+    )?;
+    std::fs::write(&module_file_path, document.to_string())?;
+    info!("Removed {} from {}", name, module_file_path.display());
+
+    let unlocked = outcome
+        .previous_lock
+        .iter()
+        .flat_map(|previous_lock| &previous_lock.dependencies)
+        .filter(|previous| {
+            !outcome
+                .lockfile
+                .dependencies
+                .iter()
+                .any(|dependency| dependency.name == previous.name)
+        })
+        .collect::<Vec<_>>();
+    for dependency in &unlocked {
+        info!("Removed {} from the lock file", dependency.name);
+    }
+
+    let proto_out = output_directory(root, output_directory_name, &module_descriptor)?;
+    let mut warnings = outcome
+        .resolved
+        .warnings
+        .iter()
+        .map(WarningReport::from)
+        .collect::<Vec<_>>();
+    // Synchronizing the output removes exactly the files that are no longer copied
+    let removed_files = if proto_out.exists() {
+        engine::fetch(
+            cache.clone(),
+            &outcome.resolved,
+            cache.coord_locks().clone(),
+            parallel.network_jobs,
+        )?;
+        let report = engine::copy(
+            cache.clone(),
+            outcome.resolved.clone(),
+            proto_out.clone(),
+            module_descriptor.output_layout,
+            &module_descriptor.imports,
+            parallel.copy_jobs,
+        )?;
+        info!(
+            "Removed {} files from {}",
+            report.removed,
+            proto_out.display()
+        );
+        warnings.extend(report.warnings.iter().map(WarningReport::from));
+        report.removed
+    } else {
+        0
+    };
+
+    if format == OutputFormat::Json {
+        let report = RemoveReport {
+            removed: name,
+            unlocked,
+            removed_files,
+            warnings,
+        };
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    }
+
+    Ok(())
+}
+
 /// Handler to init command
 pub fn do_init(
     root: &Path,
@@ -261,6 +955,8 @@ pub fn do_init(
             name,
             description: None,
             proto_out_dir: None,
+            output_layout: Default::default(),
+            imports: Default::default(),
             dependencies: vec![],
         }
     };
@@ -292,20 +988,50 @@ pub fn do_migrate(
     Ok(())
 }
 
+/// Handler to check-compile command
+pub fn do_check_compile(
+    root: &Path,
+    module_file_name: &Path,
+    output_directory_name: Option<&Path>,
+    includes: &[PathBuf],
+    descriptor_set: Option<&Path>,
+) -> Result<(), Box<dyn Error>> {
+    let module_descriptor = load_module_descriptor(root, module_file_name)?;
+
+    let proto_out = output_directory(root, output_directory_name, &module_descriptor)?;
+    if !proto_out.exists() {
+        return Err(CommandError::OutputMissing(proto_out).into());
+    }
+
+    let includes = includes
+        .iter()
+        .map(|include| root.join(include))
+        .collect::<Vec<_>>();
+    let descriptor_set_bytes =
+        engine::compile(&proto_out, module_descriptor.output_layout, &includes)?;
+    info!("Compiled the proto files of {}", proto_out.display());
+
+    if let Some(descriptor_set) = descriptor_set {
+        let descriptor_set = root.join(descriptor_set);
+        std::fs::write(&descriptor_set, descriptor_set_bytes)?;
+        info!("Wrote descriptor set to {}", descriptor_set.display());
+    }
+
+    Ok(())
+}
+
 pub fn do_clean(
     root: &Path,
     module_file_name: &Path,
     lock_file_name: &Path,
     output_directory_name: Option<&Path>,
+    format: OutputFormat,
 ) -> Result<(), Box<dyn Error>> {
     let module_descriptor = load_module_descriptor(root, module_file_name)?;
 
     let lock_file_path = root.join(lock_file_name);
 
-    let output_directory_name = output_directory_name
-        .or_else(|| module_descriptor.proto_out_dir.as_ref().map(Path::new))
-        .unwrap_or(Path::new(DEFAULT_OUTPUT_DIRECTORY_NAME));
-    let output_directory_path = root.join(output_directory_name);
+    let output_directory_path = output_directory(root, output_directory_name, &module_descriptor)?;
 
     info!(
         "Cleaning protofetch proto_out source files folder {}.",
@@ -314,31 +1040,223 @@ pub fn do_clean(
     let output1 = std::fs::remove_dir_all(&output_directory_path);
     let output2 = std::fs::remove_file(&lock_file_path);
 
+    let mut removed = Vec::new();
     for (output, path) in [(output1, output_directory_path), (output2, lock_file_path)] {
         match output {
+            Ok(()) => removed.push(path),
             Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
                 info!("Already removed {}, nothing to do", path.display());
-                Ok(())
             }
-            otherwise => otherwise,
-        }?;
+            Err(err) => return Err(err.into()),
+        }
+    }
+
+    if format == OutputFormat::Json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&CleanReport { removed })?
+        );
+    }
+
+    Ok(())
+}
+
+/// Handler to cache gc command
+pub fn do_cache_gc(
+    cache: &ProtofetchGitCache,
+    root: &Path,
+    lock_file_name: &Path,
+    max_age: Duration,
+    projects: &[PathBuf],
+) -> Result<(), Box<dyn Error>> {
+    let referenced = if projects.is_empty() {
+        None
+    } else {
+        let mut referenced = BTreeSet::new();
+        for project in projects {
+            let lock_file_path = root.join(project).join(lock_file_name);
+            if !lock_file_path.exists() {
+                return Err(
+                    format!("Lock file {} does not exist", lock_file_path.display()).into(),
+                );
+            }
+            for dependency in LockFile::from_file(&lock_file_path)?.dependencies {
+                let coordinate = Coordinate::from_url_protocol(
+                    &dependency.coordinate.url,
+                    dependency.coordinate.protocol,
+                )?;
+                referenced.insert(coordinate.to_path());
+            }
+        }
+        Some(referenced)
+    };
+
+    let report = cache.gc(max_age, referenced.as_ref())?;
+    info!(
+        "Removed {} worktrees and {} repositories, reclaimed {}",
+        report.removed_worktrees,
+        report.removed_repositories,
+        format_bytes(report.reclaimed_bytes)
+    );
+    Ok(())
+}
+
+/// Handler to cache list command
+pub fn do_cache_list(
+    cache: &ProtofetchGitCache,
+    format: OutputFormat,
+) -> Result<(), Box<dyn Error>> {
+    let entries = cache.entries()?;
+    match format {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&entries)?),
+        OutputFormat::Text => {
+            for entry in &entries {
+                let legacy_worktrees = match entry.legacy_worktrees.len() {
+                    0 => String::new(),
+                    count => format!("  {} legacy worktree(s)", count),
+                };
+                println!(
+                    "{}  {}{}  last used {}  {}",
+                    entry.coordinate,
+                    format_bytes(entry.size_bytes),
+                    legacy_worktrees,
+                    format_last_used(entry.last_used),
+                    entry.origin.as_deref().unwrap_or("<no origin>")
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Handler to cache info command
+pub fn do_cache_info(
+    cache: &ProtofetchGitCache,
+    url: &str,
+    format: OutputFormat,
+) -> Result<(), Box<dyn Error>> {
+    let url = url.trim_end_matches('/');
+    // Local repositories are cached under their path without the leading separator
+    let coordinate = url.trim_start_matches('/');
+    let entry = cache
+        .entries()?
+        .into_iter()
+        .find(|entry| entry.coordinate == coordinate || entry.origin.as_deref() == Some(url))
+        .ok_or_else(|| format!("{} is not in the cache", url))?;
+    match format {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&entry)?),
+        OutputFormat::Text => print_cache_entry(&entry),
     }
+    Ok(())
+}
 
+/// Handler to cache verify command
+pub fn do_cache_verify(cache: &ProtofetchGitCache, repair: bool) -> Result<(), Box<dyn Error>> {
+    let broken = cache.verify(repair)?;
+    for entry in &broken {
+        warn!("{}: {}", entry.path.display(), entry.problem);
+    }
+    if broken.is_empty() {
+        info!("The cache is healthy");
+    } else if repair {
+        info!(
+            "Repaired {} broken entries, quarantined repositories will be re-cloned on the next fetch",
+            broken.len()
+        );
+    } else {
+        return Err(format!(
+            "Found {} broken cache entries, run with --repair to fix them",
+            broken.len()
+        )
+        .into());
+    }
     Ok(())
 }
 
+fn print_cache_entry(entry: &CacheEntry) {
+    println!("{}", entry.coordinate);
+    println!("  origin: {}", entry.origin.as_deref().unwrap_or("<none>"));
+    println!("  path: {}", entry.path.display());
+    println!("  size: {}", format_bytes(entry.size_bytes));
+    println!("  last used: {}", format_last_used(entry.last_used));
+    if !entry.legacy_worktrees.is_empty() {
+        println!("  legacy worktrees:");
+    }
+    for worktree in &entry.legacy_worktrees {
+        println!(
+            "    {}  {}  last used {}",
+            worktree.commit_hash,
+            format_bytes(worktree.size_bytes),
+            format_last_used(worktree.last_used)
+        );
+    }
+}
+
+fn format_last_used(last_used: Option<SystemTime>) -> String {
+    let Some(last_used) = last_used else {
+        return "never".to_owned();
+    };
+    let seconds = SystemTime::now()
+        .duration_since(last_used)
+        .unwrap_or_default()
+        .as_secs();
+    match seconds {
+        0..=59 => "just now".to_owned(),
+        60..=3599 => format!("{}m ago", seconds / 60),
+        3600..=86399 => format!("{}h ago", seconds / 3600),
+        _ => format!("{}d ago", seconds / 86400),
+    }
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
 fn load_module_descriptor(
     root: &Path,
     module_file_name: &Path,
 ) -> Result<Descriptor, Box<dyn Error>> {
-    let module_descriptor = Descriptor::from_file(&root.join(module_file_name)).or_else(|_| {
+    let module_file = root.join(module_file_name);
+    let module_descriptor = if module_file.exists() {
+        Descriptor::from_file(&module_file)?
+    } else {
         ProtodepDescriptor::from_file(&root.join("protodep.toml"))
-            .and_then(|d| d.into_proto_fetch())
-    })?;
+            .and_then(|d| d.into_proto_fetch())?
+    };
 
     Ok(module_descriptor)
 }
 
+/// The output directory, `output_directory_name` or else the one of the descriptor, relative to
+/// `root`. It must not contain the project, whose files would otherwise be replaced or removed.
+fn output_directory(
+    root: &Path,
+    output_directory_name: Option<&Path>,
+    module_descriptor: &Descriptor,
+) -> Result<PathBuf, CommandError> {
+    let output_directory_name = output_directory_name
+        .or_else(|| module_descriptor.proto_out_dir.as_ref().map(Path::new))
+        .unwrap_or(Path::new(DEFAULT_OUTPUT_DIRECTORY_NAME));
+    let proto_out = root.join(output_directory_name);
+    // A directory that does not exist yet cannot contain the project
+    if let (Ok(output), Ok(project)) = (proto_out.canonicalize(), root.canonicalize()) {
+        if project.starts_with(output) {
+            return Err(CommandError::OutputContainsProject(proto_out));
+        }
+    }
+    Ok(proto_out)
+}
+
 /// Name if present otherwise attempt to extract from directory
 fn build_module_name(name: Option<String>, path: &Path) -> Result<ModuleName, Box<dyn Error>> {
     match name {
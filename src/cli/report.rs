@@ -0,0 +1,243 @@
+//! Results of the commands for `--format json`.
+
+use std::{error::Error, path::PathBuf};
+
+use serde::Serialize;
+
+use crate::{
+    cli::command_handlers::CommandError,
+    engine::{CompileError, FetchError, FileChange, ProtoError, Warning},
+    git::{cache::CacheError, repository::ProtoRepoError},
+    model::{
+        protofetch::{lock::LockedDependency, ModuleName},
+        ParseError,
+    },
+};
+
+#[derive(Debug, Serialize)]
+pub struct FetchReport<'a> {
+    pub modules: &'a [LockedDependency],
+    pub lock_file_changed: bool,
+    pub output: OutputSummary,
+    pub warnings: Vec<WarningReport>,
+}
+
+/// What a fetch changed in the output directory.
+#[derive(Debug, Serialize)]
+pub struct OutputSummary {
+    pub directory: PathBuf,
+    pub added: usize,
+    pub changed: usize,
+    pub removed: usize,
+    pub unchanged: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LockReport<'a> {
+    pub dry_run: bool,
+    /// Whether the lock file changed, or would change in a dry run.
+    pub changed: bool,
+    pub modules: &'a [LockedDependency],
+    pub changes: Vec<LockChangeReport<'a>>,
+    pub breaking_changes: Vec<BreakingChangeReport>,
+    pub diffs: Vec<ModuleDiffReport>,
+    pub warnings: Vec<WarningReport>,
+}
+
+/// A changed lock file entry, with the tags of the locked commits.
+#[derive(Debug, Serialize)]
+#[serde(tag = "change", rename_all = "snake_case")]
+pub enum LockChangeReport<'a> {
+    Added {
+        new: &'a LockedDependency,
+        new_tags: Vec<String>,
+    },
+    Removed {
+        old: &'a LockedDependency,
+    },
+    Updated {
+        old: &'a LockedDependency,
+        old_tags: Vec<String>,
+        new: &'a LockedDependency,
+        new_tags: Vec<String>,
+    },
+}
+
+#[derive(Debug, Serialize)]
+pub struct BreakingChangeReport {
+    pub module: ModuleName,
+    pub message: String,
+}
+
+/// The files of the output that change for a dependency that moved to a new commit.
+#[derive(Debug, Serialize)]
+pub struct ModuleDiffReport {
+    pub module: ModuleName,
+    pub old_commit_hash: String,
+    pub new_commit_hash: String,
+    pub files: Vec<FileDiffReport>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FileDiffReport {
+    pub path: PathBuf,
+    pub change: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unified: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AddReport<'a> {
+    pub added: &'a LockedDependency,
+    pub lock_file_changed: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RemoveReport<'a> {
+    pub removed: &'a str,
+    /// Lock file entries that no dependency needs anymore.
+    pub unlocked: Vec<&'a LockedDependency>,
+    /// Number of files removed from the output directory.
+    pub removed_files: usize,
+    pub warnings: Vec<WarningReport>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CleanReport {
+    /// Paths that were removed, the ones that did not exist are skipped.
+    pub removed: Vec<PathBuf>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WarningReport {
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl From<&Warning> for WarningReport {
+    fn from(warning: &Warning) -> Self {
+        WarningReport {
+            code: warning.code(),
+            message: warning.to_string(),
+        }
+    }
+}
+
+pub fn file_change_name(change: FileChange) -> &'static str {
+    match change {
+        FileChange::Added => "added",
+        FileChange::Removed => "removed",
+        FileChange::Changed => "changed",
+    }
+}
+
+/// Returns a stable identifier of the kind of the error, for consumption by other tools.
+pub fn error_code(error: &(dyn Error + 'static)) -> &'static str {
+    if let Some(error) = error.downcast_ref::<CommandError>() {
+        return error.code();
+    }
+    if let Some(error) = error.downcast_ref::<ParseError>() {
+        return parse_error_code(error);
+    }
+    if let Some(error) = error.downcast_ref::<FetchError>() {
+        return match error {
+            FetchError::Parsing(error) => parse_error_code(error),
+            FetchError::ProtoRepoError(_) => "git_error",
+            FetchError::Cache(_) => "cache_error",
+            FetchError::IO(_) => "io_error",
+            FetchError::Resolver(_) => "resolve_failed",
+            FetchError::BlobRead(_) | FetchError::ThreadPool(_) => "fetch_failed",
+        };
+    }
+    if let Some(error) = error.downcast_ref::<ProtoError>() {
+        return match error {
+            ProtoError::UnresolvedImports(_) => "unresolved_imports",
+            ProtoError::DanglingImports(_) => "dangling_imports",
+            ProtoError::Parse { .. } => "proto_parse_error",
+            ProtoError::IO(_) => "io_error",
+            ProtoError::BadPath(_) | ProtoError::Cache(_) | ProtoError::ThreadPool(_) => {
+                "copy_failed"
+            }
+        };
+    }
+    if let Some(error) = error.downcast_ref::<CompileError>() {
+        return match error {
+            CompileError::IO(_) => "io_error",
+            CompileError::Protox(_) => "compile_failed",
+        };
+    }
+    if error.is::<CacheError>() {
+        return "cache_error";
+    }
+    if error.is::<ProtoRepoError>() {
+        return "git_error";
+    }
+    if error.is::<std::io::Error>() {
+        return "io_error";
+    }
+    "error"
+}
+
+fn parse_error_code(error: &ParseError) -> &'static str {
+    match error {
+        ParseError::UnsupportedLockFileVersion(_) | ParseError::OldLockFileVersion(_) => {
+            "lock_file_version"
+        }
+        ParseError::UnknownKey { .. } => "unknown_key",
+        ParseError::Located { error, .. } => parse_error_code(error),
+        _ => "parse_error",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+
+    use crate::{
+        cli::command_handlers::CommandError,
+        engine::ProtoError,
+        model::{Location, ParseError},
+    };
+
+    use super::error_code;
+
+    #[test]
+    fn error_codes_are_stable() {
+        let errors: Vec<(Box<dyn Error>, &str)> = vec![
+            (CommandError::LockFileMissing.into(), "lock_file_missing"),
+            (CommandError::BreakingChanges(2).into(), "breaking_changes"),
+            (
+                ParseError::OldLockFileVersion(1).into(),
+                "lock_file_version",
+            ),
+            (
+                ParseError::MissingKey("url".to_owned()).into(),
+                "parse_error",
+            ),
+            (
+                ParseError::Located {
+                    location: Location {
+                        file: None,
+                        line: 2,
+                        column: 1,
+                    },
+                    error: Box::new(ParseError::UnknownKey {
+                        key: "revison".to_owned(),
+                        table: "dependency `api`".to_owned(),
+                        suggestion: Some("revision".to_owned()),
+                    }),
+                }
+                .into(),
+                "unknown_key",
+            ),
+            (
+                ProtoError::UnresolvedImports(Vec::new()).into(),
+                "unresolved_imports",
+            ),
+            ("something else".into(), "error"),
+        ];
+        for (error, code) in errors {
+            assert_eq!(error_code(error.as_ref()), code, "{error}");
+        }
+    }
+}
@@ -1,10 +1,13 @@
-use std::{collections::HashMap, path::PathBuf, str::FromStr};
+use std::{collections::HashMap, path::PathBuf, str::FromStr, time::Duration};
 
 use anyhow::{bail, Context};
 use log::{debug, trace};
+use schemars::{schema_for, JsonSchema, Schema};
 use serde::Deserialize;
 
-use crate::{git::backend::GitBackendType, model::protofetch::Protocol};
+use crate::{
+    flock::DEFAULT_LOCK_TIMEOUT, git::backend::GitBackendType, model::protofetch::Protocol,
+};
 
 #[derive(Debug)]
 pub struct ProtofetchConfig {
@@ -14,6 +17,7 @@ pub struct ProtofetchConfig {
     pub copy_jobs: Option<usize>,
     pub git_backend: GitBackendType,
     pub git_executable: Option<String>,
+    pub lock_timeout: Duration,
 }
 
 impl ProtofetchConfig {
@@ -31,6 +35,11 @@ impl ProtofetchConfig {
             copy_jobs: raw_config.copy_jobs,
             git_backend: raw_config.git.backend.unwrap_or_default(),
             git_executable: raw_config.git.executable_path,
+            lock_timeout: raw_config
+                .cache
+                .lock_timeout
+                .map(Duration::from_secs)
+                .unwrap_or(DEFAULT_LOCK_TIMEOUT),
         };
         trace!("Loaded configuration: {:?}", config);
 
@@ -38,27 +47,41 @@ impl ProtofetchConfig {
     }
 }
 
-#[derive(Default, Debug, Deserialize, PartialEq, Eq)]
+/// The JSON Schema of the user configuration file.
+pub fn schema() -> Schema {
+    schema_for!(RawConfig)
+}
+
+/// The user configuration, `config.toml` in the protofetch configuration directory.
+#[derive(Default, Debug, Deserialize, PartialEq, Eq, JsonSchema)]
+#[schemars(title = "config.toml")]
 struct RawConfig {
     #[serde(default)]
     cache: CacheConfig,
     #[serde(default)]
     git: GitConfig,
+    /// How many dependencies to fetch in parallel.
     #[serde(default)]
     jobs: Option<usize>,
+    /// How many files to copy to the output in parallel.
     #[serde(default)]
     copy_jobs: Option<usize>,
 }
 
-#[derive(Default, Debug, Deserialize, PartialEq, Eq)]
+#[derive(Default, Debug, Deserialize, PartialEq, Eq, JsonSchema)]
 struct CacheConfig {
+    /// Directory of the cached repositories.
     dir: Option<PathBuf>,
+    /// How long to wait for other processes using the cache, in seconds.
+    lock_timeout: Option<u64>,
 }
 
-#[derive(Default, Debug, Deserialize, PartialEq, Eq)]
+#[derive(Default, Debug, Deserialize, PartialEq, Eq, JsonSchema)]
 struct GitConfig {
+    /// Protocol of dependencies that do not set one.
     protocol: Option<Protocol>,
     backend: Option<GitBackendType>,
+    /// Path of the git executable used by the `cli` backend.
     executable_path: Option<String>,
 }
 
@@ -114,6 +137,9 @@ impl RawConfig {
         if let Some(dir) = get::<PathBuf>("PROTOFETCH_CACHE_DIR", &env_override)? {
             config.cache.dir = Some(dir);
         }
+        if let Some(lock_timeout) = get::<u64>("PROTOFETCH_CACHE_LOCK_TIMEOUT", &env_override)? {
+            config.cache.lock_timeout = Some(lock_timeout);
+        }
         if let Some(protocol) = get::<Protocol>("PROTOFETCH_GIT_PROTOCOL", &env_override)? {
             config.git.protocol = Some(protocol);
         }
@@ -213,7 +239,10 @@ mod tests {
         assert_eq!(
             config,
             RawConfig {
-                cache: CacheConfig { dir: None },
+                cache: CacheConfig {
+                    dir: None,
+                    lock_timeout: None,
+                },
                 git: GitConfig {
                     protocol: None,
                     backend: None,
@@ -229,6 +258,7 @@ mod tests {
     fn load_environment() {
         let env = HashMap::from([
             ("PROTOFETCH_CACHE_DIR", "/cache"),
+            ("PROTOFETCH_CACHE_LOCK_TIMEOUT", "60"),
             ("PROTOFETCH_GIT_PROTOCOL", "ssh"),
             ("PROTOFETCH_GIT_BACKEND", "cli"),
             ("PROTOFETCH_GIT_EXECUTABLE_PATH", "/usr/bin/git"),
@@ -240,7 +270,8 @@ mod tests {
             config,
             RawConfig {
                 cache: CacheConfig {
-                    dir: Some("/cache".into())
+                    dir: Some("/cache".into()),
+                    lock_timeout: Some(60),
                 },
                 git: GitConfig {
                     protocol: Some(Protocol::Ssh),
@@ -261,6 +292,7 @@ mod tests {
             Some(toml! {
                 [cache]
                 dir = "/cache"
+                lock_timeout = 600
 
                 [git]
                 protocol = "ssh"
@@ -274,7 +306,8 @@ mod tests {
             config,
             RawConfig {
                 cache: CacheConfig {
-                    dir: Some("/cache".into())
+                    dir: Some("/cache".into()),
+                    lock_timeout: Some(600),
                 },
                 git: GitConfig {
                     protocol: Some(Protocol::Ssh),
@@ -0,0 +1,515 @@
+use std::{collections::BTreeMap, fmt::Display, path::PathBuf};
+
+use log::{debug, warn};
+use protox::prost_reflect::prost_types::{
+    field_descriptor_proto::{Label, Type},
+    DescriptorProto, EnumDescriptorProto, FieldDescriptorProto, FileDescriptorProto,
+    MethodDescriptorProto,
+};
+
+use crate::{
+    cache::RepositoryCache,
+    model::protofetch::{Coordinate, RevisionSpecification},
+};
+
+/// A change between two versions of a dependency that can break generated code or the wire
+/// compatibility of its users.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BreakingChange {
+    FileRemoved {
+        file: PathBuf,
+    },
+    PackageChanged {
+        file: PathBuf,
+        old: String,
+        new: String,
+    },
+    MessageRemoved {
+        message: String,
+    },
+    FieldRemoved {
+        message: String,
+        field: String,
+        number: i32,
+    },
+    FieldNumberChanged {
+        message: String,
+        field: String,
+        old: i32,
+        new: i32,
+    },
+    FieldTypeChanged {
+        message: String,
+        field: String,
+        old: String,
+        new: String,
+    },
+    EnumRemoved {
+        r#enum: String,
+    },
+    EnumValueRemoved {
+        r#enum: String,
+        value: String,
+        number: i32,
+    },
+    ServiceRemoved {
+        service: String,
+    },
+    MethodRemoved {
+        service: String,
+        method: String,
+    },
+    MethodSignatureChanged {
+        service: String,
+        method: String,
+        old: String,
+        new: String,
+    },
+}
+
+impl Display for BreakingChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BreakingChange::FileRemoved { file } => {
+                write!(f, "file {} was removed", file.display())
+            }
+            BreakingChange::PackageChanged { file, old, new } => write!(
+                f,
+                "package of {} changed from {} to {}",
+                file.display(),
+                old,
+                new
+            ),
+            BreakingChange::MessageRemoved { message } => {
+                write!(f, "message {} was removed", message)
+            }
+            BreakingChange::FieldRemoved {
+                message,
+                field,
+                number,
+            } => write!(f, "field {}.{} = {} was removed", message, field, number),
+            BreakingChange::FieldNumberChanged {
+                message,
+                field,
+                old,
+                new,
+            } => write!(
+                f,
+                "number of field {}.{} changed from {} to {}",
+                message, field, old, new
+            ),
+            BreakingChange::FieldTypeChanged {
+                message,
+                field,
+                old,
+                new,
+            } => write!(
+                f,
+                "type of field {}.{} changed from {} to {}",
+                message, field, old, new
+            ),
+            BreakingChange::EnumRemoved { r#enum } => write!(f, "enum {} was removed", r#enum),
+            BreakingChange::EnumValueRemoved {
+                r#enum,
+                value,
+                number,
+            } => write!(
+                f,
+                "enum value {}.{} = {} was removed",
+                r#enum, value, number
+            ),
+            BreakingChange::ServiceRemoved { service } => {
+                write!(f, "service {} was removed", service)
+            }
+            BreakingChange::MethodRemoved { service, method } => {
+                write!(f, "rpc {}.{} was removed", service, method)
+            }
+            BreakingChange::MethodSignatureChanged {
+                service,
+                method,
+                old,
+                new,
+            } => write!(
+                f,
+                "signature of rpc {}.{} changed from {} to {}",
+                service, method, old, new
+            ),
+        }
+    }
+}
+
+/// Compares the `.proto` files of a repository at two commits and returns the breaking changes
+/// of `new_commit` relative to `old_commit`, both of which are fetched if necessary.
+///
+/// Files that cannot be parsed at either commit are skipped with a warning.
+pub fn breaking_changes(
+    cache: &dyn RepositoryCache,
+    coordinate: &Coordinate,
+    old_specification: &RevisionSpecification,
+    old_commit: &str,
+    new_specification: &RevisionSpecification,
+    new_commit: &str,
+) -> anyhow::Result<Vec<BreakingChange>> {
+    debug!(
+        "Checking {} for breaking changes between {} and {}",
+        coordinate, old_commit, new_commit
+    );
+    let old = parse_files(cache, coordinate, old_specification, old_commit)?;
+    let new = parse_files(cache, coordinate, new_specification, new_commit)?;
+    Ok(compare(&old, &new))
+}
+
+fn parse_files(
+    cache: &dyn RepositoryCache,
+    coordinate: &Coordinate,
+    specification: &RevisionSpecification,
+    commit_hash: &str,
+) -> anyhow::Result<BTreeMap<PathBuf, FileDescriptorProto>> {
+    cache.fetch(coordinate, specification, commit_hash)?;
+    let paths = cache
+        .list_files(coordinate, commit_hash)?
+        .into_iter()
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "proto")
+        })
+        .collect::<Vec<_>>();
+    let contents = cache.read_files(coordinate, commit_hash, &paths)?;
+    let mut files = BTreeMap::new();
+    for (path, content) in paths.into_iter().zip(contents) {
+        match protox::file::File::from_source(
+            &path.to_string_lossy(),
+            &String::from_utf8_lossy(&content),
+        ) {
+            Ok(file) => {
+                files.insert(path, file.file_descriptor_proto().clone());
+            }
+            Err(error) => warn!(
+                "Skipping {} at {} in the breaking change check: {}",
+                path.display(),
+                commit_hash,
+                error
+            ),
+        }
+    }
+    Ok(files)
+}
+
+/// Returns the breaking changes between two sets of unlinked file descriptors, keyed by path.
+///
+/// Declarations are matched by their fully-qualified names, so moving them between files of the
+/// same package is not a breaking change. Type names are compared as written.
+fn compare(
+    old: &BTreeMap<PathBuf, FileDescriptorProto>,
+    new: &BTreeMap<PathBuf, FileDescriptorProto>,
+) -> Vec<BreakingChange> {
+    let mut changes = Vec::new();
+
+    for (path, old_file) in old {
+        match new.get(path) {
+            None => changes.push(BreakingChange::FileRemoved { file: path.clone() }),
+            Some(new_file) if old_file.package() != new_file.package() => {
+                changes.push(BreakingChange::PackageChanged {
+                    file: path.clone(),
+                    old: old_file.package().to_owned(),
+                    new: new_file.package().to_owned(),
+                })
+            }
+            Some(_) => {}
+        }
+    }
+
+    let old = Declarations::collect(old.values());
+    let new = Declarations::collect(new.values());
+
+    for (name, old_message) in &old.messages {
+        match new.messages.get(name) {
+            Some(new_message) => compare_fields(name, old_message, new_message, &mut changes),
+            None => changes.push(BreakingChange::MessageRemoved {
+                message: name.clone(),
+            }),
+        }
+    }
+
+    for (name, old_enum) in &old.enums {
+        let Some(new_enum) = new.enums.get(name) else {
+            changes.push(BreakingChange::EnumRemoved {
+                r#enum: name.clone(),
+            });
+            continue;
+        };
+        for value in &old_enum.value {
+            if !new_enum
+                .value
+                .iter()
+                .any(|new_value| new_value.number() == value.number())
+            {
+                changes.push(BreakingChange::EnumValueRemoved {
+                    r#enum: name.clone(),
+                    value: value.name().to_owned(),
+                    number: value.number(),
+                });
+            }
+        }
+    }
+
+    for (name, old_methods) in &old.services {
+        let Some(new_methods) = new.services.get(name) else {
+            changes.push(BreakingChange::ServiceRemoved {
+                service: name.clone(),
+            });
+            continue;
+        };
+        for old_method in old_methods.iter() {
+            match new_methods
+                .iter()
+                .find(|new_method| new_method.name() == old_method.name())
+            {
+                None => changes.push(BreakingChange::MethodRemoved {
+                    service: name.clone(),
+                    method: old_method.name().to_owned(),
+                }),
+                Some(new_method) if signature(old_method) != signature(new_method) => {
+                    changes.push(BreakingChange::MethodSignatureChanged {
+                        service: name.clone(),
+                        method: old_method.name().to_owned(),
+                        old: signature(old_method),
+                        new: signature(new_method),
+                    })
+                }
+                Some(_) => {}
+            }
+        }
+    }
+
+    changes
+}
+
+fn compare_fields(
+    message: &str,
+    old: &DescriptorProto,
+    new: &DescriptorProto,
+    changes: &mut Vec<BreakingChange>,
+) {
+    for old_field in &old.field {
+        if let Some(new_field) = new
+            .field
+            .iter()
+            .find(|new_field| new_field.number() == old_field.number())
+        {
+            if field_type(old_field) != field_type(new_field) {
+                changes.push(BreakingChange::FieldTypeChanged {
+                    message: message.to_owned(),
+                    field: old_field.name().to_owned(),
+                    old: field_type(old_field),
+                    new: field_type(new_field),
+                });
+            }
+        } else if let Some(new_field) = new
+            .field
+            .iter()
+            .find(|new_field| new_field.name() == old_field.name())
+        {
+            changes.push(BreakingChange::FieldNumberChanged {
+                message: message.to_owned(),
+                field: old_field.name().to_owned(),
+                old: old_field.number(),
+                new: new_field.number(),
+            });
+        } else {
+            changes.push(BreakingChange::FieldRemoved {
+                message: message.to_owned(),
+                field: old_field.name().to_owned(),
+                number: old_field.number(),
+            });
+        }
+    }
+}
+
+fn field_type(field: &FieldDescriptorProto) -> String {
+    let name = match field.type_name.as_deref() {
+        Some(type_name) => type_name.trim_start_matches('.').to_owned(),
+        None => field
+            .r#type
+            .and_then(|r#type| Type::try_from(r#type).ok())
+            .map(|r#type| {
+                r#type
+                    .as_str_name()
+                    .trim_start_matches("TYPE_")
+                    .to_ascii_lowercase()
+            })
+            .unwrap_or_default(),
+    };
+    if field.label() == Label::Repeated {
+        format!("repeated {}", name)
+    } else {
+        name
+    }
+}
+
+fn signature(method: &MethodDescriptorProto) -> String {
+    let stream = |streaming: bool| if streaming { "stream " } else { "" };
+    format!(
+        "({}{}) returns ({}{})",
+        stream(method.client_streaming()),
+        method.input_type().trim_start_matches('.'),
+        stream(method.server_streaming()),
+        method.output_type().trim_start_matches('.'),
+    )
+}
+
+/// The declarations of a set of files, keyed by fully-qualified name.
+#[derive(Default)]
+struct Declarations<'a> {
+    messages: BTreeMap<String, &'a DescriptorProto>,
+    enums: BTreeMap<String, &'a EnumDescriptorProto>,
+    services: BTreeMap<String, &'a [MethodDescriptorProto]>,
+}
+
+impl<'a> Declarations<'a> {
+    fn collect(files: impl IntoIterator<Item = &'a FileDescriptorProto>) -> Self {
+        let mut declarations = Declarations::default();
+        for file in files {
+            let package = file.package();
+            for message in &file.message_type {
+                declarations.add_message(package, message);
+            }
+            for r#enum in &file.enum_type {
+                declarations
+                    .enums
+                    .insert(qualify(package, r#enum.name()), r#enum);
+            }
+            for service in &file.service {
+                declarations
+                    .services
+                    .insert(qualify(package, service.name()), &service.method);
+            }
+        }
+        declarations
+    }
+
+    fn add_message(&mut self, scope: &str, message: &'a DescriptorProto) {
+        let name = qualify(scope, message.name());
+        for nested in &message.nested_type {
+            self.add_message(&name, nested);
+        }
+        for r#enum in &message.enum_type {
+            self.enums.insert(qualify(&name, r#enum.name()), r#enum);
+        }
+        self.messages.insert(name, message);
+    }
+}
+
+fn qualify(scope: &str, name: &str) -> String {
+    if scope.is_empty() {
+        name.to_owned()
+    } else {
+        format!("{}.{}", scope, name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files(files: &[(&str, &str)]) -> BTreeMap<PathBuf, FileDescriptorProto> {
+        files
+            .iter()
+            .map(|(name, source)| {
+                let file = protox::file::File::from_source(name, source).unwrap();
+                (PathBuf::from(name), file.file_descriptor_proto().clone())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn compatible_changes_are_not_reported() {
+        let old = files(&[(
+            "acme/api.proto",
+            "syntax = \"proto3\";\npackage acme;\n\
+             message Request { string id = 1; }\n\
+             service Api { rpc Get(Request) returns (Request); }\n",
+        )]);
+        let new = files(&[
+            (
+                "acme/api.proto",
+                "syntax = \"proto3\";\npackage acme;\nimport \"acme/request.proto\";\n\
+                 service Api {\n\
+                   rpc Get(Request) returns (Request);\n\
+                   rpc List(Request) returns (stream Request);\n\
+                 }\n",
+            ),
+            (
+                "acme/request.proto",
+                "syntax = \"proto3\";\npackage acme;\n\
+                 message Request { string id = 1; int32 page_size = 2; }\n",
+            ),
+        ]);
+
+        assert_eq!(compare(&old, &new), vec![]);
+    }
+
+    #[test]
+    fn breaking_changes_are_reported() {
+        let old = files(&[
+            (
+                "acme/api.proto",
+                "syntax = \"proto3\";\npackage acme;\n\
+                 message Request {\n\
+                   string id = 1;\n\
+                   int32 page_size = 2;\n\
+                   repeated string tags = 3;\n\
+                   string filter = 4;\n\
+                   message Nested { bool flag = 1; }\n\
+                 }\n\
+                 enum Status { UNKNOWN = 0; ACTIVE = 1; }\n\
+                 service Api {\n\
+                   rpc Get(Request) returns (Request);\n\
+                   rpc Delete(Request) returns (Request);\n\
+                 }\n",
+            ),
+            ("acme/legacy.proto", "syntax = \"proto3\";\npackage acme;\n"),
+            (
+                "other/other.proto",
+                "syntax = \"proto3\";\npackage other;\n",
+            ),
+        ]);
+        let new = files(&[
+            (
+                "acme/api.proto",
+                "syntax = \"proto3\";\npackage acme;\n\
+                 message Request {\n\
+                   string id = 1;\n\
+                   int64 page_size = 2;\n\
+                   string tags = 3;\n\
+                   string filter = 5;\n\
+                 }\n\
+                 enum Status { UNKNOWN = 0; }\n\
+                 service Api { rpc Get(Request) returns (stream Request); }\n",
+            ),
+            (
+                "other/other.proto",
+                "syntax = \"proto3\";\npackage other.v2;\n",
+            ),
+        ]);
+
+        assert_eq!(
+            compare(&old, &new)
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+            vec![
+                "file acme/legacy.proto was removed",
+                "package of other/other.proto changed from other to other.v2",
+                "type of field acme.Request.page_size changed from int32 to int64",
+                "type of field acme.Request.tags changed from repeated string to string",
+                "number of field acme.Request.filter changed from 4 to 5",
+                "message acme.Request.Nested was removed",
+                "enum value acme.Status.ACTIVE = 1 was removed",
+                "signature of rpc acme.Api.Get changed from (Request) returns (Request) \
+                 to (Request) returns (stream Request)",
+                "rpc acme.Api.Delete was removed",
+            ]
+        );
+    }
+}
@@ -0,0 +1,176 @@
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+use log::warn;
+use rayon::prelude::*;
+
+use crate::{
+    engine::copy::ProtoError,
+    model::protofetch::ImportRules,
+    proto::{self, ImportKind},
+};
+
+/// An import of a file in the output that is neither in the output nor provided by the toolchain.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DanglingImport {
+    /// Path of the importing file, relative to the output directory.
+    pub file: PathBuf,
+    pub import: PathBuf,
+}
+
+impl Display for DanglingImport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} imported by {}",
+            self.import.display(),
+            self.file.display()
+        )
+    }
+}
+
+/// Parses every `.proto` file under `dir` and returns the imports that cannot be found under any
+/// of the include roots, which are relative to `dir`. Weak and provided imports are skipped.
+pub(super) fn find_dangling_imports(
+    dir: &Path,
+    include_roots: &[PathBuf],
+    rules: &ImportRules,
+) -> Result<Vec<DanglingImport>, ProtoError> {
+    let mut files = Vec::new();
+    collect_proto_files(dir, dir, &mut files)?;
+
+    let mut dangling = files
+        .par_iter()
+        .map(|file| {
+            let content = std::fs::read(dir.join(file))?;
+            let imports = match proto::parse_imports(&String::from_utf8_lossy(&content)) {
+                Ok(imports) => imports,
+                Err(source) if rules.strict_imports => {
+                    return Err(ProtoError::Parse {
+                        path: file.clone(),
+                        source,
+                    })
+                }
+                Err(source) => {
+                    warn!("Not checking the imports of {}: {}", file.display(), source);
+                    Vec::new()
+                }
+            };
+            Ok(imports
+                .into_iter()
+                .filter(|import| import.kind != ImportKind::Weak)
+                .map(|import| PathBuf::from(import.path))
+                .filter(|import| {
+                    !rules.is_provided(import)
+                        && !include_roots
+                            .iter()
+                            .any(|root| dir.join(root).join(import).is_file())
+                })
+                .map(|import| DanglingImport {
+                    file: file.clone(),
+                    import,
+                })
+                .collect::<Vec<_>>())
+        })
+        .collect::<Result<Vec<_>, ProtoError>>()?
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+    dangling.sort();
+    Ok(dangling)
+}
+
+/// Collects the paths of all `.proto` files under `dir`, relative to `root`.
+pub(super) fn collect_proto_files(
+    root: &Path,
+    dir: &Path,
+    files: &mut Vec<PathBuf>,
+) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            collect_proto_files(root, &path, files)?;
+        } else if path
+            .extension()
+            .is_some_and(|extension| extension == "proto")
+        {
+            let relative = path.strip_prefix(root).expect("entry is inside the root");
+            files.push(relative.to_path_buf());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeSet, fs};
+
+    use super::*;
+
+    fn write_files(dir: &Path, files: &[(&str, &str)]) {
+        for (file, content) in files {
+            let path = dir.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+    }
+
+    #[test]
+    fn imports_missing_from_the_output_are_dangling() {
+        let dir = tempfile::tempdir().unwrap();
+        write_files(
+            dir.path(),
+            &[
+                (
+                    "api/service.proto",
+                    "import \"api/common.proto\";\n\
+                     import \"api/missing.proto\";\n\
+                     import weak \"api/optional.proto\";\n\
+                     import \"google/protobuf/any.proto\";\n\
+                     import \"google/api/annotations.proto\";\n",
+                ),
+                ("api/common.proto", "syntax = \"proto3\";\n"),
+            ],
+        );
+        let rules = ImportRules {
+            provided_imports: BTreeSet::from(["google/api/*".parse().unwrap()]),
+            ..Default::default()
+        };
+
+        let dangling = find_dangling_imports(dir.path(), &[PathBuf::new()], &rules).unwrap();
+
+        assert_eq!(
+            dangling,
+            vec![DanglingImport {
+                file: PathBuf::from("api/service.proto"),
+                import: PathBuf::from("api/missing.proto"),
+            }]
+        );
+    }
+
+    #[test]
+    fn imports_are_resolved_against_all_include_roots() {
+        let dir = tempfile::tempdir().unwrap();
+        write_files(
+            dir.path(),
+            &[
+                ("dep1/a.proto", "import \"b.proto\";\nimport \"c.proto\";\n"),
+                ("dep2/b.proto", "syntax = \"proto3\";\n"),
+            ],
+        );
+        let roots = [PathBuf::from("dep1"), PathBuf::from("dep2")];
+
+        let dangling = find_dangling_imports(dir.path(), &roots, &ImportRules::default()).unwrap();
+
+        assert_eq!(
+            dangling,
+            vec![DanglingImport {
+                file: PathBuf::from("dep1/a.proto"),
+                import: PathBuf::from("c.proto"),
+            }]
+        );
+    }
+}
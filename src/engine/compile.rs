@@ -0,0 +1,83 @@
+use std::path::{Path, PathBuf};
+
+use log::debug;
+use thiserror::Error;
+
+use crate::{
+    engine::{closure, output},
+    model::protofetch::OutputLayout,
+};
+
+#[derive(Error, Debug)]
+pub enum CompileError {
+    #[error("IO error: {0}")]
+    IO(#[from] std::io::Error),
+    #[error("Could not compile the fetched proto files: {0}")]
+    Protox(#[from] protox::Error),
+}
+
+/// Parses and links every `.proto` file of the output directory, and returns the encoded
+/// `FileDescriptorSet` of these files and everything they import.
+///
+/// `includes` are additional directories to resolve imports against, e.g. for provided imports.
+/// Well-known types are always available.
+pub fn compile(
+    proto_dir: &Path,
+    layout: OutputLayout,
+    includes: &[PathBuf],
+) -> Result<Vec<u8>, CompileError> {
+    let include_roots = output::include_roots(proto_dir, layout)?
+        .into_iter()
+        .map(|root| proto_dir.join(root))
+        .collect::<Vec<_>>();
+
+    let mut files = Vec::new();
+    for root in &include_roots {
+        closure::collect_proto_files(root, root, &mut files)?;
+    }
+    files.sort();
+    files.dedup();
+    debug!(
+        "Compiling {} files from {}",
+        files.len(),
+        proto_dir.display()
+    );
+
+    let mut compiler = protox::Compiler::new(include_roots.iter().chain(includes))?;
+    compiler.include_imports(true);
+    compiler.open_files(&files)?;
+    Ok(compiler.encode_file_descriptor_set())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn linking_errors_are_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("api")).unwrap();
+        fs::write(
+            dir.path().join("api/a.proto"),
+            "syntax = \"proto3\";\n\
+             import \"google/protobuf/any.proto\";\n\
+             message A { google.protobuf.Any any = 1; }\n",
+        )
+        .unwrap();
+
+        assert!(!compile(dir.path(), OutputLayout::Flat, &[])
+            .unwrap()
+            .is_empty());
+
+        fs::write(
+            dir.path().join("api/b.proto"),
+            "syntax = \"proto3\";\nmessage B { Missing missing = 1; }\n",
+        )
+        .unwrap();
+
+        let error = compile(dir.path(), OutputLayout::Flat, &[]).unwrap_err();
+        assert!(error.to_string().contains("Missing"), "{error}");
+    }
+}
@@ -1,7 +1,6 @@
 use std::{
-    collections::{HashMap, HashSet, VecDeque},
-    fs::File,
-    io::{BufRead as _, BufReader},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque},
+    fmt::Display,
     path::{Path, PathBuf},
 };
 
@@ -11,9 +10,14 @@ use thiserror::Error;
 
 use crate::{
     cache::RepositoryCache,
-    engine::model::{ResolvedDependency, ResolvedModule, ResolvedRootModule},
-    git::coord_locks::CoordinateLocks,
-    model::protofetch::{Coordinate, DenyPolicies, ModuleName},
+    engine::{
+        closure::{self, DanglingImport},
+        model::{ResolvedDependency, ResolvedModule, ResolvedRootModule},
+        output::{self, OutputReport, StagedDir},
+        symbols, Warning,
+    },
+    model::protofetch::{Coordinate, DenyPolicies, ImportRules, ModuleName, OutputLayout},
+    proto::{self, Import, ImportKind, ProtoParseError},
 };
 
 #[derive(Error, Debug)]
@@ -26,13 +30,49 @@ pub enum ProtoError {
     Cache(anyhow::Error),
     #[error(transparent)]
     ThreadPool(#[from] ThreadPoolBuildError),
+    #[error("Could not parse {path}: {source}")]
+    Parse {
+        path: PathBuf,
+        source: ProtoParseError,
+    },
+    #[error("Unresolved imports:{}", .0.iter().map(|unresolved| format!("\n  {unresolved}")).collect::<String>())]
+    UnresolvedImports(Vec<UnresolvedImport>),
+    #[error("Dangling imports in the output:{}", .0.iter().map(|dangling| format!("\n  {dangling}")).collect::<String>())]
+    DanglingImports(Vec<DanglingImport>),
 }
 
-#[derive(Debug, Clone)]
+/// An import that pruning could not find in any of the modules it searched.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnresolvedImport {
+    pub import: PathBuf,
+    /// Path of the importing file, relative to its content root.
+    pub importer: PathBuf,
+    /// The module of the importing file.
+    pub module: ModuleName,
+    /// The modules that were searched for the import, in order.
+    pub searched: Vec<ModuleName>,
+}
+
+impl Display for UnresolvedImport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} imported by {} in {}, searched {}",
+            self.import.display(),
+            self.importer.display(),
+            self.module,
+            self.searched
+                .iter()
+                .map(ModuleName::to_string)
+                .collect::<Vec<_>>()
+                .join(" -> ")
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 struct ProtoSource {
-    // Base path containing the content root.
-    worktree: PathBuf,
-    // Relative path from the base to the content root.
+    // Relative path from the repository root to the content root.
     content_root: PathBuf,
     // Relative path to the .proto file from the content root
     package_path: PathBuf,
@@ -40,31 +80,63 @@ struct ProtoSource {
 
 impl ProtoSource {
     fn full_path(&self) -> PathBuf {
-        self.worktree
-            .join(&self.content_root)
-            .join(&self.package_path)
+        self.content_root.join(&self.package_path)
     }
 }
 
 #[derive(Debug, PartialEq, Eq, Hash)]
 struct ProtoFileMapping<'m> {
-    // Worktree of the dependency
-    worktree: PathBuf,
-    // Relative path of the content root to the worktree
+    // Relative path of the content root to the repository root
     content_root: PathBuf,
     // Relative path of the proto file to the content root
     package_path: PathBuf,
     // The source module name
     module: &'m ModuleName,
-    // The coordinate of the module this file belongs to, used for error reporting.
+    // The coordinate of the module this file belongs to.
     coordinate: &'m Coordinate,
+    // The commit the file is read from.
+    commit_hash: &'m str,
+    // Directory of the output the file is relocated to
+    output_prefix: Option<&'m Path>,
 }
 
 impl<'m> ProtoFileMapping<'m> {
+    // Relative path of the proto file to the repository root
     fn source_path(&self) -> PathBuf {
-        self.worktree
-            .join(&self.content_root)
-            .join(&self.package_path)
+        self.content_root.join(&self.package_path)
+    }
+
+    // Path other files import the file by once it is copied
+    fn import_path(&self) -> PathBuf {
+        match self.output_prefix {
+            Some(prefix) => prefix.join(&self.package_path),
+            None => self.package_path.clone(),
+        }
+    }
+
+    // Relative path of the file in the output directory
+    fn target_path(&self, layout: OutputLayout) -> PathBuf {
+        match layout {
+            OutputLayout::Flat => self.import_path(),
+            OutputLayout::PerModule => {
+                PathBuf::from(self.module.to_string()).join(self.import_path())
+            }
+        }
+    }
+
+    // Uniquely identifies the source file across all modules
+    fn source_key(&self) -> PathBuf {
+        let mut key = self.coordinate.to_path();
+        key.push(self.commit_hash);
+        key.push(self.source_path());
+        key
+    }
+
+    fn read(&self, cache: &dyn RepositoryCache) -> Result<Vec<u8>, ProtoError> {
+        let mut contents = cache
+            .read_files(self.coordinate, self.commit_hash, &[self.source_path()])
+            .map_err(ProtoError::Cache)?;
+        Ok(contents.remove(0))
     }
 }
 
@@ -78,9 +150,10 @@ pub fn copy<C>(
     cache: C,
     resolved: ResolvedRootModule,
     proto_dir: PathBuf,
+    layout: OutputLayout,
+    imports: &ImportRules,
     parallelism: usize,
-    coord_locks: CoordinateLocks,
-) -> Result<(), ProtoError>
+) -> Result<OutputReport, ProtoError>
 where
     C: RepositoryCache + Clone + 'static,
 {
@@ -88,46 +161,111 @@ where
         .num_threads(parallelism.max(1))
         .build()?
         .install(|| {
-            let worktrees = resolved
-                .modules
-                .par_iter()
-                .map(|module| {
-                    let coord_lock = coord_locks.lock_for(&module.coordinate);
-                    let _g = coord_lock.lock().expect("coord lock poisoned");
-                    cache
-                        .create_worktree(&module.coordinate, &module.commit_hash)
-                        .map(|path| (module.name.clone(), path))
-                        .map_err(ProtoError::Cache)
-                })
-                .collect::<Result<HashMap<_, _>, _>>()?;
+            let mut context = plan(&cache, &resolved, layout, imports)?;
 
-            let modules = resolved
-                .modules
+            let unresolved = context.take_unresolved_imports();
+            if !unresolved.is_empty() && imports.strict_imports {
+                return Err(ProtoError::UnresolvedImports(unresolved));
+            }
+            let reported = unresolved
                 .iter()
-                .map(|m| (m.name.clone(), m))
-                .collect::<HashMap<_, _>>();
-
-            let mut context = Context {
-                worktrees,
-                modules,
-                plan: Vec::new(),
-                by_source: HashMap::new(),
-                by_target: HashMap::new(),
-                deny_policies: Vec::new(),
-                active_prunes: Vec::new(),
-                active_dependencies: Vec::new(),
-                additional_transitive_dependencies: Vec::new(),
-                unresolved_imports: Default::default(),
-            };
-
-            plan_copies(resolved.dependencies.iter().collect(), &mut context)?;
-
-            context.report_unresolved_imports();
-
-            copy_files(context.plan, proto_dir)
+                .map(|unresolved| unresolved.import.clone())
+                .collect();
+            let mut warnings = std::mem::take(&mut context.warnings);
+            for unresolved in unresolved {
+                let warning = Warning::UnresolvedImport(unresolved);
+                warn!("{}", warning);
+                warnings.push(warning);
+            }
+
+            copy_files(
+                &cache,
+                context.plan,
+                proto_dir,
+                layout,
+                imports,
+                &reported,
+                warnings,
+            )
+        })
+}
+
+/// Returns the files that copying `resolved` would write to the output directory, by module.
+/// Every file maps its path in the output directory to its path in the repository.
+///
+/// Unresolved imports are not reported.
+pub fn planned_files<C>(
+    cache: C,
+    resolved: &ResolvedRootModule,
+    layout: OutputLayout,
+    imports: &ImportRules,
+    parallelism: usize,
+) -> Result<BTreeMap<ModuleName, BTreeMap<PathBuf, PathBuf>>, ProtoError>
+where
+    C: RepositoryCache + Clone + 'static,
+{
+    ThreadPoolBuilder::new()
+        .num_threads(parallelism.max(1))
+        .build()?
+        .install(|| {
+            let context = plan(&cache, resolved, layout, imports)?;
+            let mut files = BTreeMap::<_, BTreeMap<_, _>>::new();
+            for mapping in &context.plan {
+                files
+                    .entry(mapping.module.clone())
+                    .or_default()
+                    .insert(mapping.target_path(layout), mapping.source_path());
+            }
+            Ok(files)
         })
 }
 
+fn plan<'m>(
+    cache: &'m dyn RepositoryCache,
+    resolved: &'m ResolvedRootModule,
+    layout: OutputLayout,
+    imports: &'m ImportRules,
+) -> Result<Context<'m>, ProtoError> {
+    let trees = resolved
+        .modules
+        .par_iter()
+        .map(|module| {
+            cache
+                .list_files(&module.coordinate, &module.commit_hash)
+                .map(|files| (module.name.clone(), files))
+                .map_err(ProtoError::Cache)
+        })
+        .collect::<Result<HashMap<_, _>, _>>()?;
+
+    let modules = resolved
+        .modules
+        .iter()
+        .map(|m| (m.name.clone(), m))
+        .collect::<HashMap<_, _>>();
+
+    let mut context = Context {
+        cache,
+        layout,
+        imports,
+        trees,
+        modules,
+        plan: Vec::new(),
+        by_source: HashMap::new(),
+        by_target: HashMap::new(),
+        deny_policies: Vec::new(),
+        active_prunes: Vec::new(),
+        active_dependencies: Vec::new(),
+        additional_transitive_dependencies: Vec::new(),
+        unresolved_imports: Default::default(),
+        warnings: Vec::new(),
+        reexports: Default::default(),
+        weak_imports: Default::default(),
+    };
+
+    plan_copies(resolved.dependencies.iter().collect(), &mut context)?;
+    Ok(context)
+}
+
 fn plan_copies<'a, 'm: 'a>(
     dependencies: Vec<&'m ResolvedDependency>,
     context: &'a mut Context<'m>,
@@ -212,7 +350,7 @@ fn plan_children<'a, 'm: 'a>(
             .with_maybe_prune_context(prune_context, |context| plan_copies(dependencies, context));
         result?;
         if let Some(prune_context) = prune_context {
-            context.extend_unresolved_imports(prune_context.root, prune_context.remaining);
+            context.extend_unresolved_imports(prune_context);
         }
         Ok::<_, ProtoError>(())
     })
@@ -234,11 +372,12 @@ fn plan_not_pruning<'a, 'm: 'a>(
 
         for proto in allowed_protos {
             context.add_mapping(ProtoFileMapping {
-                worktree: proto.worktree,
                 content_root: proto.content_root,
                 package_path: proto.package_path,
                 module: &module.name,
                 coordinate: &module.coordinate,
+                commit_hash: &module.commit_hash,
+                output_prefix: dependency.rules.output_prefix.as_deref(),
             });
         }
 
@@ -293,6 +432,11 @@ fn plan_pruning<'a, 'm: 'a>(
         let mut copied_files = false;
         let (child_prune_context, result) =
             context.with_maybe_prune_context(child_prune_context, |context| {
+                for prune_context in &mut context.active_prunes {
+                    if !prune_context.searched.contains(&&module.name) {
+                        prune_context.searched.push(&module.name);
+                    }
+                }
                 let prune_context = context
                     .active_prune()
                     .expect("Pruning context must exist at this point");
@@ -302,22 +446,98 @@ fn plan_pruning<'a, 'm: 'a>(
                     .cloned()
                     .collect::<VecDeque<_>>();
 
-                while let Some(needed) = queue.pop_front() {
-                    if let Some(proto) = candidate_protos.remove(&needed) {
+                while !queue.is_empty() {
+                    // The files needed so far are read at once, their imports in the next round
+                    let round = queue
+                        .drain(..)
+                        .map(|needed| {
+                            let proto = candidate_protos.remove(&needed);
+                            (needed, proto)
+                        })
+                        .collect::<Vec<_>>();
+                    let paths = round
+                        .iter()
+                        .filter_map(|(_, proto)| proto.as_ref().map(ProtoSource::full_path))
+                        .collect::<Vec<_>>();
+                    let mut contents = context
+                        .cache
+                        .read_files(&module.coordinate, &module.commit_hash, &paths)
+                        .map_err(ProtoError::Cache)?
+                        .into_iter();
+                    for (needed, proto) in round {
+                        let Some(proto) = proto else {
+                            // A file planned elsewhere still makes its re-exports visible here
+                            if let Some((owner, reexports)) =
+                                context.reexports.get(&needed).cloned()
+                            {
+                                let prune_context = context.active_prune_mut().unwrap();
+                                for reexport in reexports {
+                                    if !prune_context.seen.contains(&reexport)
+                                        && prune_context.remaining.insert(reexport.clone())
+                                    {
+                                        prune_context
+                                            .importers
+                                            .entry(reexport.clone())
+                                            .or_insert_with(|| (owner, needed.clone()));
+                                        queue.push_back(reexport);
+                                    }
+                                }
+                            }
+                            continue;
+                        };
                         copied_files = true;
-                        let prune_context = context.active_prune_mut().unwrap();
-                        for dependency in extract_proto_dependencies(&proto.full_path())? {
+                        let content = contents.next().expect("content of every needed file");
+                        let imports = match extract_proto_dependencies(&content, context.imports) {
+                            Ok(imports) => imports,
+                            Err(source) if context.imports.strict_imports => {
+                                return Err(ProtoError::Parse {
+                                    path: proto.full_path(),
+                                    source,
+                                })
+                            }
+                            Err(source) => {
+                                let warning = Warning::UnparsableImports {
+                                    module: module.name.clone(),
+                                    path: proto.full_path(),
+                                    error: source.to_string(),
+                                };
+                                warn!("{}", warning);
+                                context.warnings.push(warning);
+                                Vec::new()
+                            }
+                        };
+                        let reexports = imports
+                            .iter()
+                            .filter(|import| import.kind == ImportKind::Public)
+                            .map(|import| PathBuf::from(&import.path))
+                            .collect::<Vec<_>>();
+                        if !reexports.is_empty() {
+                            context
+                                .reexports
+                                .insert(proto.package_path.clone(), (&module.name, reexports));
+                        }
+                        for import in imports {
+                            let dependency = PathBuf::from(import.path);
+                            if import.kind == ImportKind::Weak {
+                                context.weak_imports.insert(dependency.clone());
+                            }
+                            let prune_context = context.active_prune_mut().unwrap();
                             if !prune_context.seen.contains(&dependency) {
+                                prune_context
+                                    .importers
+                                    .entry(dependency.clone())
+                                    .or_insert_with(|| (&module.name, proto.package_path.clone()));
                                 queue.push_back(dependency.clone());
                                 prune_context.remaining.insert(dependency);
                             }
                         }
                         context.add_mapping(ProtoFileMapping {
-                            worktree: proto.worktree,
                             content_root: proto.content_root,
                             package_path: proto.package_path,
                             module: &module.name,
                             coordinate: &module.coordinate,
+                            commit_hash: &module.commit_hash,
+                            output_prefix: dependency.rules.output_prefix.as_deref(),
                         });
                     }
                 }
@@ -334,7 +554,7 @@ fn collect_not_denied_files(
     dependency: &ResolvedDependency,
     context: &Context,
 ) -> Result<Vec<ProtoSource>, ProtoError> {
-    let worktree = context.worktree(&dependency.name);
+    let files = context.tree(&dependency.name);
 
     let mut content_roots = dependency
         .rules
@@ -347,7 +567,7 @@ fn collect_not_denied_files(
         content_roots.push(PathBuf::default());
     }
 
-    let protos = find_proto_files(worktree.clone(), content_roots)?;
+    let protos = find_proto_files(files, content_roots);
 
     let protos = protos
         .into_iter()
@@ -368,78 +588,64 @@ fn collect_not_denied_files(
     Ok(protos)
 }
 
-// Recursively finds all .proto files in the given directories and its subdirectories,
-// returning their relative paths to the closest root.
-// Provided worktree path must be absolute.
-fn find_proto_files(
-    worktree: PathBuf,
-    mut content_roots: Vec<PathBuf>,
-) -> Result<Vec<ProtoSource>, ProtoError> {
-    fn rec(
-        worktree: &Path,
-        content_root: &Path,
-        dir: &Path,
-        files: &mut Vec<ProtoSource>,
-        cutoff: &HashSet<PathBuf>,
-    ) -> Result<(), ProtoError> {
-        for entry in std::fs::read_dir(dir)? {
-            let entry = entry?;
-            let path = entry.path();
-            if path.is_dir() {
-                if !cutoff.contains(&path) {
-                    rec(worktree, content_root, &path, files, cutoff)?;
-                }
-            } else if let Some(extension) = path.extension() {
-                if extension == "proto" {
-                    if let Ok(path) = path.strip_prefix(worktree) {
-                        if let Ok(package_path) = path.strip_prefix(content_root) {
-                            files.push(ProtoSource {
-                                worktree: worktree.to_path_buf(),
-                                content_root: content_root.to_path_buf(),
-                                package_path: package_path.to_path_buf(),
-                            });
-                        }
-                    }
-                }
-            }
-        }
-
-        Ok(())
-    }
-
-    content_roots.sort_by_key(|content_root| content_root.as_os_str().len());
-
-    let mut files = Vec::<ProtoSource>::new();
-    let mut cutoff = HashSet::<PathBuf>::new();
+// Finds all .proto files under the given content roots, returning their paths relative to the
+// closest root. Paths of the files are relative to the repository root.
+fn find_proto_files(files: &[PathBuf], content_roots: Vec<PathBuf>) -> Vec<ProtoSource> {
+    files
+        .iter()
+        .filter(|file| {
+            file.extension()
+                .is_some_and(|extension| extension == "proto")
+        })
+        .filter_map(|file| {
+            content_roots
+                .iter()
+                .filter_map(|content_root| {
+                    let package_path = file.strip_prefix(content_root).ok()?;
+                    Some(ProtoSource {
+                        content_root: content_root.clone(),
+                        package_path: package_path.to_path_buf(),
+                    })
+                })
+                .max_by_key(|proto| proto.content_root.components().count())
+        })
+        .collect()
+}
 
-    for content_root in content_roots.into_iter().rev() {
-        let root_path = worktree.join(&content_root);
-        if root_path.is_dir() {
-            rec(&worktree, &content_root, &root_path, &mut files, &cutoff)?;
-        }
-        cutoff.insert(root_path);
-    }
-    Ok(files)
+/// Extracts the imports from a proto file, skipping the ones provided by the toolchain.
+fn extract_proto_dependencies(
+    content: &[u8],
+    rules: &ImportRules,
+) -> Result<Vec<Import>, ProtoParseError> {
+    let imports = proto::parse_imports(&String::from_utf8_lossy(content))?;
+    Ok(imports
+        .into_iter()
+        .filter(|import| !rules.is_provided(Path::new(&import.path)))
+        .collect())
 }
 
-/// Extracts the dependencies from a proto file, skipping google/protobuf imports,
-/// since these are provided by default by protoc.
-fn extract_proto_dependencies(file: &Path) -> Result<Vec<PathBuf>, ProtoError> {
-    let mut dependencies = Vec::new();
-    let mut reader = BufReader::new(File::open(file)?);
-    let mut line = String::new();
-    while reader.read_line(&mut line)? > 0 {
-        if line.starts_with("import ") {
-            if let Some(dependency) = line.split_whitespace().nth(1) {
-                let dependency = dependency.to_string().replace([';', '\"'], "");
-                if !dependency.starts_with("google/protobuf/") {
-                    dependencies.push(PathBuf::from(dependency));
-                }
-            }
-        }
-        line.clear();
-    }
-    Ok(dependencies)
+/// Replaces the paths of `import` statements for which `rewrite` returns a new path.
+/// Content that is not valid UTF-8 is returned unchanged.
+fn rewrite_imports(
+    content: Vec<u8>,
+    rewrite: impl Fn(&Path) -> Option<PathBuf>,
+) -> Result<Vec<u8>, ProtoParseError> {
+    let text = match String::from_utf8(content) {
+        Ok(text) => text,
+        Err(error) => return Ok(error.into_bytes()),
+    };
+    let rewritten = proto::rewrite_imports(&text, |import| {
+        let import = rewrite(Path::new(import))?;
+        // Imports always use forward slashes
+        Some(
+            import
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/"),
+        )
+    })?;
+    Ok(rewritten.into_bytes())
 }
 
 fn filter_allowed_files(
@@ -457,52 +663,204 @@ fn filter_allowed_files(
         .collect::<Vec<_>>()
 }
 
-fn copy_files(plan: Vec<ProtoFileMapping<'_>>, proto_dir: PathBuf) -> Result<(), ProtoError> {
-    for mapping in plan {
-        trace!(
-            "Copying proto file {} from {}/ for dependency {} ({})",
-            &mapping.package_path.to_string_lossy(),
-            &mapping.content_root.to_string_lossy(),
-            &mapping.module,
-            &mapping.coordinate
-        );
-        let source = mapping
-            .worktree
-            .join(&mapping.content_root)
-            .join(&mapping.package_path);
-        let target = proto_dir.join(&mapping.package_path);
-        let prefix = target.parent().ok_or_else(|| {
-            ProtoError::BadPath(format!(
-                "Bad parent dest file for {}",
-                &target.to_string_lossy()
-            ))
-        })?;
-        std::fs::create_dir_all(prefix)?;
-        std::fs::copy(source, target)?;
+/// Reads the content of every planned file, reading the files of a module at once.
+fn read_planned_files(
+    cache: &dyn RepositoryCache,
+    plan: &[ProtoFileMapping],
+) -> Result<Vec<Vec<u8>>, ProtoError> {
+    let mut by_commit = HashMap::<(&Coordinate, &str), Vec<usize>>::new();
+    for (index, mapping) in plan.iter().enumerate() {
+        by_commit
+            .entry((mapping.coordinate, mapping.commit_hash))
+            .or_default()
+            .push(index);
     }
-    Ok(())
+    let read = by_commit
+        .into_par_iter()
+        .map(|((coordinate, commit_hash), indices)| {
+            let paths = indices
+                .iter()
+                .map(|&index| plan[index].source_path())
+                .collect::<Vec<_>>();
+            let contents = cache
+                .read_files(coordinate, commit_hash, &paths)
+                .map_err(ProtoError::Cache)?;
+            Ok(indices.into_iter().zip(contents).collect::<Vec<_>>())
+        })
+        .collect::<Result<Vec<_>, ProtoError>>()?;
+    let mut contents = vec![Vec::new(); plan.len()];
+    for (index, content) in read.into_iter().flatten() {
+        contents[index] = content;
+    }
+    Ok(contents)
 }
 
-fn files_differ(a: &Path, b: &Path) -> Result<bool, ProtoError> {
-    let a = std::fs::read(a)?;
-    let b = std::fs::read(b)?;
-    Ok(a != b)
+/// Brings the output directory in line with the plan: planned files are written unless they
+/// are already up to date, and files written by a previous fetch that are no longer planned are
+/// removed.
+/// The changes are made to a staged copy of the files a previous fetch wrote, which are only
+/// replaced once all changes succeeded, while other files of the directory are left alone.
+/// Imports of the resulting files that cannot be resolved are reported, unless they were already
+/// reported while pruning.
+fn copy_files(
+    cache: &dyn RepositoryCache,
+    plan: Vec<ProtoFileMapping<'_>>,
+    proto_dir: PathBuf,
+    layout: OutputLayout,
+    imports: &ImportRules,
+    reported: &HashSet<PathBuf>,
+    warnings: Vec<Warning>,
+) -> Result<OutputReport, ProtoError> {
+    let planned = plan
+        .iter()
+        .map(|mapping| mapping.target_path(layout))
+        .collect::<HashSet<_>>();
+    // Imports of relocated files are resolved against the files of the same module first
+    let mut by_package = HashMap::<&Path, Vec<&ProtoFileMapping>>::new();
+    for mapping in &plan {
+        by_package
+            .entry(&mapping.package_path)
+            .or_default()
+            .push(mapping);
+    }
+    let relocated = plan.iter().any(|mapping| mapping.output_prefix.is_some());
+
+    let mut written_files = planned.clone();
+    if layout == OutputLayout::PerModule {
+        written_files.insert(PathBuf::from(output::INCLUDE_PATHS_FILE_NAME));
+    }
+
+    let staged = StagedDir::new(&proto_dir)?;
+    let mut report = OutputReport {
+        removed: output::remove_stale(staged.path(), &written_files)?,
+        warnings,
+        ..Default::default()
+    };
+
+    let contents = read_planned_files(cache, &plan)?;
+    let written = plan
+        .par_iter()
+        .zip(contents)
+        .map(|(mapping, mut content)| {
+            trace!(
+                "Copying proto file {} from {}/ for dependency {} ({})",
+                &mapping.package_path.to_string_lossy(),
+                &mapping.content_root.to_string_lossy(),
+                &mapping.module,
+                &mapping.coordinate
+            );
+            if relocated {
+                content = rewrite_imports(content, |import| {
+                    let candidates = by_package.get(import)?;
+                    let imported = candidates
+                        .iter()
+                        .find(|candidate| candidate.module == mapping.module)
+                        .or_else(|| candidates.first())?;
+                    let import_path = imported.import_path();
+                    (import_path != import).then_some(import_path)
+                })
+                .map_err(|source| ProtoError::Parse {
+                    path: mapping.source_path(),
+                    source,
+                })?;
+            }
+            let target = staged.path().join(mapping.target_path(layout));
+            if target.parent().is_none() {
+                return Err(ProtoError::BadPath(format!(
+                    "Bad parent dest file for {}",
+                    &target.to_string_lossy()
+                )));
+            }
+            // The duplicate symbol check only warns, so it must not fail the fetch either
+            let declarations = proto::parse_declarations(&String::from_utf8_lossy(&content))
+                .unwrap_or_else(|error| {
+                    warn!(
+                        "Skipping {} in the duplicate symbol check: {}",
+                        mapping.source_path().display(),
+                        error
+                    );
+                    Default::default()
+                });
+            Ok((output::write_if_changed(&target, &content)?, declarations))
+        })
+        .collect::<Result<Vec<_>, ProtoError>>()?;
+    for (written, _) in &written {
+        report.record(*written);
+    }
+
+    let targets = plan
+        .iter()
+        .map(|mapping| mapping.target_path(layout))
+        .collect::<Vec<_>>();
+    let duplicates = symbols::find_duplicate_symbols(plan.iter().zip(&targets).zip(&written).map(
+        |((mapping, target), (_, declarations))| (mapping.module, target.as_path(), declarations),
+    ));
+    for duplicate in duplicates {
+        let warning = Warning::DuplicateSymbol(duplicate);
+        warn!("{}", warning);
+        report.warnings.push(warning);
+    }
+
+    let include_paths = staged.path().join(output::INCLUDE_PATHS_FILE_NAME);
+    let include_roots = match layout {
+        OutputLayout::Flat => vec![PathBuf::new()],
+        OutputLayout::PerModule => {
+            let modules = plan
+                .iter()
+                .map(|mapping| mapping.module.to_string())
+                .collect::<BTreeSet<_>>();
+            let mut content = String::new();
+            for module in &modules {
+                content.push_str(module);
+                content.push('\n');
+            }
+            output::write_if_changed(&include_paths, content.as_bytes())?;
+            modules.into_iter().map(PathBuf::from).collect()
+        }
+    };
+
+    let dangling = closure::find_dangling_imports(staged.path(), &include_roots, imports)?
+        .into_iter()
+        .filter(|dangling| !reported.contains(&dangling.import))
+        .collect::<Vec<_>>();
+    if !dangling.is_empty() && imports.strict_imports {
+        return Err(ProtoError::DanglingImports(dangling));
+    }
+    for dangling in dangling {
+        let warning = Warning::DanglingImport(dangling);
+        warn!("{}", warning);
+        report.warnings.push(warning);
+    }
+
+    staged.commit()?;
+    Ok(report)
 }
 
 struct Context<'m> {
-    worktrees: HashMap<ModuleName, PathBuf>,
+    cache: &'m dyn RepositoryCache,
+    layout: OutputLayout,
+    imports: &'m ImportRules,
+    // Files of every module at its resolved commit
+    trees: HashMap<ModuleName, Vec<PathBuf>>,
     modules: HashMap<ModuleName, &'m ResolvedModule>,
     // The plan of which files to copy from where to where
     plan: Vec<ProtoFileMapping<'m>>,
     // Maps source paths to their index in the plan.
     by_source: HashMap<PathBuf, usize>,
-    // Maps target paths to their index in the plan.
+    // Maps target paths, relative to the output directory, to their index in the plan.
     by_target: HashMap<PathBuf, usize>,
     deny_policies: Vec<&'m DenyPolicies>,
     active_prunes: Vec<PruneContext<'m>>,
     active_dependencies: Vec<&'m ResolvedDependency>,
     additional_transitive_dependencies: Vec<&'m ResolvedDependency>,
-    unresolved_imports: Vec<(&'m ResolvedModule, PathBuf)>,
+    unresolved_imports: Vec<UnresolvedImport>,
+    // Files that were discarded in favor of previously planned ones.
+    warnings: Vec<Warning>,
+    // Maps files to the imports they make visible to their importers with `import public`.
+    // Also keeps the module the file was planned from.
+    reexports: HashMap<PathBuf, (&'m ModuleName, Vec<PathBuf>)>,
+    // Imports that are allowed to be missing.
+    weak_imports: HashSet<PathBuf>,
 }
 
 impl<'m> Context<'m> {
@@ -512,44 +870,46 @@ impl<'m> Context<'m> {
             .expect("Internal invariant violated: no information about the already resolved module")
     }
 
-    fn worktree(&self, name: &ModuleName) -> &PathBuf {
-        self.worktrees
+    fn tree(&self, name: &ModuleName) -> &[PathBuf] {
+        self.trees
             .get(name)
-            .expect("Internal invariant violated: no worktree for the already resolved module")
+            .expect("Internal invariant violated: no file list for the already resolved module")
     }
 
     fn add_mapping(&mut self, mapping: ProtoFileMapping<'m>) {
-        let mut source_path = mapping.worktree.clone();
-        source_path.push(&mapping.content_root);
-        source_path.push(&mapping.package_path);
+        let source_key = mapping.source_key();
 
-        if let Some(existing) = self.by_source.get(&source_path) {
+        if let Some(existing) = self.by_source.get(&source_key) {
             let existing = &self.plan[*existing];
-            if existing.package_path != mapping.package_path {
-                warn!(
-                    "Discarded duplicate target {} in favor of {} for {} ({})",
-                    mapping.package_path.display(),
-                    existing.package_path.display(),
-                    mapping.module,
-                    mapping.coordinate
-                );
+            if existing.target_path(self.layout) != mapping.target_path(self.layout) {
+                let warning = Warning::DiscardedTarget {
+                    module: mapping.module.clone(),
+                    coordinate: mapping.coordinate.to_string(),
+                    discarded: mapping.target_path(self.layout),
+                    kept: existing.target_path(self.layout),
+                };
+                warn!("{}", warning);
+                self.warnings.push(warning);
                 return;
             }
         }
-        if let Some(existing) = self.by_target.get(&mapping.package_path) {
+        let target_path = mapping.target_path(self.layout);
+        if let Some(existing) = self.by_target.get(&target_path) {
             let existing = &self.plan[*existing];
-            if existing.worktree != mapping.worktree
+            if existing.coordinate != mapping.coordinate
+                || existing.commit_hash != mapping.commit_hash
                 || existing.content_root != mapping.content_root
             {
-                if files_differ(&existing.source_path(), &mapping.source_path()).unwrap_or(true) {
-                    warn!(
-                        "Discarded conflicting source {} ({}) in favor of {} ({}) for {}",
-                        mapping.module,
-                        mapping.coordinate,
-                        existing.module,
-                        existing.coordinate,
-                        mapping.package_path.display(),
-                    );
+                if self.files_differ(existing, &mapping).unwrap_or(true) {
+                    let warning = Warning::DiscardedSource {
+                        target: target_path,
+                        module: mapping.module.clone(),
+                        coordinate: mapping.coordinate.to_string(),
+                        kept_module: existing.module.clone(),
+                        kept_coordinate: existing.coordinate.to_string(),
+                    };
+                    warn!("{}", warning);
+                    self.warnings.push(warning);
                 } else {
                     debug!(
                         "Discarded conflicting identical source {} ({}) in favor of {} ({}) for {}",
@@ -557,7 +917,7 @@ impl<'m> Context<'m> {
                         mapping.coordinate,
                         existing.module,
                         existing.coordinate,
-                        mapping.package_path.display(),
+                        target_path.display(),
                     );
                 }
                 return;
@@ -570,11 +930,19 @@ impl<'m> Context<'m> {
         }
 
         let index = self.plan.len();
-        self.by_source.insert(source_path, index);
-        self.by_target.insert(mapping.package_path.clone(), index);
+        self.by_source.insert(source_key, index);
+        self.by_target.insert(target_path, index);
         self.plan.push(mapping);
     }
 
+    fn files_differ(
+        &self,
+        a: &ProtoFileMapping<'_>,
+        b: &ProtoFileMapping<'_>,
+    ) -> Result<bool, ProtoError> {
+        Ok(a.read(self.cache)? != b.read(self.cache)?)
+    }
+
     fn with_deny_policy<F, R>(&mut self, policy: &'m DenyPolicies, f: F) -> R
     where
         F: FnOnce(&mut Self) -> R,
@@ -663,44 +1031,157 @@ impl<'m> Context<'m> {
         self.active_prunes.last_mut()
     }
 
-    fn extend_unresolved_imports(&mut self, module: &'m ResolvedModule, imports: HashSet<PathBuf>) {
-        self.unresolved_imports
-            .extend(imports.into_iter().map(|import| (module, import)));
+    fn extend_unresolved_imports(&mut self, prune_context: PruneContext<'m>) {
+        let PruneContext {
+            remaining,
+            importers,
+            searched,
+            ..
+        } = prune_context;
+        // Imports without an importer are inherited from an outer pruning, which reports them
+        for import in remaining {
+            if let Some((module, importer)) = importers.get(&import) {
+                self.unresolved_imports.push(UnresolvedImport {
+                    import,
+                    importer: importer.clone(),
+                    module: (*module).clone(),
+                    searched: searched.iter().map(|&name| name.clone()).collect(),
+                });
+            }
+        }
     }
 
-    fn report_unresolved_imports(&self) {
-        for (module, import) in &self.unresolved_imports {
-            if self.by_target.contains_key(import) {
+    /// Returns the imports that were not resolved, leaving out the ones that were still copied
+    /// from elsewhere and weak imports.
+    fn take_unresolved_imports(&mut self) -> Vec<UnresolvedImport> {
+        let mut unresolved = std::mem::take(&mut self.unresolved_imports);
+        unresolved.retain(|unresolved| {
+            if self
+                .plan
+                .iter()
+                .any(|mapping| mapping.package_path == unresolved.import)
+            {
                 debug!(
-                    "Import {} was not resolved when pruning {} ({}), but it is still present in the final set of copied files",
-                    import.display(),
-                    module.name,
-                    module.coordinate
+                    "Import {} was not resolved when pruning {}, but it is still present in the final set of copied files",
+                    unresolved.import.display(),
+                    unresolved.module,
                 );
+                false
+            } else if self.weak_imports.contains(&unresolved.import) {
+                debug!("Weak import {} was not resolved", unresolved);
+                false
             } else {
-                warn!(
-                    "Import {} was not resolved when pruning {} ({})",
-                    import.display(),
-                    module.name,
-                    module.coordinate
-                );
+                true
             }
-        }
+        });
+        unresolved.sort_by(|a, b| (&a.import, &a.importer).cmp(&(&b.import, &b.importer)));
+        unresolved
     }
 }
 
 struct PruneContext<'m> {
-    root: &'m ResolvedModule,
     remaining: HashSet<PathBuf>,
     seen: HashSet<PathBuf>,
+    // Maps imports to the module and the path of the file that first needed them.
+    importers: HashMap<PathBuf, (&'m ModuleName, PathBuf)>,
+    // Modules looked into while this pruning was active, in order.
+    searched: Vec<&'m ModuleName>,
 }
 
 impl<'m> PruneContext<'m> {
     fn new(root: &'m ResolvedModule, remaining: HashSet<PathBuf>) -> PruneContext<'m> {
         PruneContext {
-            root,
             remaining,
             seen: HashSet::new(),
+            importers: HashMap::new(),
+            searched: vec![&root.name],
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths(paths: &[&str]) -> Vec<PathBuf> {
+        paths.iter().map(PathBuf::from).collect()
+    }
+
+    #[test]
+    fn find_proto_files_uses_the_closest_content_root() {
+        let files = paths(&[
+            "README.md",
+            "proto/a.proto",
+            "proto/nested/b.proto",
+            "other/c.proto",
+        ]);
+
+        let protos = find_proto_files(&files, paths(&["proto", "proto/nested"]));
+
+        assert_eq!(
+            protos,
+            vec![
+                ProtoSource {
+                    content_root: PathBuf::from("proto"),
+                    package_path: PathBuf::from("a.proto"),
+                },
+                ProtoSource {
+                    content_root: PathBuf::from("proto/nested"),
+                    package_path: PathBuf::from("b.proto"),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn extract_proto_dependencies_skips_provided_imports() {
+        let content = b"syntax = \"proto3\";\n\
+                        import \"google/protobuf/any.proto\";\n\
+                        import \"google/api/annotations.proto\";\n\
+                        import \"org/a.proto\";\n";
+        let import_paths = |rules: &ImportRules| {
+            extract_proto_dependencies(content, rules)
+                .unwrap()
+                .into_iter()
+                .map(|import| import.path)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            import_paths(&ImportRules::default()),
+            vec!["google/api/annotations.proto", "org/a.proto"]
+        );
+        assert_eq!(
+            import_paths(&ImportRules {
+                provided_imports: BTreeSet::from(["google/api/*".parse().unwrap()]),
+                fetch_wellknown: true,
+                strict_imports: false,
+            }),
+            vec!["google/protobuf/any.proto", "org/a.proto"]
+        );
+    }
+
+    #[test]
+    fn rewrite_imports_replaces_only_relocated_paths() {
+        let content = b"syntax = \"proto3\";\n\
+                        import \"api/v1/a.proto\";\n\
+                        import public \"api/v1/b.proto\"; // comment\n\
+                        import \"other.proto\";\n"
+            .to_vec();
+
+        let rewritten = rewrite_imports(content, |import| {
+            import
+                .starts_with("api")
+                .then(|| Path::new("vendor/acme").join(import))
+        })
+        .unwrap();
+
+        assert_eq!(
+            String::from_utf8(rewritten).unwrap(),
+            "syntax = \"proto3\";\n\
+             import \"vendor/acme/api/v1/a.proto\";\n\
+             import public \"vendor/acme/api/v1/b.proto\"; // comment\n\
+             import \"other.proto\";\n"
+        );
+    }
+}
@@ -0,0 +1,154 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+use similar::TextDiff;
+
+use crate::{cache::RepositoryCache, model::protofetch::Coordinate};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileChange {
+    Added,
+    Removed,
+    Changed,
+}
+
+impl Display for FileChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let status = match self {
+            FileChange::Added => "A",
+            FileChange::Removed => "D",
+            FileChange::Changed => "M",
+        };
+        f.write_str(status)
+    }
+}
+
+/// A file of the output directory that differs between two commits of a dependency.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileDiff {
+    /// Path of the file in the output directory.
+    pub path: PathBuf,
+    pub change: FileChange,
+    /// Unified diff of the content, if requested.
+    pub unified: Option<String>,
+}
+
+/// The files one dependency contributes to the output at a commit, mapping their paths in the
+/// output directory to their paths in the repository.
+pub struct OutputFiles<'a> {
+    pub commit_hash: &'a str,
+    pub files: &'a BTreeMap<PathBuf, PathBuf>,
+}
+
+/// Compares the files a dependency contributes to the output at two commits.
+pub fn diff_files(
+    cache: &dyn RepositoryCache,
+    coordinate: &Coordinate,
+    old: OutputFiles,
+    new: OutputFiles,
+    unified: bool,
+) -> anyhow::Result<Vec<FileDiff>> {
+    let mut old_contents = read_files(cache, coordinate, &old)?;
+    let mut new_contents = read_files(cache, coordinate, &new)?;
+
+    let paths = old
+        .files
+        .keys()
+        .chain(new.files.keys())
+        .collect::<BTreeSet<_>>();
+    let mut diffs = Vec::new();
+    for path in paths {
+        let old_content = old_contents.remove(path);
+        let new_content = new_contents.remove(path);
+        let change = match (&old_content, &new_content) {
+            (None, Some(_)) => FileChange::Added,
+            (Some(_), None) => FileChange::Removed,
+            (Some(old_content), Some(new_content)) if old_content != new_content => {
+                FileChange::Changed
+            }
+            _ => continue,
+        };
+        let unified = unified.then(|| {
+            unified_diff(
+                path,
+                old_content.as_deref().map(String::from_utf8_lossy),
+                new_content.as_deref().map(String::from_utf8_lossy),
+            )
+        });
+        diffs.push(FileDiff {
+            path: path.clone(),
+            change,
+            unified,
+        });
+    }
+    Ok(diffs)
+}
+
+/// Reads the content of every file, by its path in the output directory.
+fn read_files<'a>(
+    cache: &dyn RepositoryCache,
+    coordinate: &Coordinate,
+    files: &OutputFiles<'a>,
+) -> anyhow::Result<BTreeMap<&'a PathBuf, Vec<u8>>> {
+    let sources = files.files.values().cloned().collect::<Vec<_>>();
+    let contents = cache.read_files(coordinate, files.commit_hash, &sources)?;
+    Ok(files.files.keys().zip(contents).collect())
+}
+
+fn unified_diff(path: &Path, old: Option<impl AsRef<str>>, new: Option<impl AsRef<str>>) -> String {
+    let header = |prefix: &str, content: bool| {
+        if content {
+            format!("{}/{}", prefix, path.display())
+        } else {
+            "/dev/null".to_owned()
+        }
+    };
+    let old_header = header("a", old.is_some());
+    let new_header = header("b", new.is_some());
+    let old = old.as_ref().map_or("", AsRef::as_ref);
+    let new = new.as_ref().map_or("", AsRef::as_ref);
+    TextDiff::from_lines(old, new)
+        .unified_diff()
+        .header(&old_header, &new_header)
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unified_diffs_of_added_files_are_against_dev_null() {
+        assert_eq!(
+            unified_diff(
+                Path::new("acme/api.proto"),
+                None::<&str>,
+                Some("syntax = \"proto3\";\n")
+            ),
+            "--- /dev/null\n\
+             +++ b/acme/api.proto\n\
+             @@ -0,0 +1 @@\n\
+             +syntax = \"proto3\";\n"
+        );
+    }
+
+    #[test]
+    fn unified_diffs_of_changed_files_show_the_changed_lines() {
+        assert_eq!(
+            unified_diff(
+                Path::new("acme/api.proto"),
+                Some("syntax = \"proto3\";\nmessage A {}\n"),
+                Some("syntax = \"proto3\";\nmessage B {}\n")
+            ),
+            "--- a/acme/api.proto\n\
+             +++ b/acme/api.proto\n\
+             @@ -1,2 +1,2 @@\n \
+             syntax = \"proto3\";\n\
+             -message A {}\n\
+             +message B {}\n"
+        );
+    }
+}
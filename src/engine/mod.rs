@@ -1,23 +1,34 @@
 pub mod model;
 
+mod breaking;
+mod closure;
+mod compile;
 mod copy;
+mod diff;
 mod fetch;
+mod output;
 mod resolve;
+mod symbols;
+mod warning;
 
 use std::str::Utf8Error;
 
 use thiserror::Error;
 
-pub use copy::copy;
+pub use breaking::{breaking_changes, BreakingChange};
+pub use compile::{compile, CompileError};
+pub use copy::{copy, planned_files, ProtoError};
+pub use diff::{diff_files, FileChange, FileDiff, OutputFiles};
 pub use fetch::fetch;
 pub use resolve::resolve;
+pub use warning::Warning;
 
 /// Tunables for the parallel resolver / fetcher.
 #[derive(Debug, Clone, Copy)]
 pub struct ParallelConfig {
     /// Maximum number of in-flight network operations (resolve / fetch).
     pub network_jobs: usize,
-    /// Maximum number of in-flight disk-bound operations (worktree + copy).
+    /// Maximum number of in-flight disk-bound operations (tree listing + copy).
     pub copy_jobs: usize,
 }
 
@@ -1,10 +1,17 @@
-use crate::model::protofetch::{Coordinate, ModuleName, RevisionSpecification, Rules};
+use crate::{
+    engine::Warning,
+    model::protofetch::{Coordinate, ModuleName, RevisionSpecification, Rules},
+};
 
+#[derive(Clone)]
 pub struct ResolvedRootModule {
     pub modules: Vec<ResolvedModule>,
     pub dependencies: Vec<ResolvedDependency>,
+    /// Dependencies that were declared more than once and discarded.
+    pub warnings: Vec<Warning>,
 }
 
+#[derive(Clone)]
 pub struct ResolvedModule {
     pub name: ModuleName,
     pub commit_hash: String,
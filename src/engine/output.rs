@@ -0,0 +1,437 @@
+use std::{
+    collections::{BTreeSet, HashSet},
+    ffi::OsString,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use log::{debug, trace, warn};
+
+use crate::{engine::Warning, model::protofetch::OutputLayout};
+
+/// Lists the directories of the output that protoc needs as include paths, relative to the output
+/// directory. Only written for [`OutputLayout::PerModule`].
+pub(super) const INCLUDE_PATHS_FILE_NAME: &str = "include_paths.txt";
+
+/// Lists the files of the output directory that protofetch wrote, relative to it. Only these are
+/// ever removed, so that files put into the output directory by other means survive a fetch.
+pub(super) const MANIFEST_FILE_NAME: &str = ".protofetch-manifest";
+
+/// Returns the directories of the output, relative to it, that imports are resolved against.
+pub(super) fn include_roots(dir: &Path, layout: OutputLayout) -> std::io::Result<Vec<PathBuf>> {
+    match layout {
+        OutputLayout::Flat => Ok(vec![PathBuf::new()]),
+        OutputLayout::PerModule => Ok(std::fs::read_to_string(dir.join(INCLUDE_PATHS_FILE_NAME))?
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(PathBuf::from)
+            .collect()),
+    }
+}
+
+/// What a fetch changed in the output directory.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct OutputReport {
+    pub added: usize,
+    pub changed: usize,
+    pub removed: usize,
+    pub unchanged: usize,
+    /// Problems found while planning and copying the files.
+    pub warnings: Vec<Warning>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Written {
+    Added,
+    Changed,
+    Unchanged,
+}
+
+impl OutputReport {
+    pub(super) fn record(&mut self, written: Written) {
+        match written {
+            Written::Added => self.added += 1,
+            Written::Changed => self.changed += 1,
+            Written::Unchanged => self.unchanged += 1,
+        }
+    }
+}
+
+/// Writes the file unless it already has the given content, so that its modification time only
+/// changes when the content does.
+///
+/// A changed file is replaced instead of overwritten, because a [`StagedDir`] shares the files
+/// with the directory it stages.
+pub(super) fn write_if_changed(path: &Path, content: &[u8]) -> std::io::Result<Written> {
+    let written = match std::fs::read(path) {
+        Ok(existing) if existing == content => return Ok(Written::Unchanged),
+        Ok(_) => {
+            std::fs::remove_file(path)?;
+            Written::Changed
+        }
+        Err(error) if error.kind() == ErrorKind::NotFound => {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            Written::Added
+        }
+        Err(error) => return Err(error),
+    };
+    trace!("Writing {} ({:?})", path.display(), written);
+    std::fs::write(path, content)?;
+    Ok(written)
+}
+
+/// Reads the files listed in the manifest of `dir`, none if it has no manifest.
+pub(super) fn read_manifest(dir: &Path) -> std::io::Result<HashSet<PathBuf>> {
+    match std::fs::read_to_string(dir.join(MANIFEST_FILE_NAME)) {
+        Ok(manifest) => Ok(manifest
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(PathBuf::from)
+            .collect()),
+        Err(error) if error.kind() == ErrorKind::NotFound => Ok(HashSet::new()),
+        Err(error) => Err(error),
+    }
+}
+
+/// Removes the files under `dir` that its manifest lists but whose paths relative to `dir` are
+/// not in `keep`, together with directories left empty by that, and then lists `keep` in the
+/// manifest. Files the manifest does not list are never touched.
+/// Returns the number of removed files.
+pub(super) fn remove_stale(dir: &Path, keep: &HashSet<PathBuf>) -> std::io::Result<usize> {
+    let mut removed = 0;
+    for file in read_manifest(dir)?.difference(keep) {
+        let path = dir.join(file);
+        if remove_file(dir, &path)? {
+            debug!("Removing stale file {}", path.display());
+            removed += 1;
+        }
+    }
+
+    let mut manifest = String::new();
+    for file in keep.iter().collect::<BTreeSet<_>>() {
+        manifest.push_str(&file.to_string_lossy());
+        manifest.push('\n');
+    }
+    write_if_changed(&dir.join(MANIFEST_FILE_NAME), manifest.as_bytes())?;
+    Ok(removed)
+}
+
+/// A copy of the files of a directory that protofetch wrote, as listed in its manifest, that is
+/// modified instead of them and then applied with [`Self::commit`], so that a failed fetch never
+/// leaves partially written files behind. Other files of the directory are neither copied nor
+/// touched, so the directory may also hold files of the project.
+///
+/// The copy is kept next to the directory, in `.<name>.protofetch-new`, so that renames stay on
+/// the same filesystem. Once complete, it is renamed to `.<name>.protofetch-ready` and its files
+/// are moved into the directory, the manifest last, which the next fetch completes if it was
+/// interrupted. The files of the copy are hard links to the files of the directory, so they must
+/// be replaced, as done by [`write_if_changed`], and never modified in place.
+pub(super) struct StagedDir {
+    target: PathBuf,
+    staging: PathBuf,
+    ready: PathBuf,
+    committed: bool,
+}
+
+impl StagedDir {
+    pub(super) fn new(target: &Path) -> std::io::Result<Self> {
+        let name = target.file_name().ok_or_else(|| {
+            std::io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("{} cannot be used as an output directory", target.display()),
+            )
+        })?;
+        let sibling = |suffix: &str| {
+            let mut sibling_name = OsString::from(".");
+            sibling_name.push(name);
+            sibling_name.push(suffix);
+            target.with_file_name(sibling_name)
+        };
+        let staged = StagedDir {
+            target: target.to_path_buf(),
+            staging: sibling(".protofetch-new"),
+            ready: sibling(".protofetch-ready"),
+            committed: false,
+        };
+
+        // Leftovers of an interrupted fetch
+        if staged.ready.exists() {
+            warn!(
+                "Completing the update of {}, the previous fetch was interrupted",
+                target.display()
+            );
+            staged.apply()?;
+        }
+        if staged.staging.exists() {
+            debug!("Removing leftover {}", staged.staging.display());
+            std::fs::remove_dir_all(&staged.staging)?;
+        }
+
+        std::fs::create_dir_all(&staged.staging)?;
+        let mut owned = read_manifest(target)?;
+        owned.insert(PathBuf::from(MANIFEST_FILE_NAME));
+        for file in owned {
+            let from = target.join(&file);
+            if from.is_file() {
+                link_file(&from, &staged.staging.join(&file))?;
+            }
+        }
+        Ok(staged)
+    }
+
+    pub(super) fn path(&self) -> &Path {
+        &self.staging
+    }
+
+    /// Applies the staged changes to the directory.
+    pub(super) fn commit(mut self) -> std::io::Result<()> {
+        std::fs::rename(&self.staging, &self.ready)?;
+        self.committed = true;
+        self.apply()
+    }
+
+    /// Removes the files of the directory that the manifest of the ready copy no longer lists,
+    /// and moves the files of the ready copy into the directory.
+    fn apply(&self) -> std::io::Result<()> {
+        let manifest = self.ready.join(MANIFEST_FILE_NAME);
+        // Without a manifest, the ready copy was already applied
+        if manifest.exists() {
+            let files = read_manifest(&self.ready)?;
+            for file in read_manifest(&self.target)?.difference(&files) {
+                remove_file(&self.target, &self.target.join(file))?;
+            }
+            for file in &files {
+                let from = self.ready.join(file);
+                // Already moved by an interrupted commit
+                if !from.exists() {
+                    continue;
+                }
+                let to = self.target.join(file);
+                if let Some(parent) = to.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                std::fs::rename(from, to)?;
+            }
+            std::fs::rename(manifest, self.target.join(MANIFEST_FILE_NAME))?;
+        }
+        std::fs::remove_dir_all(&self.ready)
+    }
+}
+
+impl Drop for StagedDir {
+    fn drop(&mut self) {
+        if !self.committed {
+            if let Err(error) = std::fs::remove_dir_all(&self.staging) {
+                debug!("Could not remove {}: {}", self.staging.display(), error);
+            }
+        }
+    }
+}
+
+/// Removes the file at `path`, if any, together with directories up to `root` left empty by that.
+/// Returns whether the file existed.
+fn remove_file(root: &Path, path: &Path) -> std::io::Result<bool> {
+    match std::fs::remove_file(path) {
+        Ok(()) => {}
+        Err(error) if error.kind() == ErrorKind::NotFound => return Ok(false),
+        Err(error) => return Err(error),
+    }
+    for parent in path.ancestors().skip(1) {
+        if parent == root || std::fs::read_dir(parent)?.next().is_some() {
+            break;
+        }
+        std::fs::remove_dir(parent)?;
+    }
+    Ok(true)
+}
+
+/// Creates a hard link to a file, or a copy of it where links are not supported.
+fn link_file(from: &Path, to: &Path) -> std::io::Result<()> {
+    if let Some(parent) = to.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    if let Err(error) = std::fs::hard_link(from, to) {
+        trace!(
+            "Copying {} instead of linking it: {}",
+            from.display(),
+            error
+        );
+        std::fs::copy(from, to)?;
+        let modified = std::fs::metadata(from)?.modified()?;
+        std::fs::File::options()
+            .write(true)
+            .open(to)?
+            .set_modified(modified)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn unchanged_files_are_not_rewritten() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("org/a.proto");
+
+        assert_eq!(write_if_changed(&path, b"a").unwrap(), Written::Added);
+        assert_eq!(write_if_changed(&path, b"a").unwrap(), Written::Unchanged);
+        assert_eq!(write_if_changed(&path, b"b").unwrap(), Written::Changed);
+        assert_eq!(fs::read(&path).unwrap(), b"b");
+    }
+
+    #[test]
+    fn stale_files_and_their_empty_directories_are_removed() {
+        let dir = tempfile::tempdir().unwrap();
+        for file in [
+            "kept.proto",
+            "old/stale.proto",
+            "user.proto",
+            "other/BUILD",
+            "empty/.keep",
+        ] {
+            let path = dir.path().join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, b"").unwrap();
+        }
+        fs::write(
+            dir.path().join(MANIFEST_FILE_NAME),
+            "kept.proto\nold/stale.proto\nold/missing.proto\n",
+        )
+        .unwrap();
+        let keep = HashSet::from([PathBuf::from("kept.proto"), PathBuf::from("new.proto")]);
+
+        assert_eq!(remove_stale(dir.path(), &keep).unwrap(), 1);
+
+        assert!(dir.path().join("kept.proto").exists());
+        assert!(!dir.path().join("old").exists());
+        assert!(dir.path().join("user.proto").exists());
+        assert!(dir.path().join("other/BUILD").exists());
+        assert!(dir.path().join("empty/.keep").exists());
+        assert_eq!(read_manifest(dir.path()).unwrap(), keep);
+    }
+
+    #[test]
+    fn unlisted_files_are_never_stale() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("user.proto"), b"").unwrap();
+
+        assert_eq!(remove_stale(dir.path(), &HashSet::new()).unwrap(), 0);
+
+        assert!(dir.path().join("user.proto").exists());
+    }
+
+    fn write_files(dir: &Path, files: &[(&str, &str)]) {
+        for (file, content) in files {
+            let path = dir.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+    }
+
+    #[test]
+    fn staged_changes_are_only_visible_after_commit() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("proto_src");
+        write_files(
+            &target,
+            &[
+                ("a.proto", "old"),
+                ("b.proto", "old"),
+                (MANIFEST_FILE_NAME, "a.proto\nb.proto\n"),
+            ],
+        );
+
+        let staged = StagedDir::new(&target).unwrap();
+        let keep = HashSet::from([PathBuf::from("a.proto"), PathBuf::from("c/c.proto")]);
+        remove_stale(staged.path(), &keep).unwrap();
+        write_if_changed(&staged.path().join("a.proto"), b"new").unwrap();
+        write_if_changed(&staged.path().join("c/c.proto"), b"new").unwrap();
+        assert_eq!(fs::read(target.join("a.proto")).unwrap(), b"old");
+        assert!(target.join("b.proto").exists());
+
+        staged.commit().unwrap();
+        assert_eq!(fs::read(target.join("a.proto")).unwrap(), b"new");
+        assert!(!target.join("b.proto").exists());
+        assert_eq!(fs::read(target.join("c/c.proto")).unwrap(), b"new");
+        assert_eq!(read_manifest(&target).unwrap(), keep);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn unlisted_files_are_neither_staged_nor_touched() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("proto_src");
+        write_files(
+            &target,
+            &[
+                ("a.proto", "old"),
+                ("user.proto", "user"),
+                ("vendor/BUILD", "user"),
+                (MANIFEST_FILE_NAME, "a.proto\n"),
+            ],
+        );
+
+        let staged = StagedDir::new(&target).unwrap();
+        assert!(staged.path().join("a.proto").exists());
+        assert!(!staged.path().join("user.proto").exists());
+        assert!(!staged.path().join("vendor").exists());
+        remove_stale(staged.path(), &HashSet::new()).unwrap();
+        staged.commit().unwrap();
+
+        assert!(!target.join("a.proto").exists());
+        assert_eq!(fs::read(target.join("user.proto")).unwrap(), b"user");
+        assert_eq!(fs::read(target.join("vendor/BUILD")).unwrap(), b"user");
+    }
+
+    #[test]
+    fn dropped_staging_leaves_the_directory_untouched() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("proto_src");
+        write_files(
+            &target,
+            &[("a.proto", "old"), (MANIFEST_FILE_NAME, "a.proto\n")],
+        );
+
+        let staged = StagedDir::new(&target).unwrap();
+        fs::remove_file(staged.path().join("a.proto")).unwrap();
+        drop(staged);
+
+        assert_eq!(fs::read(target.join("a.proto")).unwrap(), b"old");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn interrupted_commit_is_completed() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("proto_src");
+        let ready = dir.path().join(".proto_src.protofetch-ready");
+        write_files(
+            &target,
+            &[
+                ("a.proto", "new"),
+                ("old.proto", "old"),
+                (MANIFEST_FILE_NAME, "old.proto\n"),
+            ],
+        );
+        write_files(
+            &ready,
+            &[
+                ("b.proto", "new"),
+                (MANIFEST_FILE_NAME, "a.proto\nb.proto\n"),
+            ],
+        );
+
+        let staged = StagedDir::new(&target).unwrap();
+
+        assert!(!ready.exists());
+        assert!(!target.join("old.proto").exists());
+        assert_eq!(fs::read(target.join("b.proto")).unwrap(), b"new");
+        assert_eq!(fs::read(staged.path().join("a.proto")).unwrap(), b"new");
+    }
+}
@@ -6,7 +6,7 @@ use rayon::{ScopeFifo, ThreadPoolBuilder};
 use crate::{
     engine::{
         model::{ResolvedDependency, ResolvedModule, ResolvedRootModule},
-        FetchError,
+        FetchError, Warning,
     },
     git::coord_locks::CoordinateLocks,
     model::protofetch::{
@@ -37,6 +37,7 @@ where
             .collect()
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn resolve_dependencies<'scope, R>(
         scope: &ScopeFifo<'scope>,
         dependencies: Vec<Dependency>,
@@ -45,6 +46,7 @@ where
         seen: &mut BTreeMap<ModuleName, (LockedCoordinate, RevisionSpecification)>,
         modules: &mut Vec<ResolvedModule>,
         locked: &mut Vec<LockedDependency>,
+        warnings: &mut Vec<Warning>,
     ) -> Result<(), FetchError>
     where
         R: ModuleResolver,
@@ -64,17 +66,26 @@ where
                     to_resolve.push(dependency);
                 }
                 Some((existing_coordinate, existing_specification)) => {
-                    if existing_coordinate != &locked_coordinate {
-                        warn!(
-                            "Discarded {} in favor of {} for {}",
-                            dependency.coordinate, existing_coordinate, dependency.name
-                        );
+                    let (discarded, kept) = if existing_coordinate != &locked_coordinate {
+                        (
+                            dependency.coordinate.to_string(),
+                            existing_coordinate.to_string(),
+                        )
                     } else if existing_specification != &dependency.specification {
-                        warn!(
-                            "Discarded {} in favor of {} for {}",
-                            dependency.specification, existing_specification, dependency.name
-                        );
-                    }
+                        (
+                            dependency.specification.to_string(),
+                            existing_specification.to_string(),
+                        )
+                    } else {
+                        continue;
+                    };
+                    let warning = Warning::DiscardedDependency {
+                        name: dependency.name.clone(),
+                        discarded,
+                        kept,
+                    };
+                    warn!("{}", warning);
+                    warnings.push(warning);
                 }
             }
         }
@@ -128,6 +139,7 @@ where
                 seen,
                 modules,
                 locked,
+                warnings,
             )?;
         }
 
@@ -137,6 +149,7 @@ where
     let mut seen = BTreeMap::new();
     let mut modules = Vec::new();
     let mut locked = Vec::new();
+    let mut warnings = Vec::new();
 
     ThreadPoolBuilder::new()
         .num_threads(network_jobs.max(1))
@@ -151,6 +164,7 @@ where
                 &mut seen,
                 &mut modules,
                 &mut locked,
+                &mut warnings,
             )
         })?;
 
@@ -160,6 +174,7 @@ where
         ResolvedRootModule {
             modules,
             dependencies: resolved_dependencies(&descriptor.dependencies),
+            warnings,
         },
         LockFile {
             dependencies: locked,
@@ -267,6 +282,8 @@ mod tests {
                         name: ModuleName::from(*name),
                         description: None,
                         proto_out_dir: None,
+                        output_layout: Default::default(),
+                        imports: Default::default(),
                         dependencies: child_deps.clone(),
                     },
                 },
@@ -292,6 +309,8 @@ mod tests {
             name: ModuleName::from("root"),
             description: None,
             proto_out_dir: None,
+            output_layout: Default::default(),
+            imports: Default::default(),
             dependencies: vec![dep("foo", "1.0.0")],
         };
         let resolver = Arc::new(build_resolver_with(&entries));
@@ -330,6 +349,8 @@ mod tests {
             name: ModuleName::from("root"),
             description: None,
             proto_out_dir: None,
+            output_layout: Default::default(),
+            imports: Default::default(),
             dependencies: vec![dep("foo", "1.0.0"), dep("bar", "1.0.0")],
         };
         let resolver = Arc::new(build_resolver_with(&entries));
@@ -356,6 +377,8 @@ mod tests {
             name: ModuleName::from("root"),
             description: None,
             proto_out_dir: None,
+            output_layout: Default::default(),
+            imports: Default::default(),
             dependencies: vec![dep("foo", "1.0.0"), dep("bar", "1.0.0")],
         };
         let resolver = Arc::new(build_resolver_with(&entries));
@@ -385,6 +408,8 @@ mod tests {
             name: ModuleName::from("root"),
             description: None,
             proto_out_dir: None,
+            output_layout: Default::default(),
+            imports: Default::default(),
             dependencies: vec![dep("path_a_1", "1.0.0"), dep("path_b_1", "1.0.0")],
         };
         let resolver = Arc::new(build_resolver_with(&entries));
@@ -405,6 +430,8 @@ mod tests {
             name: ModuleName::from("root"),
             description: None,
             proto_out_dir: None,
+            output_layout: Default::default(),
+            imports: Default::default(),
             dependencies: vec![dep("foo", "1.0.0")],
         };
         let resolver = Arc::new(build_resolver_with(&entries));
@@ -450,6 +477,8 @@ mod tests {
             name: ModuleName::from("root"),
             description: None,
             proto_out_dir: None,
+            output_layout: Default::default(),
+            imports: Default::default(),
             dependencies: vec![
                 with_policies(dep("shared", "1.0.0"), "/a.proto"),
                 dep("foo", "1.0.0"),
@@ -0,0 +1,90 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+use crate::{model::protofetch::ModuleName, proto::Declarations};
+
+/// A fully-qualified name declared by files of more than one module.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DuplicateSymbol {
+    pub name: String,
+    /// The declaring modules with the declaring files, relative to the output directory.
+    pub declarations: Vec<(ModuleName, PathBuf)>,
+}
+
+impl Display for DuplicateSymbol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} is declared by", self.name)?;
+        for (index, (module, file)) in self.declarations.iter().enumerate() {
+            let separator = if index == 0 { " " } else { ", " };
+            write!(f, "{}{} in {}", separator, module, file.display())?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the top-level fully-qualified names that are declared by files of different modules.
+pub(super) fn find_duplicate_symbols<'a>(
+    files: impl IntoIterator<Item = (&'a ModuleName, &'a Path, &'a Declarations)>,
+) -> Vec<DuplicateSymbol> {
+    let mut declared = BTreeMap::<String, Vec<(ModuleName, PathBuf)>>::new();
+    for (module, file, declarations) in files {
+        for name in declarations.qualified_names() {
+            declared
+                .entry(name)
+                .or_default()
+                .push((module.clone(), file.to_path_buf()));
+        }
+    }
+    declared
+        .into_iter()
+        .filter_map(|(name, mut declarations)| {
+            declarations.sort();
+            let (first, _) = declarations.first()?;
+            declarations
+                .iter()
+                .any(|(module, _)| module != first)
+                .then_some(DuplicateSymbol { name, declarations })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn declarations(package: &str, names: &[&str]) -> Declarations {
+        Declarations {
+            package: Some(package.to_owned()),
+            names: names.iter().map(|name| name.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn only_names_declared_by_different_modules_are_duplicates() {
+        let dep1 = ModuleName::from("dep1");
+        let dep2 = ModuleName::from("dep2");
+        let money = declarations("acme.common.v1", &["Money"]);
+        let money_and_status = declarations("acme.common.v1", &["Money", "Status"]);
+        let status = declarations("acme.common.v1", &["Status"]);
+
+        let duplicates = find_duplicate_symbols([
+            (&dep1, Path::new("acme/money.proto"), &money),
+            (&dep2, Path::new("vendor/money.proto"), &money_and_status),
+            (&dep2, Path::new("vendor/status.proto"), &status),
+        ]);
+
+        assert_eq!(
+            duplicates,
+            vec![DuplicateSymbol {
+                name: "acme.common.v1.Money".to_owned(),
+                declarations: vec![
+                    (dep1, PathBuf::from("acme/money.proto")),
+                    (dep2, PathBuf::from("vendor/money.proto")),
+                ],
+            }]
+        );
+    }
+}
@@ -0,0 +1,115 @@
+use std::{fmt::Display, path::PathBuf};
+
+use crate::{
+    engine::{closure::DanglingImport, copy::UnresolvedImport, symbols::DuplicateSymbol},
+    model::protofetch::ModuleName,
+};
+
+/// A problem found while resolving or copying dependencies that does not fail the command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Warning {
+    /// A dependency was declared again with another coordinate or revision, and the later
+    /// declaration was ignored.
+    DiscardedDependency {
+        name: ModuleName,
+        discarded: String,
+        kept: String,
+    },
+    /// A file was planned to be copied to two different paths, and the later one was ignored.
+    DiscardedTarget {
+        module: ModuleName,
+        coordinate: String,
+        discarded: PathBuf,
+        kept: PathBuf,
+    },
+    /// Two modules provide different files for the same path, and the later one was ignored.
+    DiscardedSource {
+        target: PathBuf,
+        module: ModuleName,
+        coordinate: String,
+        kept_module: ModuleName,
+        kept_coordinate: String,
+    },
+    /// The imports of a file could not be read, so pruning does not follow them.
+    UnparsableImports {
+        module: ModuleName,
+        path: PathBuf,
+        error: String,
+    },
+    UnresolvedImport(UnresolvedImport),
+    DanglingImport(DanglingImport),
+    DuplicateSymbol(DuplicateSymbol),
+}
+
+impl Warning {
+    /// A stable identifier of the kind of the warning, for consumption by other tools.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Warning::DiscardedDependency { .. } => "discarded_dependency",
+            Warning::DiscardedTarget { .. } => "discarded_target",
+            Warning::DiscardedSource { .. } => "discarded_source",
+            Warning::UnparsableImports { .. } => "unparsable_imports",
+            Warning::UnresolvedImport(_) => "unresolved_import",
+            Warning::DanglingImport(_) => "dangling_import",
+            Warning::DuplicateSymbol(_) => "duplicate_symbol",
+        }
+    }
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Warning::DiscardedDependency {
+                name,
+                discarded,
+                kept,
+            } => write!(
+                f,
+                "Discarded {} in favor of {} for {}",
+                discarded, kept, name
+            ),
+            Warning::DiscardedTarget {
+                module,
+                coordinate,
+                discarded,
+                kept,
+            } => write!(
+                f,
+                "Discarded duplicate target {} in favor of {} for {} ({})",
+                discarded.display(),
+                kept.display(),
+                module,
+                coordinate
+            ),
+            Warning::DiscardedSource {
+                target,
+                module,
+                coordinate,
+                kept_module,
+                kept_coordinate,
+            } => write!(
+                f,
+                "Discarded conflicting source {} ({}) in favor of {} ({}) for {}",
+                module,
+                coordinate,
+                kept_module,
+                kept_coordinate,
+                target.display()
+            ),
+            Warning::UnparsableImports {
+                module,
+                path,
+                error,
+            } => write!(
+                f,
+                "Not following the imports of {} in {}: {}",
+                path.display(),
+                module,
+                error
+            ),
+            Warning::UnresolvedImport(unresolved) => write!(f, "Unresolved import {}", unresolved),
+            Warning::DanglingImport(dangling) => write!(f, "Dangling import {}", dangling),
+            Warning::DuplicateSymbol(duplicate) => write!(f, "Duplicate symbol {}", duplicate),
+        }
+    }
+}
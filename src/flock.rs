@@ -1,6 +1,7 @@
 use std::{
-    fs::File,
-    path::Path,
+    fs::{File, OpenOptions},
+    io::{Read, Seek, Write},
+    path::{Path, PathBuf},
     time::{Duration, Instant},
 };
 
@@ -8,20 +9,60 @@ use fs4::fs_std::FileExt;
 use log::debug;
 use thiserror::Error;
 
+pub const DEFAULT_LOCK_TIMEOUT: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LockKind {
+    /// Any number of processes may hold a shared lock at the same time.
+    Shared,
+    /// Excludes all other locks.
+    Exclusive,
+}
+
+/// A cross-process lock on a file, released when dropped.
+///
+/// An exclusive holder writes its process id and command line into the file while it holds the
+/// lock, so that processes waiting for the lock can tell who holds it. Shared holders would
+/// overwrite each other, so they leave the file empty.
 pub struct FileLock {
-    _file: File,
+    file: File,
+    kind: LockKind,
 }
 
 #[derive(Error, Debug)]
-#[error(transparent)]
-pub struct Error(#[from] std::io::Error);
+pub enum Error {
+    #[error(transparent)]
+    IO(#[from] std::io::Error),
+    #[error(
+        "Timed out after {}s waiting for a lock on {}, it is held by {}",
+        timeout.as_secs(),
+        path.display(),
+        holder.as_deref().unwrap_or("an unknown process")
+    )]
+    Timeout {
+        path: PathBuf,
+        timeout: Duration,
+        holder: Option<String>,
+    },
+}
 
 impl FileLock {
-    pub fn new(path: &Path) -> Result<Self, Error> {
-        let file = File::create(path)?;
+    pub fn new(path: &Path, kind: LockKind, timeout: Duration) -> Result<Self, Error> {
+        // Not truncating, the file may describe the current holder
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
         let start = Instant::now();
+        let mut reported = false;
         loop {
-            match file.try_lock_exclusive().or_else(|error| {
+            let result = match kind {
+                LockKind::Shared => FileExt::try_lock_shared(&file),
+                LockKind::Exclusive => FileExt::try_lock_exclusive(&file),
+            };
+            match result.or_else(|error| {
                 if error.raw_os_error() == fs4::lock_contended_error().raw_os_error() {
                     Ok(false)
                 } else {
@@ -29,20 +70,117 @@ impl FileLock {
                 }
             }) {
                 Ok(true) => {
-                    return Ok(Self { _file: file });
+                    if kind == LockKind::Exclusive {
+                        // Best effort, the holder is only used in messages
+                        if let Err(error) = write_holder(&mut file) {
+                            debug!(
+                                "Could not record the lock holder in {}: {}",
+                                path.display(),
+                                error
+                            );
+                        }
+                    }
+                    return Ok(Self { file, kind });
                 }
-                Ok(false) if start.elapsed().as_secs() < 300 => {
-                    debug!("Failed to acquire a lock on {}, retrying", path.display());
-                    std::thread::sleep(Duration::from_secs(1));
+                Ok(false) if start.elapsed() < timeout => {
+                    if !reported {
+                        reported = true;
+                        debug!(
+                            "Waiting for a lock on {} held by {}",
+                            path.display(),
+                            read_holder(&mut file)
+                                .as_deref()
+                                .unwrap_or("another process")
+                        );
+                    }
+                    std::thread::sleep(Duration::from_millis(100));
                 }
                 Ok(false) => {
-                    return Err(Error(std::io::Error::other(format!(
-                        "Failed to acquire a lock on {}",
-                        path.display()
-                    ))))
+                    return Err(Error::Timeout {
+                        path: path.to_path_buf(),
+                        timeout,
+                        holder: read_holder(&mut file),
+                    })
                 }
                 Err(error) => return Err(error.into()),
             }
         }
     }
+
+    pub fn kind(&self) -> LockKind {
+        self.kind
+    }
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        // The lock itself is released when the file is closed
+        if self.kind == LockKind::Exclusive {
+            if let Err(error) = self.file.set_len(0) {
+                debug!("Could not clear the lock holder: {}", error);
+            }
+        }
+    }
+}
+
+fn write_holder(file: &mut File) -> std::io::Result<()> {
+    let args = std::env::args().collect::<Vec<_>>().join(" ");
+    file.set_len(0)?;
+    file.rewind()?;
+    write!(file, "process {} ({})", std::process::id(), args)?;
+    file.flush()
+}
+
+fn read_holder(file: &mut File) -> Option<String> {
+    let mut holder = String::new();
+    file.rewind().ok()?;
+    file.read_to_string(&mut holder).ok()?;
+    let holder = holder.trim();
+    (!holder.is_empty()).then(|| holder.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shared_locks_do_not_exclude_each_other() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(".lock");
+
+        let _a = FileLock::new(&path, LockKind::Shared, Duration::ZERO).unwrap();
+        let _b = FileLock::new(&path, LockKind::Shared, Duration::ZERO).unwrap();
+    }
+
+    #[test]
+    fn released_holders_are_not_named() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(".lock");
+
+        drop(FileLock::new(&path, LockKind::Exclusive, Duration::ZERO).unwrap());
+        let _shared = FileLock::new(&path, LockKind::Shared, Duration::ZERO).unwrap();
+        let error = FileLock::new(&path, LockKind::Exclusive, Duration::ZERO)
+            .err()
+            .expect("lock should be contended");
+
+        let message = error.to_string();
+        assert!(message.contains("an unknown process"), "{message}");
+    }
+
+    #[test]
+    fn timeout_names_the_holder() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(".lock");
+
+        let _held = FileLock::new(&path, LockKind::Exclusive, Duration::ZERO).unwrap();
+        let error = FileLock::new(&path, LockKind::Shared, Duration::ZERO)
+            .err()
+            .expect("lock should be contended");
+
+        let message = error.to_string();
+        assert!(
+            message.contains(&format!("process {}", std::process::id())),
+            "{message}"
+        );
+    }
 }
@@ -1,11 +1,16 @@
 use std::{
+    io::Write,
     path::{Path, PathBuf},
     process::{Command, Stdio},
 };
 
-use log::{debug, info, trace};
+use log::{debug, trace};
 
-use super::{error::GitBackendError, types::GitOid, GitBackend, GitRepository, WorktreeResult};
+use super::{
+    error::GitBackendError,
+    types::{GitOid, RemoteRef},
+    GitBackend, GitRepository,
+};
 
 pub struct CliBackend {
     git_path: String,
@@ -60,11 +65,41 @@ impl CliRepository {
         run_command_str(cmd)
     }
 
-    fn find_worktree(&self, name: &str) -> Result<Option<PathBuf>, GitBackendError> {
+    /// Looks up `commit:path` for every path with a single `git cat-file` process, following
+    /// symlinks within the tree, see [`parse_cat_file_batch`].
+    fn cat_file_batch(
+        &self,
+        commit: &str,
+        paths: &[&str],
+        contents: bool,
+    ) -> Result<Vec<Option<Vec<u8>>>, GitBackendError> {
         let mut cmd = self.git();
-        cmd.args(["worktree", "list", "--porcelain"]);
-        let output = self.run(&mut cmd)?;
-        parse_worktree_list(&output, name)
+        cmd.args([
+            "cat-file",
+            if contents { "--batch" } else { "--batch-check" },
+            "--follow-symlinks",
+        ]);
+        cmd.stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        trace!("Running: {:?}", cmd);
+        let mut child = cmd.spawn()?;
+        let mut stdin = child.stdin.take().expect("stdin of cat-file is piped");
+        let input = paths
+            .iter()
+            .map(|path| format!("{}:{}\n", commit, path))
+            .collect::<String>();
+        // Written from another thread, so that git is not blocked on a full stdout pipe
+        let writer = std::thread::spawn(move || stdin.write_all(input.as_bytes()));
+        let output = child.wait_with_output()?;
+        let written = writer.join().expect("writing to cat-file panicked");
+        if !output.status.success() {
+            return Err(GitBackendError::CommandFailed(
+                String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            ));
+        }
+        written?;
+        parse_cat_file_batch(&output.stdout, paths.len(), contents)
     }
 }
 
@@ -99,48 +134,96 @@ fn should_retry_without_optional_tags(refspecs: &[String], message: &str) -> boo
         && message.contains("couldn't find remote ref refs/tags/")
 }
 
-#[cfg(unix)]
-fn bytes_to_path(bytes: &[u8]) -> Result<PathBuf, GitBackendError> {
-    use std::ffi::OsStr;
-    use std::os::unix::ffi::OsStrExt;
-    Ok(PathBuf::from(OsStr::from_bytes(bytes)))
+fn parse_ls_remote(output: &str) -> Result<Vec<RemoteRef>, GitBackendError> {
+    output
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| match line.split_once('\t') {
+            Some((oid, name)) => Ok(RemoteRef {
+                name: name.to_owned(),
+                oid: GitOid::from_hex(oid),
+            }),
+            None => Err(GitBackendError::GitError(format!(
+                "Unexpected ls-remote output line: {}",
+                line
+            ))),
+        })
+        .collect()
 }
 
-#[cfg(not(unix))]
-fn bytes_to_path(bytes: &[u8]) -> Result<PathBuf, GitBackendError> {
-    Ok(PathBuf::from(std::str::from_utf8(bytes)?))
+/// An entry of `git ls-tree` that may contain a proto file.
+#[derive(Debug, PartialEq, Eq)]
+struct TreeEntry {
+    path: String,
+    symlink: bool,
 }
 
-fn parse_worktree_list(output: &[u8], name: &str) -> Result<Option<PathBuf>, GitBackendError> {
-    let lines: Vec<&[u8]> = output.split(|&b| b == b'\n').collect();
-    for block in lines.split(|line| line.is_empty()) {
-        let mut wt_path_bytes: Option<&[u8]> = None;
-        let mut wt_branch: Option<&[u8]> = None;
-        let mut wt_head: Option<&[u8]> = None;
-        let mut detached = false;
-
-        for &line in block {
-            if let Some(path) = line.strip_prefix(b"worktree ") {
-                wt_path_bytes = Some(path);
-            } else if let Some(branch) = line.strip_prefix(b"branch refs/heads/") {
-                wt_branch = Some(branch);
-            } else if let Some(head) = line.strip_prefix(b"HEAD ") {
-                wt_head = Some(head);
-            } else if line == b"detached" {
-                detached = true;
-            }
-        }
-
-        if let Some(path_bytes) = wt_path_bytes {
-            let branch_matches = wt_branch.map(|b| b == name.as_bytes()).unwrap_or(false);
-            let head_matches = detached && wt_head.map(|h| h == name.as_bytes()).unwrap_or(false);
+fn parse_ls_tree(output: &[u8]) -> Result<Vec<TreeEntry>, GitBackendError> {
+    let mut entries = Vec::new();
+    for entry in output.split(|&b| b == 0).filter(|entry| !entry.is_empty()) {
+        let entry = std::str::from_utf8(entry)?;
+        let Some((info, path)) = entry.split_once('\t') else {
+            return Err(GitBackendError::GitError(format!(
+                "Unexpected ls-tree output entry: {}",
+                entry
+            )));
+        };
+        // Submodules are skipped, their commits are not fetched
+        let mut info = info.split(' ');
+        let symlink = match (info.next(), info.next()) {
+            (Some("100644" | "100755"), Some("blob")) => false,
+            (Some("120000"), Some("blob")) => true,
+            _ => continue,
+        };
+        entries.push(TreeEntry {
+            path: path.to_owned(),
+            symlink,
+        });
+    }
+    Ok(entries)
+}
 
-            if branch_matches || head_matches {
-                return Ok(Some(bytes_to_path(path_bytes)?));
+/// Parses the output of `git cat-file --batch --follow-symlinks`, or of `--batch-check` if not
+/// `contents`, for `count` requested objects. Objects that are missing or that do not resolve to
+/// a blob within the tree are `None`, and blobs are empty if not `contents`.
+fn parse_cat_file_batch(
+    output: &[u8],
+    count: usize,
+    contents: bool,
+) -> Result<Vec<Option<Vec<u8>>>, GitBackendError> {
+    let unexpected = || GitBackendError::GitError("Unexpected cat-file output".to_owned());
+    let mut rest = output;
+    let mut objects = Vec::with_capacity(count);
+    for _ in 0..count {
+        let newline = rest
+            .iter()
+            .position(|&b| b == b'\n')
+            .ok_or_else(unexpected)?;
+        let header = std::str::from_utf8(&rest[..newline])?;
+        rest = &rest[newline + 1..];
+        if header.ends_with(" missing") || header.ends_with(" ambiguous") {
+            objects.push(None);
+            continue;
+        }
+        // Symlinks that leave the tree or cannot be resolved are followed by their target
+        let fields = header.split(' ').collect::<Vec<_>>();
+        let (blob, size, has_content) = match fields.as_slice() {
+            [_, kind, size] => (*kind == "blob", *size, contents),
+            ["symlink" | "dangling" | "loop" | "notdir", size] => (false, *size, true),
+            _ => return Err(unexpected()),
+        };
+        let mut content = Vec::new();
+        if has_content {
+            let size = size.parse::<usize>().map_err(|_| unexpected())?;
+            if rest.len() <= size || rest[size] != b'\n' {
+                return Err(unexpected());
             }
+            content = rest[..size].to_vec();
+            rest = &rest[size + 1..];
         }
+        objects.push(blob.then_some(content));
     }
-    Ok(None)
+    Ok(objects)
 }
 
 impl GitRepository for CliRepository {
@@ -210,6 +293,15 @@ impl GitRepository for CliRepository {
         Ok(())
     }
 
+    fn list_remote_refs(&self, remote_name: &str) -> Result<Vec<RemoteRef>, GitBackendError> {
+        debug!("Listing remote refs of {}", self.repo_path.display());
+        let mut cmd = self.git();
+        cmd.arg("ls-remote");
+        cmd.arg(remote_name);
+        let output = self.run_str(&mut cmd)?;
+        parse_ls_remote(&output)
+    }
+
     fn commit_exists(&self, oid: &str) -> Result<bool, GitBackendError> {
         if oid.is_empty() || !oid.chars().all(|c| c.is_ascii_hexdigit()) {
             return Err(GitBackendError::InvalidRef(format!(
@@ -284,53 +376,63 @@ impl GitRepository for CliRepository {
         }
     }
 
-    fn create_worktree(
+    fn read_blobs(
         &self,
-        name: &str,
-        worktree_path: &Path,
         commit: &str,
-    ) -> Result<WorktreeResult, GitBackendError> {
-        if let Some(existing_path) = self.find_worktree(name)? {
-            let canonical = existing_path.canonicalize().map_err(|e| {
-                GitBackendError::IO(std::io::Error::new(
-                    e.kind(),
-                    format!(
-                        "Error while canonicalizing path {}: {}",
-                        existing_path.display(),
-                        e
-                    ),
-                ))
-            })?;
-            return Ok(WorktreeResult::Existing(
-                canonical,
-                Box::new(CliRepository {
-                    repo_path: existing_path,
-                    git_path: self.git_path.clone(),
-                }),
-            ));
-        }
+        blob_paths: &[String],
+    ) -> Result<Vec<Option<Vec<u8>>>, GitBackendError> {
+        let commit_spec = format!("{}^{{commit}}", commit);
+        let mut cmd = self.git();
+        cmd.args(["cat-file", "-e", &commit_spec]);
+        self.run(&mut cmd)?;
 
-        info!(
-            "Creating new worktree {} at {}",
-            name,
-            worktree_path.display()
-        );
+        let paths = blob_paths.iter().map(String::as_str).collect::<Vec<_>>();
+        self.cat_file_batch(commit, &paths, true)
+    }
 
+    fn list_tree(&self, commit: &str) -> Result<Vec<String>, GitBackendError> {
+        let commit_spec = format!("{}^{{commit}}", commit);
         let mut cmd = self.git();
-        cmd.args(["worktree", "add", "--detach"]);
-        cmd.arg(worktree_path);
-        cmd.arg(commit);
+        cmd.args(["ls-tree", "-r", "-z", "--full-tree", &commit_spec]);
+        let entries = parse_ls_tree(&self.run(&mut cmd)?)?;
+
+        // Symlinks are listed if they resolve to a file within the tree
+        let symlinks = entries
+            .iter()
+            .filter(|entry| entry.symlink)
+            .map(|entry| entry.path.as_str())
+            .collect::<Vec<_>>();
+        let mut resolved = if symlinks.is_empty() {
+            Vec::new()
+        } else {
+            self.cat_file_batch(commit, &symlinks, false)?
+        }
+        .into_iter();
+        Ok(entries
+            .into_iter()
+            .filter(|entry| !entry.symlink || resolved.next().flatten().is_some())
+            .map(|entry| entry.path)
+            .collect())
+    }
+
+    fn prune_worktrees(&self) -> Result<(), GitBackendError> {
+        let mut cmd = self.git();
+        cmd.args(["worktree", "prune"]);
         self.run(&mut cmd)?;
+        Ok(())
+    }
 
-        Ok(WorktreeResult::Created(Box::new(CliRepository {
-            repo_path: worktree_path.to_path_buf(),
-            git_path: self.git_path.clone(),
-        })))
+    fn gc(&self) -> Result<(), GitBackendError> {
+        debug!("Running git gc in {}", self.repo_path.display());
+        let mut cmd = self.git();
+        cmd.args(["gc", "--quiet"]);
+        self.run(&mut cmd)?;
+        Ok(())
     }
 
-    fn reset(&self, commit: &str) -> Result<(), GitBackendError> {
+    fn verify_objects(&self) -> Result<(), GitBackendError> {
         let mut cmd = self.git();
-        cmd.args(["reset", "--hard", commit]);
+        cmd.args(["fsck", "--no-progress", "--no-dangling"]);
         self.run(&mut cmd)?;
         Ok(())
     }
@@ -420,72 +522,127 @@ mod tests {
         (tempdir, repo, commit)
     }
 
-    fn worktree_block(
-        path: &[u8],
-        branch: Option<&[u8]>,
-        head: Option<&[u8]>,
-        detached: bool,
-    ) -> Vec<u8> {
-        let mut block = Vec::new();
-        block.extend_from_slice(b"worktree ");
-        block.extend_from_slice(path);
-        block.push(b'\n');
-        if let Some(h) = head {
-            block.extend_from_slice(b"HEAD ");
-            block.extend_from_slice(h);
-            block.push(b'\n');
-        }
-        if let Some(b) = branch {
-            block.extend_from_slice(b"branch refs/heads/");
-            block.extend_from_slice(b);
-            block.push(b'\n');
-        }
-        if detached {
-            block.extend_from_slice(b"detached\n");
-        }
-        block
+    #[test]
+    fn parse_ls_remote_keeps_peeled_tags() {
+        let output = "1111111111111111111111111111111111111111\tHEAD\n\
+                      1111111111111111111111111111111111111111\trefs/heads/main\n\
+                      2222222222222222222222222222222222222222\trefs/tags/v1\n\
+                      3333333333333333333333333333333333333333\trefs/tags/v1^{}\n";
+
+        let refs = parse_ls_remote(output).unwrap();
+
+        assert_eq!(
+            refs.iter().map(|r| r.name.as_str()).collect::<Vec<_>>(),
+            vec!["HEAD", "refs/heads/main", "refs/tags/v1", "refs/tags/v1^{}"]
+        );
+        assert_eq!(
+            refs[3].oid,
+            GitOid::from_hex("3333333333333333333333333333333333333333")
+        );
     }
 
-    fn join_blocks(blocks: &[Vec<u8>]) -> Vec<u8> {
-        blocks.join(b"\n\n".as_ref())
+    #[test]
+    fn list_remote_refs_lists_branches_of_origin() {
+        let (_origin_dir, origin, commit) = repo_with_commit("README.md");
+        let tempdir = tempfile::tempdir().unwrap();
+        let backend = CliBackend::new("git".to_owned());
+        let repo = backend.init_bare(tempdir.path()).unwrap();
+        repo.remote_add("origin", &origin.repo_path.to_string_lossy())
+            .unwrap();
+
+        let refs = repo.list_remote_refs("origin").unwrap();
+
+        assert!(refs
+            .iter()
+            .any(|r| r.name == "HEAD" && r.oid.as_str() == commit));
     }
 
     #[test]
-    fn find_worktree_by_branch_name() {
-        let output = join_blocks(&[
-            worktree_block(b"/repos/main", Some(b"main"), Some(b"abc123"), false),
-            worktree_block(
-                b"/repos/feature",
-                Some(b"feature-xyz"),
-                Some(b"def456"),
-                false,
-            ),
-        ]);
-        let result = parse_worktree_list(&output, "feature-xyz").unwrap();
-        assert_eq!(result, Some(PathBuf::from("/repos/feature")));
+    fn prune_worktrees_forgets_deleted_worktrees() {
+        let (tempdir, repo, commit) = repo_with_commit("proto/a.proto");
+        let worktree_path = tempdir.path().join("worktree");
+        run_git(
+            tempdir.path(),
+            &["worktree", "add", "--detach", "worktree", &commit],
+        );
+
+        fs::remove_dir_all(&worktree_path).unwrap();
+        repo.prune_worktrees().unwrap();
+
+        let worktrees = run_git(tempdir.path(), &["worktree", "list", "--porcelain"]);
+        assert!(!worktrees.contains("worktree/"), "{worktrees}");
     }
 
     #[test]
-    fn find_worktree_by_detached_head() {
-        let hash = "abc123def456abc123def456abc123def456abc123";
-        let output = join_blocks(&[
-            worktree_block(b"/repos/main", Some(b"main"), Some(b"deadbeef"), false),
-            worktree_block(b"/repos/detached", None, Some(hash.as_bytes()), true),
-        ]);
-        let result = parse_worktree_list(&output, hash).unwrap();
-        assert_eq!(result, Some(PathBuf::from("/repos/detached")));
+    fn list_tree_lists_files_in_subdirectories() {
+        let (_tempdir, repo, commit) = repo_with_commit("proto/nested/a.proto");
+
+        let paths = repo.list_tree(&commit).unwrap();
+
+        assert_eq!(paths, vec!["proto/nested/a.proto"]);
+    }
+
+    #[test]
+    fn parse_ls_tree_skips_submodules() {
+        let output = b"100644 blob 1111111111111111111111111111111111111111\ta.proto\0\
+                       120000 blob 2222222222222222222222222222222222222222\tlink.proto\0\
+                       160000 commit 3333333333333333333333333333333333333333\tsubmodule\0\
+                       100755 blob 4444444444444444444444444444444444444444\tdir/b c.proto\0";
+
+        let entries = parse_ls_tree(output).unwrap();
+
+        assert_eq!(
+            entries,
+            vec![
+                TreeEntry {
+                    path: "a.proto".to_owned(),
+                    symlink: false
+                },
+                TreeEntry {
+                    path: "link.proto".to_owned(),
+                    symlink: true
+                },
+                TreeEntry {
+                    path: "dir/b c.proto".to_owned(),
+                    symlink: false
+                },
+            ]
+        );
     }
 
     #[test]
-    fn find_worktree_returns_none_when_not_found() {
-        let output = join_blocks(&[worktree_block(
-            b"/repos/main",
-            Some(b"main"),
-            Some(b"abc123"),
-            false,
-        )]);
-        let result = parse_worktree_list(&output, "nonexistent").unwrap();
-        assert_eq!(result, None);
+    #[cfg(unix)]
+    fn symlinks_are_resolved_within_the_tree() {
+        let (tempdir, repo, _commit) = repo_with_commit("proto/a.proto");
+        std::os::unix::fs::symlink("proto/a.proto", tempdir.path().join("link.proto")).unwrap();
+        std::os::unix::fs::symlink("../../outside.proto", tempdir.path().join("outside.proto"))
+            .unwrap();
+        std::os::unix::fs::symlink("missing.proto", tempdir.path().join("dangling.proto")).unwrap();
+        run_git(tempdir.path(), &["add", "."]);
+        run_git(
+            tempdir.path(),
+            &[
+                "-c",
+                "user.name=Protofetch Test",
+                "-c",
+                "user.email=protofetch@example.com",
+                "commit",
+                "-m",
+                "symlinks",
+            ],
+        );
+        let commit = run_git(tempdir.path(), &["rev-parse", "HEAD"]);
+
+        let paths = repo.list_tree(&commit).unwrap();
+        let blobs = repo
+            .read_blobs(
+                &commit,
+                &["link.proto".to_owned(), "dangling.proto".to_owned()],
+            )
+            .unwrap();
+
+        assert_eq!(paths, vec!["link.proto", "proto/a.proto"]);
+        assert_eq!(blobs, vec![Some(b"content".to_vec()), None]);
     }
 
     #[test]
@@ -511,22 +668,6 @@ mod tests {
         ));
     }
 
-    #[cfg(unix)]
-    #[test]
-    fn find_worktree_handles_non_utf8_path() {
-        let non_utf8_path: &[u8] = b"/repos/caf\xe9dir";
-        let output = join_blocks(&[worktree_block(
-            non_utf8_path,
-            Some(b"my-branch"),
-            Some(b"abc123"),
-            false,
-        )]);
-        let result = parse_worktree_list(&output, "my-branch").unwrap();
-        use std::os::unix::ffi::OsStrExt;
-        let expected = PathBuf::from(std::ffi::OsStr::from_bytes(non_utf8_path));
-        assert_eq!(result, Some(expected));
-    }
-
     #[test]
     fn remote_get_url_returns_none_for_missing_remote() {
         let tempdir = tempfile::tempdir().unwrap();
@@ -1,16 +1,24 @@
 use std::path::{Path, PathBuf};
 
 use git2::{
-    cert::Cert, AutotagOption, CertificateCheckStatus, Config, Cred, CredentialType, FetchOptions,
-    Oid, RemoteCallbacks, Repository, ResetType, WorktreeAddOptions,
+    cert::Cert, AutotagOption, Blob, CertificateCheckStatus, Config, Cred, CredentialType,
+    Direction, FetchOptions, ObjectType, Oid, RemoteCallbacks, Repository, Tree, TreeWalkMode,
+    TreeWalkResult,
 };
-use log::{debug, info, trace};
+use log::{debug, trace};
 use ssh_key::{known_hosts::HostPatterns, KnownHosts};
 
-use super::{error::GitBackendError, types::GitOid, GitBackend, GitRepository, WorktreeResult};
+use super::{
+    error::GitBackendError,
+    types::{GitOid, RemoteRef},
+    GitBackend, GitRepository,
+};
 
 const GLOBAL_KNOWN_HOSTS: &str = "/etc/ssh/ssh_known_hosts";
 
+/// How many symlinks are followed at most to resolve a path, as in git.
+const MAX_SYMLINKS: usize = 40;
+
 pub struct Libgit2Backend;
 
 impl Libgit2Backend {
@@ -25,7 +33,7 @@ pub struct Libgit2Repository {
 }
 
 impl Libgit2Repository {
-    fn fetch_options(&self) -> FetchOptions<'_> {
+    fn remote_callbacks(&self) -> RemoteCallbacks<'_> {
         let mut callbacks = RemoteCallbacks::new();
 
         let mut tried_username = false;
@@ -61,9 +69,13 @@ impl Libgit2Repository {
 
         callbacks.certificate_check(check_certificate);
 
+        callbacks
+    }
+
+    fn fetch_options(&self) -> FetchOptions<'_> {
         let mut fetch_options = FetchOptions::new();
         fetch_options
-            .remote_callbacks(callbacks)
+            .remote_callbacks(self.remote_callbacks())
             .download_tags(AutotagOption::None);
 
         fetch_options
@@ -104,6 +116,23 @@ impl GitRepository for Libgit2Repository {
         Ok(())
     }
 
+    fn list_remote_refs(&self, remote_name: &str) -> Result<Vec<RemoteRef>, GitBackendError> {
+        let repo = Repository::open(&self.repo_path)?;
+        let mut remote = repo.find_remote(remote_name)?;
+        debug!("Listing remote refs of {}", self.repo_path.display());
+        let connection =
+            remote.connect_auth(Direction::Fetch, Some(self.remote_callbacks()), None)?;
+        let refs = connection
+            .list()?
+            .iter()
+            .map(|head| RemoteRef {
+                name: head.name().to_owned(),
+                oid: GitOid::from(head.oid()),
+            })
+            .collect();
+        Ok(refs)
+    }
+
     fn commit_exists(&self, oid: &str) -> Result<bool, GitBackendError> {
         let repo = Repository::open(&self.repo_path)?;
         let oid = Oid::from_str(oid).map_err(|e| GitBackendError::InvalidRef(e.to_string()))?;
@@ -143,6 +172,19 @@ impl GitRepository for Libgit2Repository {
         }
     }
 
+    fn read_blobs(
+        &self,
+        commit: &str,
+        blob_paths: &[String],
+    ) -> Result<Vec<Option<Vec<u8>>>, GitBackendError> {
+        let repo = Repository::open(&self.repo_path)?;
+        let tree = repo.revparse_single(commit)?.peel_to_commit()?.tree()?;
+        blob_paths
+            .iter()
+            .map(|path| Ok(resolve_blob(&repo, &tree, path)?.map(|blob| blob.content().to_vec())))
+            .collect()
+    }
+
     fn is_ancestor(&self, ancestor: &GitOid, descendant: &GitOid) -> Result<bool, GitBackendError> {
         let repo = Repository::open(&self.repo_path)?;
         let a = Oid::from_str(ancestor.as_str())
@@ -156,65 +198,77 @@ impl GitRepository for Libgit2Repository {
         }
     }
 
-    fn create_worktree(
-        &self,
-        name: &str,
-        worktree_path: &Path,
-        commit: &str,
-    ) -> Result<WorktreeResult, GitBackendError> {
+    fn list_tree(&self, commit: &str) -> Result<Vec<String>, GitBackendError> {
         let repo = Repository::open(&self.repo_path)?;
-
-        match repo.find_worktree(name) {
-            Ok(worktree) => {
-                let canonical_existing = worktree.path().canonicalize().map_err(|e| {
-                    GitBackendError::IO(std::io::Error::new(
-                        e.kind(),
-                        format!(
-                            "Error while canonicalizing path {}: {}",
-                            worktree.path().display(),
-                            e
-                        ),
-                    ))
-                })?;
-                Ok(WorktreeResult::Existing(
-                    canonical_existing,
-                    Box::new(Libgit2Repository {
-                        repo_path: worktree.path().to_path_buf(),
-                        git_config: Config::open_default()?,
-                    }),
-                ))
+        let tree = repo.revparse_single(commit)?.peel_to_commit()?.tree()?;
+        let mut entries = Vec::new();
+        let mut error = None;
+        tree.walk(TreeWalkMode::PreOrder, |root, entry| {
+            // Submodules are skipped, their commits are not fetched
+            if entry.kind() == Some(ObjectType::Blob)
+                && matches!(entry.filemode(), 0o100644 | 0o100755 | 0o120000)
+            {
+                match entry.name() {
+                    Some(name) => entries.push((format!("{root}{name}"), entry.filemode())),
+                    None => {
+                        error = Some(GitBackendError::GitError(format!(
+                            "Non UTF-8 file name in {root} of commit {commit}"
+                        )));
+                        return TreeWalkResult::Abort;
+                    }
+                }
             }
-            Err(_) => {
-                info!(
-                    "Creating new worktree {} at {}",
-                    name,
-                    worktree_path.display()
-                );
-
-                // We need to create a branch-like reference to be able to create a worktree
-                let reference = repo.reference(
-                    &format!("refs/heads/{}", commit),
-                    repo.revparse_single(commit)?.id(),
-                    true,
-                    "",
-                )?;
-
-                let mut options = WorktreeAddOptions::new();
-                options.reference(Some(&reference));
-                repo.worktree(name, worktree_path, Some(&options))?;
-
-                Ok(WorktreeResult::Created(Box::new(Libgit2Repository {
-                    repo_path: worktree_path.to_path_buf(),
-                    git_config: Config::open_default()?,
-                })))
+            TreeWalkResult::Ok
+        })?;
+        if let Some(error) = error {
+            return Err(error);
+        }
+        // Symlinks are listed if they resolve to a file within the tree
+        let mut paths = Vec::with_capacity(entries.len());
+        for (path, filemode) in entries {
+            if filemode != 0o120000 || resolve_blob(&repo, &tree, &path)?.is_some() {
+                paths.push(path);
             }
         }
+        Ok(paths)
     }
 
-    fn reset(&self, commit: &str) -> Result<(), GitBackendError> {
+    fn prune_worktrees(&self) -> Result<(), GitBackendError> {
         let repo = Repository::open(&self.repo_path)?;
-        let obj = repo.revparse_single(commit)?;
-        repo.reset(&obj, ResetType::Hard, None)?;
+        for name in repo.worktrees()?.iter().flatten() {
+            let worktree = repo.find_worktree(name)?;
+            if worktree.validate().is_err() {
+                debug!("Pruning worktree {} in {}", name, self.repo_path.display());
+                worktree.prune(None)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn gc(&self) -> Result<(), GitBackendError> {
+        // libgit2 has no equivalent of `git gc`
+        debug!(
+            "Skipping garbage collection of {}, not supported by libgit2",
+            self.repo_path.display()
+        );
+        Ok(())
+    }
+
+    fn verify_objects(&self) -> Result<(), GitBackendError> {
+        let repo = Repository::open(&self.repo_path)?;
+        let odb = repo.odb()?;
+        let mut oids = Vec::new();
+        odb.foreach(|oid| {
+            oids.push(*oid);
+            true
+        })?;
+        for oid in oids {
+            // Objects are hashed while being read, so this catches corrupted contents too
+            odb.read(oid)?;
+        }
+        for reference in repo.references()? {
+            reference?.peel(git2::ObjectType::Any)?;
+        }
         Ok(())
     }
 }
@@ -239,6 +293,54 @@ impl GitBackend for Libgit2Backend {
     }
 }
 
+/// Looks up the blob at `path` in `tree`, following symlinks as long as they stay within the tree.
+fn resolve_blob<'r>(
+    repo: &'r Repository,
+    tree: &Tree<'r>,
+    path: &str,
+) -> Result<Option<Blob<'r>>, GitBackendError> {
+    let mut path = path.to_owned();
+    for _ in 0..=MAX_SYMLINKS {
+        let entry = match tree.get_path(Path::new(&path)) {
+            Ok(entry) => entry,
+            Err(e) if e.code() == git2::ErrorCode::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        if entry.kind() != Some(ObjectType::Blob) {
+            return Ok(None);
+        }
+        let blob = repo.find_blob(entry.id())?;
+        if entry.filemode() != 0o120000 {
+            return Ok(Some(blob));
+        }
+        match symlink_target(&path, std::str::from_utf8(blob.content())?) {
+            Some(target) => path = target,
+            None => return Ok(None),
+        }
+    }
+    Ok(None)
+}
+
+/// The path in the tree a symlink at `link` pointing to `target` refers to, unless it leaves the
+/// tree.
+fn symlink_target(link: &str, target: &str) -> Option<String> {
+    if target.starts_with('/') {
+        return None;
+    }
+    let mut components = link.split('/').collect::<Vec<_>>();
+    components.pop();
+    for component in target.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                components.pop()?;
+            }
+            component => components.push(component),
+        }
+    }
+    Some(components.join("/"))
+}
+
 fn check_certificate(
     certificate: &Cert<'_>,
     host: &str,
@@ -323,3 +425,22 @@ impl<T> Git2ResultCompat<T> for Option<T> {
         self
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn symlink_target_stays_within_the_tree() {
+        assert_eq!(
+            symlink_target("proto/link.proto", "../common/a.proto").as_deref(),
+            Some("common/a.proto")
+        );
+        assert_eq!(
+            symlink_target("link.proto", "./a.proto").as_deref(),
+            Some("a.proto")
+        );
+        assert_eq!(symlink_target("link.proto", "../a.proto"), None);
+        assert_eq!(symlink_target("link.proto", "/etc/a.proto"), None);
+    }
+}
@@ -8,16 +8,17 @@ pub mod cli;
 
 use std::{
     panic::{RefUnwindSafe, UnwindSafe},
-    path::{Path, PathBuf},
+    path::Path,
     str::FromStr,
 };
 
 use anyhow::bail;
 use log::info;
+use schemars::JsonSchema;
 use serde::Deserialize;
 
 use error::GitBackendError;
-use types::GitOid;
+use types::{GitOid, RemoteRef};
 
 /// Per-repository operations. Obtained from a [`GitBackend`].
 /// The implementing type stores the repository path internally.
@@ -26,19 +27,29 @@ pub trait GitRepository {
     fn remote_get_url(&self, name: &str) -> Result<Option<String>, GitBackendError>;
     fn remote_set_url(&self, name: &str, url: &str) -> Result<(), GitBackendError>;
     fn fetch(&self, remote_name: &str, refspecs: &[String]) -> Result<(), GitBackendError>;
+    /// List the references advertised by the remote without fetching any objects.
+    fn list_remote_refs(&self, remote_name: &str) -> Result<Vec<RemoteRef>, GitBackendError>;
     fn commit_exists(&self, oid: &str) -> Result<bool, GitBackendError>;
     fn revparse_commit(&self, spec: &str) -> Result<GitOid, GitBackendError>;
     fn read_blob(&self, commit: &str, blob_path: &str) -> Result<Option<Vec<u8>>, GitBackendError>;
-    /// Check if `ancestor` is an ancestor of `descendant`.
-    fn is_ancestor(&self, ancestor: &GitOid, descendant: &GitOid) -> Result<bool, GitBackendError>;
-    fn create_worktree(
+    /// Read several blobs from the tree of a commit at once, following symlinks within the tree.
+    /// Paths that do not resolve to a blob are `None`.
+    fn read_blobs(
         &self,
-        name: &str,
-        worktree_path: &Path,
         commit: &str,
-    ) -> Result<WorktreeResult, GitBackendError>;
-    /// Hard-reset the repository to a specific commit.
-    fn reset(&self, commit: &str) -> Result<(), GitBackendError>;
+        blob_paths: &[String],
+    ) -> Result<Vec<Option<Vec<u8>>>, GitBackendError>;
+    /// Check if `ancestor` is an ancestor of `descendant`.
+    fn is_ancestor(&self, ancestor: &GitOid, descendant: &GitOid) -> Result<bool, GitBackendError>;
+    /// List the paths of all regular files in the tree of a commit, and of the symlinks that
+    /// resolve to one within the tree, relative to its root.
+    fn list_tree(&self, commit: &str) -> Result<Vec<String>, GitBackendError>;
+    /// Remove administrative data of worktrees whose directories no longer exist.
+    fn prune_worktrees(&self) -> Result<(), GitBackendError>;
+    /// Compact the object database. Backends that cannot do this may leave it untouched.
+    fn gc(&self) -> Result<(), GitBackendError>;
+    /// Read every object and reference to make sure the repository is not corrupted.
+    fn verify_objects(&self) -> Result<(), GitBackendError>;
 }
 
 /// Factory for opening or creating git repositories.
@@ -51,25 +62,8 @@ pub trait GitBackend: Send + Sync + UnwindSafe + RefUnwindSafe {
     fn open(&self, path: &Path) -> Result<Box<dyn GitRepository>, GitBackendError>;
 }
 
-/// Result of a worktree creation attempt.
-pub enum WorktreeResult {
-    /// A new worktree was created. The inner value is a repository handle for the worktree.
-    Created(Box<dyn GitRepository>),
-    /// An existing worktree was found at the given canonical path.
-    Existing(PathBuf, Box<dyn GitRepository>),
-}
-
-impl std::fmt::Debug for WorktreeResult {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            WorktreeResult::Created(_) => write!(f, "Created(..)"),
-            WorktreeResult::Existing(p, _) => write!(f, "Existing({:?}, ..)", p),
-        }
-    }
-}
-
 /// The type of git backend to use.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Default)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Default, JsonSchema)]
 pub enum GitBackendType {
     #[default]
     #[serde(rename = "libgit2")]
@@ -21,3 +21,13 @@ impl fmt::Display for GitOid {
         f.write_str(&self.hex)
     }
 }
+
+/// A reference advertised by a remote, as listed by `git ls-remote`.
+///
+/// Annotated tags are advertised twice: once under their own name pointing at the
+/// tag object, and once with a `^{}` suffix pointing at the peeled commit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteRef {
+    pub name: String,
+    pub oid: GitOid,
+}
@@ -0,0 +1,354 @@
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use log::{debug, info, warn};
+
+use crate::flock::LockKind;
+
+use super::{CacheError, ProtofetchGitCache};
+
+/// What a cache garbage collection run removed.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct GcReport {
+    pub removed_worktrees: usize,
+    pub removed_repositories: usize,
+    pub reclaimed_bytes: u64,
+}
+
+impl ProtofetchGitCache {
+    /// Removes repositories and worktrees that were not used within `max_age`, then prunes and
+    /// compacts the remaining repositories. Quarantined repositories are removed as well.
+    ///
+    /// If `referenced` is given, repositories whose coordinate path (see
+    /// [`crate::model::protofetch::Coordinate::to_path`]) is not in the set are removed
+    /// regardless of their age. Repositories are removed together with all of their worktrees.
+    pub fn gc(
+        &self,
+        max_age: Duration,
+        referenced: Option<&BTreeSet<PathBuf>>,
+    ) -> Result<GcReport, CacheError> {
+        self.lock_cache(LockKind::Exclusive)?;
+        let repositories_path = self.repositories_path();
+        let worktrees_path = self.worktrees_path();
+        let mut report = GcReport::default();
+
+        let now = SystemTime::now();
+        let mut repositories = Vec::new();
+        for repository in find_dirs(&repositories_path, is_bare_repository)? {
+            let coordinate = repository
+                .strip_prefix(&repositories_path)
+                .expect("repository is inside the cache");
+            let idle = repository_last_used(&repository)
+                .ok()
+                .and_then(|last_used| now.duration_since(last_used).ok())
+                .unwrap_or_default();
+            if referenced.is_some_and(|referenced| !referenced.contains(coordinate)) {
+                info!("Removing unreferenced repository {}", coordinate.display());
+            } else if idle > max_age {
+                info!(
+                    "Removing repository {} unused for {}s",
+                    coordinate.display(),
+                    idle.as_secs()
+                );
+            } else {
+                repositories.push(repository);
+                continue;
+            }
+            let worktrees = worktrees_path.join(coordinate);
+            report.reclaimed_bytes += remove_dir(&repository, &repositories_path)?;
+            report.reclaimed_bytes += remove_dir(&worktrees, &worktrees_path)?;
+            report.removed_repositories += 1;
+        }
+
+        for worktree in find_dirs(&worktrees_path, is_worktree)? {
+            let idle = worktree_last_used(&worktree)
+                .ok()
+                .and_then(|last_used| now.duration_since(last_used).ok())
+                .unwrap_or_default();
+            if idle > max_age {
+                debug!(
+                    "Removing worktree {} unused for {}s",
+                    worktree.display(),
+                    idle.as_secs()
+                );
+                report.reclaimed_bytes += remove_dir(&worktree, &worktrees_path)?;
+                report.removed_worktrees += 1;
+            }
+        }
+
+        for repository in repositories {
+            let before = dir_size(&repository)?;
+            let repo = match self.backend.open(&repository) {
+                Ok(repo) => repo,
+                Err(error) => {
+                    warn!(
+                        "Skipping repository {} that cannot be opened: {}",
+                        repository.display(),
+                        error
+                    );
+                    continue;
+                }
+            };
+            repo.prune_worktrees()?;
+            repo.gc()?;
+            let after = dir_size(&repository)?;
+            report.reclaimed_bytes += before.saturating_sub(after);
+        }
+
+        let quarantine_path = self.quarantine_path();
+        if quarantine_path.exists() {
+            debug!("Removing quarantined repositories");
+            report.reclaimed_bytes += dir_size(&quarantine_path)?;
+            std::fs::remove_dir_all(&quarantine_path)?;
+        }
+
+        Ok(report)
+    }
+}
+
+/// A directory with an `objects` child alone may also be an organization with a repository
+/// called `objects`, so the `HEAD` file is required as well.
+pub(super) fn is_bare_repository(path: &Path) -> bool {
+    path.join("HEAD").is_file() && (path.join("objects").is_dir() || path.join("config").is_file())
+}
+
+/// Matches what is left of repositories too broken to be found by [`is_bare_repository`], so
+/// that they can be repaired. Coordinates only ever create directories, so the files `HEAD` and
+/// `config` always belong to a repository.
+pub(super) fn is_broken_repository(path: &Path) -> bool {
+    !is_bare_repository(path) && (path.join("HEAD").is_file() || path.join("config").is_file())
+}
+
+/// The last time the repository was used. Fetches write `FETCH_HEAD` and [`mark_used`] bumps it
+/// when nothing had to be fetched, while `HEAD` tells when a repository was created.
+pub(super) fn repository_last_used(path: &Path) -> std::io::Result<SystemTime> {
+    std::fs::metadata(path.join("FETCH_HEAD"))
+        .or_else(|_| std::fs::metadata(path.join("HEAD")))?
+        .modified()
+}
+
+/// Records that the repository was used, so that it is not garbage collected.
+pub(super) fn mark_used(path: &Path) -> std::io::Result<()> {
+    match std::fs::File::options()
+        .append(true)
+        .open(path.join("FETCH_HEAD"))
+    {
+        Ok(file) => file.set_modified(SystemTime::now()),
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(error) => Err(error),
+    }
+}
+
+pub(super) fn is_worktree(path: &Path) -> bool {
+    path.join(".git").is_file()
+}
+
+/// The last time the worktree was used. Older protofetch versions checked out every module into
+/// a worktree and bumped the modification time of its `.git` file whenever it was used.
+pub(super) fn worktree_last_used(path: &Path) -> std::io::Result<SystemTime> {
+    std::fs::metadata(path.join(".git"))?.modified()
+}
+
+/// Finds directories under `root` matching `is_match`, without descending into matches.
+pub(super) fn find_dirs(
+    root: &Path,
+    is_match: fn(&Path) -> bool,
+) -> Result<Vec<PathBuf>, CacheError> {
+    let mut found = Vec::new();
+    if !root.is_dir() {
+        return Ok(found);
+    }
+    let mut pending = vec![root.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        for entry in std::fs::read_dir(&dir)? {
+            let entry = entry?;
+            if !entry.file_type()?.is_dir() {
+                continue;
+            }
+            let path = entry.path();
+            if is_match(&path) {
+                found.push(path);
+            } else {
+                pending.push(path);
+            }
+        }
+    }
+    found.sort();
+    Ok(found)
+}
+
+pub(super) fn dir_size(path: &Path) -> Result<u64, CacheError> {
+    let metadata = match std::fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(error) => return Err(error.into()),
+    };
+    if !metadata.is_dir() {
+        return Ok(metadata.len());
+    }
+    let mut size = 0;
+    for entry in std::fs::read_dir(path)? {
+        size += dir_size(&entry?.path())?;
+    }
+    Ok(size)
+}
+
+/// Removes `path` and any parents left empty below `root`, returning the number of bytes freed.
+fn remove_dir(path: &Path, root: &Path) -> Result<u64, CacheError> {
+    if !path.exists() {
+        return Ok(0);
+    }
+    let size = dir_size(path)?;
+    std::fs::remove_dir_all(path)?;
+    let mut parent = path.parent();
+    while let Some(dir) = parent {
+        if dir == root || !dir.starts_with(root) || std::fs::remove_dir(dir).is_err() {
+            break;
+        }
+        parent = dir.parent();
+    }
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::{
+        flock::DEFAULT_LOCK_TIMEOUT, git::backend::GitBackendType, model::protofetch::Protocol,
+    };
+
+    use super::*;
+
+    const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+    fn cache() -> (tempfile::TempDir, ProtofetchGitCache) {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = ProtofetchGitCache::new(
+            dir.path().to_path_buf(),
+            Protocol::Https,
+            GitBackendType::Libgit2,
+            None,
+            DEFAULT_LOCK_TIMEOUT,
+        )
+        .unwrap();
+        (dir, cache)
+    }
+
+    fn fake_worktree(cache: &ProtofetchGitCache, path: &str, last_used: SystemTime) -> PathBuf {
+        let worktree = cache.worktrees_path().join(path);
+        fs::create_dir_all(&worktree).unwrap();
+        fs::write(worktree.join("file.proto"), b"syntax = \"proto3\";").unwrap();
+        fs::File::create(worktree.join(".git"))
+            .unwrap()
+            .set_modified(last_used)
+            .unwrap();
+        worktree
+    }
+
+    fn bare_repository(cache: &ProtofetchGitCache, path: &str) -> PathBuf {
+        let repository = cache.repositories_path().join(path);
+        fs::create_dir_all(&repository).unwrap();
+        cache.backend.init_bare(&repository).unwrap();
+        repository
+    }
+
+    fn fetched_repository(
+        cache: &ProtofetchGitCache,
+        path: &str,
+        last_used: SystemTime,
+    ) -> PathBuf {
+        let repository = bare_repository(cache, path);
+        fs::File::create(repository.join("FETCH_HEAD"))
+            .unwrap()
+            .set_modified(last_used)
+            .unwrap();
+        repository
+    }
+
+    #[test]
+    fn removes_only_stale_worktrees() {
+        let (_dir, cache) = cache();
+        let stale = fake_worktree(
+            &cache,
+            "example.com/org/stale/1111",
+            SystemTime::now() - 10 * DAY,
+        );
+        let fresh = fake_worktree(&cache, "example.com/org/fresh/2222", SystemTime::now());
+
+        let report = cache.gc(7 * DAY, None).unwrap();
+
+        assert!(!stale.exists());
+        assert!(!cache
+            .worktrees_path()
+            .join("example.com/org/stale")
+            .exists());
+        assert!(fresh.exists());
+        assert_eq!(report.removed_worktrees, 1);
+        assert_eq!(report.removed_repositories, 0);
+        assert!(report.reclaimed_bytes > 0);
+    }
+
+    #[test]
+    fn removes_only_stale_repositories() {
+        let (_dir, cache) = cache();
+        let stale = fetched_repository(
+            &cache,
+            "example.com/org/stale",
+            SystemTime::now() - 10 * DAY,
+        );
+        let fresh = fetched_repository(&cache, "example.com/org/fresh", SystemTime::now());
+
+        let report = cache.gc(7 * DAY, None).unwrap();
+
+        assert!(!stale.exists());
+        assert!(fresh.exists());
+        assert_eq!(report.removed_repositories, 1);
+    }
+
+    #[test]
+    fn marking_a_repository_used_keeps_it() {
+        let (_dir, cache) = cache();
+        let repository =
+            fetched_repository(&cache, "example.com/org/repo", SystemTime::now() - 10 * DAY);
+
+        mark_used(&repository).unwrap();
+        let report = cache.gc(7 * DAY, None).unwrap();
+
+        assert!(repository.exists());
+        assert_eq!(report.removed_repositories, 0);
+    }
+
+    #[test]
+    fn keeps_organizations_with_a_repository_called_objects() {
+        let (_dir, cache) = cache();
+        let repository = bare_repository(&cache, "example.com/org/objects");
+
+        let referenced = BTreeSet::from([PathBuf::from("example.com/org/objects")]);
+        let report = cache.gc(7 * DAY, Some(&referenced)).unwrap();
+
+        assert!(repository.exists());
+        assert_eq!(report.removed_repositories, 0);
+    }
+
+    #[test]
+    fn removes_unreferenced_repositories_with_their_worktrees() {
+        let (_dir, cache) = cache();
+        let used = bare_repository(&cache, "example.com/org/used");
+        let unused = bare_repository(&cache, "example.com/org/unused");
+        let unused_worktree =
+            fake_worktree(&cache, "example.com/org/unused/3333", SystemTime::now());
+
+        let referenced = BTreeSet::from([PathBuf::from("example.com/org/used")]);
+        let report = cache.gc(7 * DAY, Some(&referenced)).unwrap();
+
+        assert!(used.exists());
+        assert!(!unused.exists());
+        assert!(!unused_worktree.exists());
+        assert_eq!(report.removed_repositories, 1);
+        assert_eq!(report.removed_worktrees, 0);
+    }
+}
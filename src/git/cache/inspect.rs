@@ -0,0 +1,165 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Serialize, Serializer};
+
+use crate::flock::LockKind;
+
+use super::{
+    gc::{
+        dir_size, find_dirs, is_bare_repository, is_worktree, repository_last_used,
+        worktree_last_used,
+    },
+    CacheError, ProtofetchGitCache,
+};
+
+/// A repository in the cache.
+#[derive(Debug, Serialize)]
+pub struct CacheEntry {
+    /// Location of the repository relative to the cache, e.g. `github.com/org/repo`.
+    pub coordinate: String,
+    pub path: PathBuf,
+    /// URL of the `origin` remote, if the repository could be opened.
+    pub origin: Option<String>,
+    /// Size of the repository and of its legacy worktrees.
+    pub size_bytes: u64,
+    #[serde(serialize_with = "serialize_unix_seconds")]
+    pub last_used: Option<SystemTime>,
+    /// Worktrees checked out by older protofetch versions, until they are garbage collected.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub legacy_worktrees: Vec<LegacyWorktree>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LegacyWorktree {
+    pub commit_hash: String,
+    pub path: PathBuf,
+    pub size_bytes: u64,
+    #[serde(serialize_with = "serialize_unix_seconds")]
+    pub last_used: Option<SystemTime>,
+}
+
+impl ProtofetchGitCache {
+    /// Lists all cached repositories, sorted by coordinate.
+    pub fn entries(&self) -> Result<Vec<CacheEntry>, CacheError> {
+        let repositories_path = self.repositories_path();
+        find_dirs(&repositories_path, is_bare_repository)?
+            .into_iter()
+            .map(|path| {
+                let relative = path
+                    .strip_prefix(&repositories_path)
+                    .expect("repository is inside the cache")
+                    .to_path_buf();
+                self.entry(&relative, path)
+            })
+            .collect()
+    }
+
+    fn entry(&self, relative: &Path, path: PathBuf) -> Result<CacheEntry, CacheError> {
+        let _lock = self.lock_path(relative, LockKind::Shared)?;
+        let origin = self
+            .backend
+            .open(&path)
+            .and_then(|repo| repo.remote_get_url("origin"))
+            .ok()
+            .flatten();
+
+        let mut legacy_worktrees = Vec::new();
+        for worktree in find_dirs(&self.worktrees_path().join(relative), is_worktree)? {
+            legacy_worktrees.push(LegacyWorktree {
+                commit_hash: worktree
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+                size_bytes: dir_size(&worktree)?,
+                last_used: worktree_last_used(&worktree).ok(),
+                path: worktree,
+            });
+        }
+
+        let last_used = legacy_worktrees
+            .iter()
+            .map(|worktree| worktree.last_used)
+            .chain([repository_last_used(&path).ok()])
+            .max()
+            .flatten();
+
+        Ok(CacheEntry {
+            coordinate: relative
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/"),
+            size_bytes: dir_size(&path)?
+                + legacy_worktrees
+                    .iter()
+                    .map(|worktree| worktree.size_bytes)
+                    .sum::<u64>(),
+            path,
+            origin,
+            last_used,
+            legacy_worktrees,
+        })
+    }
+}
+
+fn serialize_unix_seconds<S: Serializer>(
+    time: &Option<SystemTime>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    time.and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|duration| duration.as_secs())
+        .serialize(serializer)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::{
+        flock::DEFAULT_LOCK_TIMEOUT, git::backend::GitBackendType, model::protofetch::Protocol,
+    };
+
+    use super::*;
+
+    #[test]
+    fn lists_repositories_with_their_legacy_worktrees() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = ProtofetchGitCache::new(
+            dir.path().to_path_buf(),
+            Protocol::Https,
+            GitBackendType::Libgit2,
+            None,
+            DEFAULT_LOCK_TIMEOUT,
+        )
+        .unwrap();
+        let repository = cache.repositories_path().join("example.com/org/repo");
+        fs::create_dir_all(&repository).unwrap();
+        cache
+            .backend
+            .init_bare(&repository)
+            .unwrap()
+            .remote_add("origin", "https://example.com/org/repo")
+            .unwrap();
+        let worktree = cache.worktrees_path().join("example.com/org/repo/1234");
+        fs::create_dir_all(&worktree).unwrap();
+        fs::write(worktree.join(".git"), b"gitdir: somewhere").unwrap();
+
+        let entries = cache.entries().unwrap();
+
+        assert_eq!(entries.len(), 1);
+        let entry = &entries[0];
+        assert_eq!(entry.coordinate, "example.com/org/repo");
+        assert_eq!(
+            entry.origin.as_deref(),
+            Some("https://example.com/org/repo")
+        );
+        assert_eq!(entry.legacy_worktrees.len(), 1);
+        assert_eq!(entry.legacy_worktrees[0].commit_hash, "1234");
+        assert_eq!(entry.legacy_worktrees[0].size_bytes, 17);
+        assert!(entry.size_bytes > entry.legacy_worktrees[0].size_bytes);
+        assert_eq!(entry.last_used, entry.legacy_worktrees[0].last_used);
+    }
+}
@@ -0,0 +1,279 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::Duration,
+};
+
+use log::{debug, info, trace, warn};
+use thiserror::Error;
+
+use crate::{
+    flock::{FileLock, LockKind},
+    git::{
+        backend::{
+            create_backend, error::GitBackendError, GitBackend, GitBackendType, GitRepository,
+        },
+        coord_locks::CoordinateLocks,
+        repository::{ListedRemoteRefs, ProtoGitRepository},
+    },
+    model::protofetch::{Coordinate, Protocol},
+};
+
+mod gc;
+mod inspect;
+mod verify;
+
+pub use inspect::CacheEntry;
+
+const CACHE_VERSION: &str = "v2";
+
+pub struct ProtofetchGitCache {
+    unversioned_location: PathBuf,
+    default_protocol: Protocol,
+    coord_locks: CoordinateLocks,
+    backend: Box<dyn GitBackend>,
+    lock_timeout: Duration,
+    /// Shared while the cache is used, exclusive while it is maintained as a whole.
+    cache_lock: Mutex<Option<FileLock>>,
+    listed_remote_refs: ListedRemoteRefs,
+}
+
+#[derive(Error, Debug)]
+pub enum CacheError {
+    #[error("Git backend error: {0}")]
+    Backend(#[from] GitBackendError),
+    #[error("Cache location {location} does not exist")]
+    BadLocation { location: String },
+    #[error("Cache lock cannot be acquired: {0}")]
+    Lock(#[from] crate::flock::Error),
+    #[error("IO error: {0}")]
+    IO(#[from] std::io::Error),
+}
+
+impl ProtofetchGitCache {
+    pub fn new(
+        location: PathBuf,
+        default_protocol: Protocol,
+        backend_type: GitBackendType,
+        git_executable: Option<String>,
+        lock_timeout: Duration,
+    ) -> Result<ProtofetchGitCache, CacheError> {
+        if location.exists() {
+            if !location.is_dir() {
+                return Err(CacheError::BadLocation {
+                    location: location.to_str().unwrap_or("").to_string(),
+                });
+            }
+        } else {
+            std::fs::create_dir_all(&location)?;
+        }
+
+        let lock = Self::acquire_lock(&location, LockKind::Shared, lock_timeout)?;
+        let backend = create_backend(backend_type, git_executable);
+
+        Ok(ProtofetchGitCache {
+            unversioned_location: location,
+            default_protocol,
+            coord_locks: CoordinateLocks::default(),
+            backend,
+            lock_timeout,
+            cache_lock: Mutex::new(Some(lock)),
+            listed_remote_refs: Default::default(),
+        })
+    }
+
+    pub fn coord_locks(&self) -> &CoordinateLocks {
+        &self.coord_locks
+    }
+
+    pub fn clear(&self) -> anyhow::Result<()> {
+        self.lock_cache(LockKind::Exclusive)?;
+        if self.unversioned_location.exists() {
+            info!(
+                "Clearing protofetch repository cache {}",
+                &self.unversioned_location.display()
+            );
+            std::fs::remove_dir_all(&self.unversioned_location)?;
+        }
+        Ok(())
+    }
+
+    pub fn repository(&self, entry: &Coordinate) -> Result<ProtoGitRepository, CacheError> {
+        let mut path = self.repositories_path();
+        path.push(entry.to_path());
+
+        let url = entry.to_git_url(self.default_protocol);
+
+        let repo = if path.exists() {
+            self.open_entry(&path, &url)?
+        } else {
+            self.create_repo(&path, &url)?
+        };
+        if let Err(error) = gc::mark_used(&path) {
+            debug!("Could not mark {} as used: {}", path.display(), error);
+        }
+
+        Ok(ProtoGitRepository::new(
+            repo,
+            url,
+            self.listed_remote_refs.clone(),
+        ))
+    }
+
+    /// Returns the names of the remote tags that point to a commit of an already fetched
+    /// repository. The remote refs listed while resolving the repository are reused.
+    pub fn tags_of(
+        &self,
+        coordinate: &Coordinate,
+        commit_hash: &str,
+    ) -> anyhow::Result<Vec<String>> {
+        // Listing the remote refs does not write to the repository, unlike opening it with
+        // `repository`, which may re-create it
+        let _lock = self.lock_coordinate(coordinate, LockKind::Shared)?;
+        Ok(self.fetched_repository(coordinate)?.tags_of(commit_hash)?)
+    }
+
+    /// Opens a repository that has already been fetched, without the checks and repairs done
+    /// by [`Self::repository`], so that it is cheap enough to call for every file read from it.
+    pub fn fetched_repository(&self, entry: &Coordinate) -> Result<ProtoGitRepository, CacheError> {
+        let mut path = self.repositories_path();
+        path.push(entry.to_path());
+        let repo = self.backend.open(&path)?;
+        Ok(ProtoGitRepository::new(
+            repo,
+            entry.to_git_url(self.default_protocol),
+            self.listed_remote_refs.clone(),
+        ))
+    }
+
+    fn root_path(&self) -> PathBuf {
+        self.unversioned_location.join(CACHE_VERSION)
+    }
+
+    fn repositories_path(&self) -> PathBuf {
+        let mut path = self.root_path();
+        path.push("repositories");
+        path
+    }
+
+    /// Worktrees checked out by older protofetch versions, kept around until garbage collected.
+    pub fn worktrees_path(&self) -> PathBuf {
+        let mut path = self.root_path();
+        path.push("worktrees");
+        path
+    }
+
+    /// Where broken repositories are moved to, so that they can be inspected later.
+    fn quarantine_path(&self) -> PathBuf {
+        let mut path = self.root_path();
+        path.push("quarantine");
+        path
+    }
+
+    /// Locks a single repository against other processes.
+    ///
+    /// This is the cross-process counterpart of [`CoordinateLocks`]: operations that fetch into
+    /// the repository need an exclusive lock, read-only ones a shared one.
+    pub fn lock_coordinate(
+        &self,
+        coordinate: &Coordinate,
+        kind: LockKind,
+    ) -> Result<FileLock, CacheError> {
+        self.lock_path(&coordinate.to_path(), kind)
+    }
+
+    fn lock_path(&self, relative: &Path, kind: LockKind) -> Result<FileLock, CacheError> {
+        let mut location = self.root_path();
+        location.push("locks");
+        location.push(relative);
+        if let Some(parent) = location.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut file_name = location.into_os_string();
+        file_name.push(".lock");
+        trace!("Acquiring a {:?} lock on {:?}", kind, file_name);
+        Ok(FileLock::new(
+            Path::new(&file_name),
+            kind,
+            self.lock_timeout,
+        )?)
+    }
+
+    /// Makes sure the whole cache is locked with at least the given kind of lock.
+    fn lock_cache(&self, kind: LockKind) -> Result<(), CacheError> {
+        let mut held = self.cache_lock.lock().expect("cache lock poisoned");
+        if held.as_ref().is_some_and(|lock| lock.kind() >= kind) {
+            return Ok(());
+        }
+        // Not every platform can upgrade a lock held by the same process, so release it first
+        *held = None;
+        *held = Some(Self::acquire_lock(
+            &self.unversioned_location,
+            kind,
+            self.lock_timeout,
+        )?);
+        Ok(())
+    }
+
+    fn acquire_lock(
+        root: &Path,
+        kind: LockKind,
+        timeout: Duration,
+    ) -> Result<FileLock, CacheError> {
+        let location = root.join(".lock");
+        debug!(
+            "Acquiring a {:?} lock on the cache location: {}",
+            kind,
+            location.display()
+        );
+        let lock = FileLock::new(&location, kind, timeout)?;
+        info!("Acquired a lock on the cache location");
+        Ok(lock)
+    }
+
+    fn open_entry(&self, path: &Path, url: &str) -> Result<Box<dyn GitRepository>, CacheError> {
+        trace!("Opening existing repository at {}", path.display());
+
+        let repo = match self.check_entry(path) {
+            Ok(repo) => repo,
+            Err(problem) => {
+                warn!(
+                    "Cached repository {} is broken, re-cloning it: {}",
+                    path.display(),
+                    problem
+                );
+                self.quarantine(path)?;
+                return self.create_repo(path, url);
+            }
+        };
+
+        let current_url = repo.remote_get_url("origin")?;
+        if current_url.as_deref() != Some(url) {
+            // If true then the protocol was updated before updating the cache.
+            trace!(
+                "Updating remote existing url {:?} to new url {}",
+                current_url,
+                url
+            );
+            repo.remote_set_url("origin", url)?;
+        }
+
+        Ok(repo)
+    }
+
+    fn create_repo(&self, path: &Path, url: &str) -> Result<Box<dyn GitRepository>, CacheError> {
+        trace!("Creating a new repository at {}", path.display());
+
+        std::fs::create_dir_all(path)?;
+        let repo = self.backend.init_bare(path)?;
+        repo.remote_add("origin", url)?;
+
+        Ok(repo)
+    }
+}
+
+#[allow(dead_code)]
+fn _assert_traits() {
+    fn assert<T: Send + Sync + std::panic::UnwindSafe + std::panic::RefUnwindSafe>() {}
+    assert::<ProtofetchGitCache>();
+}
@@ -0,0 +1,256 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use log::{info, warn};
+use thiserror::Error;
+
+use crate::{
+    flock::LockKind,
+    git::backend::{error::GitBackendError, GitRepository},
+};
+
+use super::{
+    gc::{find_dirs, is_bare_repository, is_broken_repository, is_worktree},
+    CacheError, ProtofetchGitCache,
+};
+
+/// Why a cached repository or worktree cannot be used.
+#[derive(Error, Debug)]
+pub enum EntryProblem {
+    #[error("repository cannot be opened: {0}")]
+    Unreadable(GitBackendError),
+    #[error("repository is incomplete, it has no HEAD or no objects")]
+    Incomplete,
+    #[error("remote origin is missing, the repository was probably not fully created")]
+    MissingOrigin,
+    #[error("stale worktree metadata cannot be pruned: {0}")]
+    StaleWorktrees(GitBackendError),
+    #[error("repository is corrupted: {0}")]
+    Corrupted(GitBackendError),
+    #[error("worktree belongs to a repository that no longer exists")]
+    OrphanedWorktree,
+}
+
+/// A cache entry found broken by [`ProtofetchGitCache::verify`].
+#[derive(Debug)]
+pub struct BrokenEntry {
+    pub path: PathBuf,
+    pub problem: EntryProblem,
+}
+
+impl ProtofetchGitCache {
+    /// Checks every cached repository and worktree, including the contents of all objects.
+    ///
+    /// With `repair`, broken repositories are moved to the quarantine directory, to be re-cloned
+    /// on the next fetch, and orphaned worktrees are removed.
+    pub fn verify(&self, repair: bool) -> Result<Vec<BrokenEntry>, CacheError> {
+        if repair {
+            self.lock_cache(LockKind::Exclusive)?;
+        }
+        let repositories_path = self.repositories_path();
+        let mut broken = Vec::new();
+
+        for repository in find_dirs(&repositories_path, is_bare_repository)? {
+            let _lock = self.lock_path(
+                repository
+                    .strip_prefix(&repositories_path)
+                    .expect("repository is inside the cache"),
+                LockKind::Shared,
+            )?;
+            let problem = match self.check_entry(&repository) {
+                Ok(repo) => match repo.verify_objects() {
+                    Ok(()) => continue,
+                    Err(error) => EntryProblem::Corrupted(error),
+                },
+                Err(problem) => problem,
+            };
+            if repair {
+                self.quarantine(&repository)?;
+            }
+            broken.push(BrokenEntry {
+                path: repository,
+                problem,
+            });
+        }
+
+        for repository in find_dirs(&repositories_path, is_broken_repository)? {
+            let _lock = self.lock_path(
+                repository
+                    .strip_prefix(&repositories_path)
+                    .expect("repository is inside the cache"),
+                LockKind::Shared,
+            )?;
+            if repair {
+                self.quarantine(&repository)?;
+            }
+            broken.push(BrokenEntry {
+                path: repository,
+                problem: EntryProblem::Incomplete,
+            });
+        }
+
+        for worktree in find_dirs(&self.worktrees_path(), is_worktree)? {
+            if gitdir_exists(&worktree) {
+                continue;
+            }
+            if repair {
+                info!("Removing orphaned worktree {}", worktree.display());
+                std::fs::remove_dir_all(&worktree)?;
+            }
+            broken.push(BrokenEntry {
+                path: worktree,
+                problem: EntryProblem::OrphanedWorktree,
+            });
+        }
+
+        Ok(broken)
+    }
+
+    /// Opens a cached repository, pruning stale worktree metadata on the way.
+    ///
+    /// This only performs cheap checks, so that it can run every time an entry is used.
+    pub(super) fn check_entry(&self, path: &Path) -> Result<Box<dyn GitRepository>, EntryProblem> {
+        let repo = self.backend.open(path).map_err(EntryProblem::Unreadable)?;
+        match repo.remote_get_url("origin") {
+            Ok(Some(_)) => {}
+            Ok(None) => return Err(EntryProblem::MissingOrigin),
+            Err(error) => return Err(EntryProblem::Unreadable(error)),
+        }
+        repo.prune_worktrees()
+            .map_err(EntryProblem::StaleWorktrees)?;
+        Ok(repo)
+    }
+
+    /// Moves a broken repository out of the way, together with its worktrees that would point
+    /// at it. Returns the new location of the repository.
+    pub(super) fn quarantine(&self, path: &Path) -> Result<PathBuf, CacheError> {
+        let coordinate = path
+            .strip_prefix(self.repositories_path())
+            .expect("repository is inside the cache");
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let target = self
+            .quarantine_path()
+            .join(timestamp.to_string())
+            .join(coordinate);
+        warn!(
+            "Moving broken repository {} to {}",
+            path.display(),
+            target.display()
+        );
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::rename(path, &target)?;
+
+        let worktrees = self.worktrees_path().join(coordinate);
+        if worktrees.exists() {
+            std::fs::remove_dir_all(worktrees)?;
+        }
+        Ok(target)
+    }
+}
+
+/// Whether the administrative directory referenced by the worktree's `.git` file still exists.
+fn gitdir_exists(worktree: &Path) -> bool {
+    std::fs::read_to_string(worktree.join(".git"))
+        .ok()
+        .and_then(|content| {
+            content
+                .trim()
+                .strip_prefix("gitdir:")
+                .map(|gitdir| worktree.join(gitdir.trim()).is_dir())
+        })
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::{
+        flock::DEFAULT_LOCK_TIMEOUT, git::backend::GitBackendType, model::protofetch::Protocol,
+    };
+
+    use super::*;
+
+    fn cache() -> (tempfile::TempDir, ProtofetchGitCache) {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = ProtofetchGitCache::new(
+            dir.path().to_path_buf(),
+            Protocol::Https,
+            GitBackendType::Libgit2,
+            None,
+            DEFAULT_LOCK_TIMEOUT,
+        )
+        .unwrap();
+        (dir, cache)
+    }
+
+    #[test]
+    fn half_created_repository_is_quarantined() {
+        let (_dir, cache) = cache();
+        let repository = cache.repositories_path().join("example.com/org/repo");
+        fs::create_dir_all(&repository).unwrap();
+        cache.backend.init_bare(&repository).unwrap();
+
+        let broken = cache.verify(true).unwrap();
+
+        assert_eq!(broken.len(), 1);
+        assert!(matches!(broken[0].problem, EntryProblem::MissingOrigin));
+        assert!(!repository.exists());
+        assert_eq!(fs::read_dir(cache.quarantine_path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn incomplete_repository_is_quarantined() {
+        let (_dir, cache) = cache();
+        let repository = cache.repositories_path().join("example.com/org/repo");
+        fs::create_dir_all(&repository).unwrap();
+        fs::write(repository.join("HEAD"), "ref: refs/heads/main\n").unwrap();
+
+        let broken = cache.verify(true).unwrap();
+
+        assert_eq!(broken.len(), 1);
+        assert!(matches!(broken[0].problem, EntryProblem::Incomplete));
+        assert!(!repository.exists());
+    }
+
+    #[test]
+    fn orphaned_worktree_is_reported_and_removed_on_repair() {
+        let (_dir, cache) = cache();
+        let worktree = cache.worktrees_path().join("example.com/org/repo/1234");
+        fs::create_dir_all(&worktree).unwrap();
+        fs::write(worktree.join(".git"), "gitdir: /does/not/exist\n").unwrap();
+
+        let broken = cache.verify(false).unwrap();
+        assert_eq!(broken.len(), 1);
+        assert!(matches!(broken[0].problem, EntryProblem::OrphanedWorktree));
+        assert!(worktree.exists());
+
+        cache.verify(true).unwrap();
+        assert!(!worktree.exists());
+    }
+
+    #[test]
+    fn broken_repository_is_recreated_when_opened() {
+        let (_dir, cache) = cache();
+        let path = cache.repositories_path().join("example.com/org/repo");
+        fs::create_dir_all(&path).unwrap();
+        fs::write(path.join("HEAD"), "garbage").unwrap();
+
+        let repo = cache
+            .open_entry(&path, "https://example.com/org/repo")
+            .unwrap();
+
+        assert_eq!(
+            repo.remote_get_url("origin").unwrap().as_deref(),
+            Some("https://example.com/org/repo")
+        );
+        assert!(cache.quarantine_path().exists());
+    }
+}
@@ -7,7 +7,7 @@ use std::{
 use crate::model::protofetch::Coordinate;
 
 /// A map of per-repo locks. libgit2 is per-`Repository` thread-safe, but
-/// concurrent fetches or worktree-add calls into the same on-disk bare repo
+/// concurrent fetches into the same on-disk bare repo
 /// can race on ref updates. Acquiring the same `Mutex` per `forge/org/repo`
 /// path serializes operations on one repo while still allowing different
 /// repos to run in parallel.
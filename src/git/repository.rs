@@ -1,12 +1,18 @@
-use std::path::{Path, PathBuf};
-
-use crate::model::protofetch::{
-    Coordinate, Descriptor, ModuleName, Revision, RevisionSpecification,
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex, MutexGuard},
 };
+
+use crate::model::protofetch::{Descriptor, ModuleName, Revision, RevisionSpecification};
 use log::{debug, warn};
 use thiserror::Error;
 
-use super::backend::{error::GitBackendError, types::GitOid, GitRepository, WorktreeResult};
+use super::backend::{
+    error::GitBackendError,
+    types::{GitOid, RemoteRef},
+    GitRepository,
+};
 
 #[derive(Error, Debug)]
 pub enum ProtoRepoError {
@@ -31,55 +37,111 @@ pub enum ProtoRepoError {
     InvalidCommitHash { commit_hash: String },
     #[error("Commit {commit_hash} was not found")]
     CommitNotFound { commit_hash: String },
-    #[error("Worktree with name {name} already exists at {existing_path} but we need it at {wanted_path}")]
-    WorktreeExists {
-        name: String,
-        existing_path: String,
-        wanted_path: String,
-    },
-    #[error("Error while canonicalizing path {path}: {error}")]
-    Canonicalization { path: String, error: std::io::Error },
+    #[error("File {path} was not found in commit {commit_hash}")]
+    FileNotFound { path: String, commit_hash: String },
     #[error("IO error: {0}")]
     IO(#[from] std::io::Error),
 }
 
+/// The refs of every remote listed so far, by URL, so that each remote is listed only once even
+/// if its repository is opened again, e.g. to describe lock file changes after resolution.
+pub type ListedRemoteRefs = Arc<Mutex<HashMap<String, Vec<RemoteRef>>>>;
+
 pub struct ProtoGitRepository {
     repo: Box<dyn GitRepository>,
     origin: String,
-    worktrees_base: PathBuf,
+    listed: ListedRemoteRefs,
 }
 
 impl ProtoGitRepository {
     pub fn new(
         repo: Box<dyn GitRepository>,
         origin: String,
-        worktrees_base: &Path,
+        listed: ListedRemoteRefs,
     ) -> ProtoGitRepository {
         ProtoGitRepository {
             repo,
             origin,
-            worktrees_base: worktrees_base.to_path_buf(),
+            listed,
         }
     }
 
+    /// Fetches every branch and the pinned tag, if any. This is only used as a fallback
+    /// when the revision cannot be matched against the remote refs.
     pub fn fetch(&self, specification: &RevisionSpecification) -> anyhow::Result<()> {
         let mut refspecs = Vec::with_capacity(3);
         if let Revision::Pinned { revision } = &specification.revision {
-            refspecs.push(format!("+refs/tags/{}:refs/tags/{}", revision, revision));
+            refspecs.push(TrackedRef::tag(revision).refspec());
             // Some protofetch.toml files specify branch in the revision field,
             // or do not specify the branch at all, so we need to fetch all branches.
             refspecs.push("+refs/heads/*:refs/remotes/origin/*".to_owned());
         }
         if let Some(branch) = &specification.branch {
-            refspecs.push(format!(
-                "+refs/heads/{}:refs/remotes/origin/{}",
-                branch, branch
-            ));
+            refspecs.push(TrackedRef::branch(branch).refspec());
         }
 
-        debug!("Fetching {:?} from {}", refspecs, self.origin);
-        self.repo.fetch("origin", &refspecs)?;
-        Ok(())
+        self.fetch_refspecs(&refspecs)
+    }
+
+    /// Resolves the specification to a commit hash and makes sure the commit is available locally.
+    ///
+    /// The remote refs are listed first, so only the branch or tag the specification points to
+    /// has to be fetched, and nothing at all if the commit is already in the cache.
+    pub fn fetch_and_resolve(
+        &self,
+        specification: &RevisionSpecification,
+    ) -> anyhow::Result<String> {
+        let remote_refs = self.remote_refs()?;
+
+        let oid = match (&specification.branch, &specification.revision) {
+            (None, Revision::Arbitrary) => match remote_refs.head() {
+                Some((tracked, oid)) => self.fetch_tracked(&tracked, &oid)?,
+                None => return self.fetch_all_and_resolve(specification),
+            },
+            (None, Revision::Pinned { revision }) => match remote_refs.revision(revision) {
+                Some((tracked, oid)) => self.fetch_tracked(&tracked, &oid)?,
+                None if self.is_local_commit(revision)? => GitOid::from_hex(revision.as_str()),
+                None => return self.fetch_all_and_resolve(specification),
+            },
+            (Some(branch), Revision::Arbitrary) => {
+                let (tracked, oid) = remote_refs.branch(branch)?;
+                self.fetch_tracked(&tracked, &oid)?
+            }
+            (Some(branch), Revision::Pinned { revision }) => {
+                // The ancestry check below needs the history of the branch.
+                let (tracked, oid) = remote_refs.branch(branch)?;
+                let branch_commit = self.fetch_tracked(&tracked, &oid)?;
+                let revision_commit = match remote_refs.revision(revision) {
+                    Some((tracked, oid)) => self.fetch_tracked(&tracked, &oid)?,
+                    None if self.is_local_commit(revision)? => GitOid::from_hex(revision.as_str()),
+                    None => {
+                        self.fetch(specification)?;
+                        match self.repo.revparse_commit(revision) {
+                            Ok(oid) => oid,
+                            // Not even on another branch, so certainly not on this one
+                            Err(_) if is_commit_hash(revision) => {
+                                return Err(ProtoRepoError::RevisionNotOnBranch {
+                                    revision: revision.to_owned(),
+                                    branch: branch.to_owned(),
+                                }
+                                .into())
+                            }
+                            Err(error) => return Err(error.into()),
+                        }
+                    }
+                };
+                if !self.repo.is_ancestor(&revision_commit, &branch_commit)? {
+                    return Err(ProtoRepoError::RevisionNotOnBranch {
+                        revision: revision.to_owned(),
+                        branch: branch.to_owned(),
+                    }
+                    .into());
+                }
+                revision_commit
+            }
+        };
+
+        Ok(oid.to_string())
     }
 
     pub fn fetch_commit(
@@ -87,7 +149,7 @@ impl ProtoGitRepository {
         specification: &RevisionSpecification,
         commit_hash: &str,
     ) -> anyhow::Result<()> {
-        if commit_hash.len() != 40 || !commit_hash.chars().all(|c| c.is_ascii_hexdigit()) {
+        if !is_commit_hash(commit_hash) {
             return Err(ProtoRepoError::InvalidCommitHash {
                 commit_hash: commit_hash.to_owned(),
             }
@@ -112,21 +174,24 @@ impl ProtoGitRepository {
             }
         }
 
-        if specification.branch.is_some()
-            || matches!(specification.revision, Revision::Pinned { .. })
-        {
-            self.fetch(specification)?;
-        }
+        let pinned_revision = match &specification.revision {
+            Revision::Pinned { revision } => Some(revision),
+            Revision::Arbitrary => None,
+        };
+        let needs_remote_refs = specification.branch.is_some()
+            || pinned_revision.is_some_and(|revision| !is_commit_hash(revision));
+        let remote_refs = if needs_remote_refs {
+            self.remote_refs()?
+        } else {
+            RemoteRefs::default()
+        };
 
         let oid = GitOid::from_hex(commit_hash);
 
         if let Some(branch) = &specification.branch {
-            let branch_commit = self
-                .repo
-                .revparse_commit(&format!("origin/{branch}"))
-                .map_err(|_| ProtoRepoError::BranchNotFound {
-                    branch: branch.to_owned(),
-                })?;
+            // The ancestry check needs the history of the branch.
+            let (tracked, branch_oid) = remote_refs.branch(branch)?;
+            let branch_commit = self.fetch_tracked(&tracked, &branch_oid)?;
             if !self.repo.is_ancestor(&oid, &branch_commit)? {
                 return Err(ProtoRepoError::RevisionNotOnBranch {
                     revision: commit_hash.to_owned(),
@@ -136,8 +201,15 @@ impl ProtoGitRepository {
             }
         }
 
-        if let Revision::Pinned { revision } = &specification.revision {
-            let revision_commit = self.repo.revparse_commit(revision)?;
+        if let Some(revision) = pinned_revision {
+            let revision_commit = if is_commit_hash(revision) {
+                GitOid::from_hex(revision.to_ascii_lowercase())
+            } else if let Some((_, oid)) = remote_refs.revision(revision) {
+                oid
+            } else {
+                self.fetch(specification)?;
+                self.repo.revparse_commit(revision)?
+            };
             if oid != revision_commit {
                 return Err(ProtoRepoError::PreciseRevisionMismatch {
                     commit_hash: commit_hash.to_owned(),
@@ -150,6 +222,50 @@ impl ProtoGitRepository {
         Ok(())
     }
 
+    fn fetch_refspecs(&self, refspecs: &[String]) -> anyhow::Result<()> {
+        debug!("Fetching {:?} from {}", refspecs, self.origin);
+        self.repo.fetch("origin", refspecs)?;
+        Ok(())
+    }
+
+    fn remote_refs(&self) -> Result<RemoteRefs, ProtoRepoError> {
+        if let Some(refs) = self.listed_remote_refs().get(&self.origin) {
+            return Ok(RemoteRefs { refs: refs.clone() });
+        }
+        // Not listed under the lock, so that other remotes can be listed in parallel
+        debug!("Listing remote refs of {}", self.origin);
+        let refs = self.repo.list_remote_refs("origin")?;
+        self.listed_remote_refs()
+            .insert(self.origin.clone(), refs.clone());
+        Ok(RemoteRefs { refs })
+    }
+
+    fn listed_remote_refs(&self) -> MutexGuard<'_, HashMap<String, Vec<RemoteRef>>> {
+        self.listed.lock().expect("listed remote refs poisoned")
+    }
+
+    /// Fetches a single remote ref, unless the commit it points to is already in the cache.
+    fn fetch_tracked(&self, tracked: &TrackedRef, oid: &GitOid) -> anyhow::Result<GitOid> {
+        if self.repo.commit_exists(oid.as_str())? {
+            debug!("Commit {} of {} is already fetched", oid, tracked.remote);
+            return Ok(oid.clone());
+        }
+        self.fetch_refspecs(&[tracked.refspec()])?;
+        Ok(self.repo.revparse_commit(&tracked.local)?)
+    }
+
+    fn fetch_all_and_resolve(
+        &self,
+        specification: &RevisionSpecification,
+    ) -> anyhow::Result<String> {
+        self.fetch(specification)?;
+        Ok(self.resolve_commit_hash(specification)?)
+    }
+
+    fn is_local_commit(&self, revision: &str) -> Result<bool, ProtoRepoError> {
+        Ok(is_commit_hash(revision) && self.repo.commit_exists(revision)?)
+    }
+
     pub fn extract_descriptor(
         &self,
         dep_name: &ModuleName,
@@ -164,6 +280,8 @@ impl ProtoGitRepository {
                     name: dep_name.clone(),
                     description: None,
                     proto_out_dir: None,
+                    output_layout: Default::default(),
+                    imports: Default::default(),
                     dependencies: Vec::new(),
                 })
             }
@@ -173,6 +291,8 @@ impl ProtoGitRepository {
                     name: dep_name.clone(),
                     description: None,
                     proto_out_dir: None,
+                    output_layout: Default::default(),
+                    imports: Default::default(),
                     dependencies: Vec::new(),
                 })
             }
@@ -183,13 +303,13 @@ impl ProtoGitRepository {
             )),
             Ok(Some(blob)) => {
                 let content = std::str::from_utf8(&blob)?;
-                let descriptor = Descriptor::from_toml_str(content)?;
+                let descriptor = Descriptor::from_dependency_toml_str(content, dep_name)?;
                 Ok(descriptor)
             }
         }
     }
 
-    pub fn resolve_commit_hash(
+    fn resolve_commit_hash(
         &self,
         specification: &RevisionSpecification,
     ) -> Result<String, ProtoRepoError> {
@@ -224,56 +344,215 @@ impl ProtoGitRepository {
         Ok(oid.to_string())
     }
 
-    pub fn create_worktree(
+    /// Returns the names of the remote tags that point to the commit.
+    pub fn tags_of(&self, commit_hash: &str) -> Result<Vec<String>, ProtoRepoError> {
+        Ok(self.remote_refs()?.tags(&GitOid::from_hex(commit_hash)))
+    }
+
+    /// Lists the paths of all files in the commit, relative to the repository root.
+    pub fn list_files(&self, commit_hash: &str) -> Result<Vec<PathBuf>, ProtoRepoError> {
+        debug!("Listing files of {} at {}", self.origin, commit_hash);
+        let paths = self.repo.list_tree(commit_hash)?;
+        Ok(paths.into_iter().map(PathBuf::from).collect())
+    }
+
+    /// Reads files from the commit, `paths` being relative to the repository root.
+    pub fn read_files(
         &self,
-        coordinate: &Coordinate,
         commit_hash: &str,
-    ) -> Result<PathBuf, ProtoRepoError> {
-        let mut base_path = self.worktrees_base.clone();
-        base_path.push(coordinate.to_path());
+        paths: &[PathBuf],
+    ) -> Result<Vec<Vec<u8>>, ProtoRepoError> {
+        if paths.is_empty() {
+            return Ok(Vec::new());
+        }
+        // Git paths always use forward slashes
+        let blob_paths = paths
+            .iter()
+            .map(|path| {
+                path.components()
+                    .map(|component| component.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/")
+            })
+            .collect::<Vec<_>>();
+        let blobs = self.repo.read_blobs(commit_hash, &blob_paths)?;
+        blob_paths
+            .into_iter()
+            .zip(blobs)
+            .map(|(blob_path, blob)| {
+                blob.ok_or_else(|| ProtoRepoError::FileNotFound {
+                    path: blob_path,
+                    commit_hash: commit_hash.to_owned(),
+                })
+            })
+            .collect()
+    }
+}
+
+fn is_commit_hash(revision: &str) -> bool {
+    revision.len() == 40 && revision.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// A remote ref together with the local ref it is fetched into.
+#[derive(Debug, PartialEq, Eq)]
+struct TrackedRef {
+    remote: String,
+    local: String,
+}
 
-        if !base_path.exists() {
-            std::fs::create_dir_all(&base_path)?;
+impl TrackedRef {
+    fn head() -> Self {
+        TrackedRef {
+            remote: "HEAD".to_owned(),
+            local: "refs/remotes/origin/HEAD".to_owned(),
         }
+    }
 
-        let worktree_path = base_path.join(PathBuf::from(commit_hash));
-        let worktree_name = commit_hash;
+    fn branch(branch: &str) -> Self {
+        TrackedRef {
+            remote: format!("refs/heads/{branch}"),
+            local: format!("refs/remotes/origin/{branch}"),
+        }
+    }
 
-        debug!("Finding worktree {} for {}", worktree_name, coordinate);
+    fn tag(tag: &str) -> Self {
+        TrackedRef {
+            remote: format!("refs/tags/{tag}"),
+            local: format!("refs/tags/{tag}"),
+        }
+    }
 
-        match self
-            .repo
-            .create_worktree(worktree_name, &worktree_path, commit_hash)?
-        {
-            WorktreeResult::Created(worktree_repo) => {
-                worktree_repo.reset(commit_hash)?;
-            }
-            WorktreeResult::Existing(canonical_existing_path, worktree_repo) => {
-                let canonical_wanted_path =
-                    worktree_path
-                        .canonicalize()
-                        .map_err(|e| ProtoRepoError::Canonicalization {
-                            path: worktree_path.to_string_lossy().to_string(),
-                            error: e,
-                        })?;
-
-                if canonical_existing_path != canonical_wanted_path {
-                    return Err(ProtoRepoError::WorktreeExists {
-                        name: worktree_name.to_string(),
-                        existing_path: canonical_existing_path.to_str().unwrap_or("").to_string(),
-                        wanted_path: worktree_path.to_str().unwrap_or("").to_string(),
-                    });
-                } else {
-                    log::debug!(
-                        "Found existing worktree for {} at {}",
-                        coordinate,
-                        canonical_wanted_path.to_string_lossy()
-                    );
-                    worktree_repo.reset(commit_hash)?;
-                }
-            }
+    fn refspec(&self) -> String {
+        format!("+{}:{}", self.remote, self.local)
+    }
+}
+
+/// The refs advertised by `origin`.
+#[derive(Default)]
+struct RemoteRefs {
+    refs: Vec<RemoteRef>,
+}
+
+impl RemoteRefs {
+    /// Returns the commit a ref points to, peeling annotated tags.
+    fn commit(&self, name: &str) -> Option<GitOid> {
+        let peeled = format!("{name}^{{}}");
+        self.refs
+            .iter()
+            .find(|remote_ref| remote_ref.name == peeled)
+            .or_else(|| self.refs.iter().find(|remote_ref| remote_ref.name == name))
+            .map(|remote_ref| remote_ref.oid.clone())
+    }
+
+    fn track(&self, tracked: TrackedRef) -> Option<(TrackedRef, GitOid)> {
+        let oid = self.commit(&tracked.remote)?;
+        Some((tracked, oid))
+    }
+
+    fn head(&self) -> Option<(TrackedRef, GitOid)> {
+        self.track(TrackedRef::head())
+    }
+
+    fn branch(&self, branch: &str) -> Result<(TrackedRef, GitOid), ProtoRepoError> {
+        self.track(TrackedRef::branch(branch))
+            .ok_or_else(|| ProtoRepoError::BranchNotFound {
+                branch: branch.to_owned(),
+            })
+    }
+
+    /// Returns the sorted names of the tags that point to the commit, peeling annotated tags.
+    fn tags(&self, commit: &GitOid) -> Vec<String> {
+        let mut tags = self
+            .refs
+            .iter()
+            .filter_map(|remote_ref| remote_ref.name.strip_prefix("refs/tags/"))
+            .map(|tag| tag.trim_end_matches("^{}"))
+            .filter(|tag| self.commit(&format!("refs/tags/{tag}")).as_ref() == Some(commit))
+            .map(str::to_owned)
+            .collect::<Vec<_>>();
+        tags.sort();
+        tags.dedup();
+        tags
+    }
+
+    /// Looks the revision up as a tag first and as a branch second, like `git rev-parse` does.
+    fn revision(&self, revision: &str) -> Option<(TrackedRef, GitOid)> {
+        self.track(TrackedRef::tag(revision))
+            .or_else(|| self.track(TrackedRef::branch(revision)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn remote_refs(refs: &[(&str, &str)]) -> RemoteRefs {
+        RemoteRefs {
+            refs: refs
+                .iter()
+                .map(|(name, oid)| RemoteRef {
+                    name: name.to_string(),
+                    oid: GitOid::from_hex(*oid),
+                })
+                .collect(),
         }
+    }
+
+    #[test]
+    fn revision_prefers_peeled_tag_commit() {
+        let refs = remote_refs(&[("refs/tags/v1", "tag"), ("refs/tags/v1^{}", "commit")]);
+
+        let (tracked, oid) = refs.revision("v1").unwrap();
+
+        assert_eq!(tracked, TrackedRef::tag("v1"));
+        assert_eq!(oid, GitOid::from_hex("commit"));
+    }
+
+    #[test]
+    fn revision_prefers_tag_over_branch() {
+        let refs = remote_refs(&[("refs/heads/v1", "branch"), ("refs/tags/v1", "tag")]);
+
+        let (tracked, oid) = refs.revision("v1").unwrap();
+
+        assert_eq!(tracked, TrackedRef::tag("v1"));
+        assert_eq!(oid, GitOid::from_hex("tag"));
+    }
+
+    #[test]
+    fn revision_falls_back_to_branch() {
+        let refs = remote_refs(&[("HEAD", "head"), ("refs/heads/main", "main")]);
+
+        let (tracked, oid) = refs.revision("main").unwrap();
+
+        assert_eq!(
+            tracked.refspec(),
+            "+refs/heads/main:refs/remotes/origin/main"
+        );
+        assert_eq!(oid, GitOid::from_hex("main"));
+        assert!(refs.revision("v1").is_none());
+    }
+
+    #[test]
+    fn tags_are_matched_by_their_peeled_commit() {
+        let refs = remote_refs(&[
+            ("refs/heads/main", "commit"),
+            ("refs/tags/v2", "tag"),
+            ("refs/tags/v2^{}", "commit"),
+            ("refs/tags/v1", "commit"),
+            ("refs/tags/v0", "other"),
+        ]);
+
+        assert_eq!(refs.tags(&GitOid::from_hex("commit")), vec!["v1", "v2"]);
+        assert!(refs.tags(&GitOid::from_hex("tag")).is_empty());
+    }
+
+    #[test]
+    fn missing_branch_is_reported() {
+        let refs = remote_refs(&[("refs/heads/main", "main")]);
 
-        Ok(worktree_path)
+        assert!(matches!(
+            refs.branch("feature"),
+            Err(ProtoRepoError::BranchNotFound { .. })
+        ));
     }
 }
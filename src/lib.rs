@@ -6,6 +6,10 @@ mod engine;
 mod flock;
 mod git;
 mod model;
+mod proto;
 mod resolver;
 
-pub use api::{DependencyUpdate, LockMode, LockUpdateMode, Protofetch, ProtofetchBuilder};
+pub use api::{
+    error_code, json_schema, BreakingChangePolicy, DependencyUpdate, FileDiffMode, LockMode,
+    LockUpdateMode, NewDependency, OutputFormat, Protofetch, ProtofetchBuilder, SchemaKind,
+};
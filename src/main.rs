@@ -1,10 +1,13 @@
-use std::error::Error;
+use std::{error::Error, path::PathBuf, time::Duration};
 
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 use env_logger::Target;
 
 use log::warn;
-use protofetch::{DependencyUpdate, LockMode, LockUpdateMode, Protofetch};
+use protofetch::{
+    error_code, json_schema, BreakingChangePolicy, DependencyUpdate, FileDiffMode, LockMode,
+    LockUpdateMode, NewDependency, OutputFormat, Protofetch, SchemaKind,
+};
 
 /// Dependency management tool for Protocol Buffers files.
 #[derive(Debug, Parser)]
@@ -29,10 +32,17 @@ pub struct CliArgs {
     /// PROTOFETCH_JOBS / config.toml. Defaults to 16.
     #[clap(long)]
     pub jobs: Option<usize>,
-    /// Maximum number of in-flight disk jobs (worktree + copy). Overrides
+    /// Maximum number of in-flight disk jobs (tree listing + copy). Overrides
     /// PROTOFETCH_COPY_JOBS / config.toml. Defaults to max(4, num_cpus / 2).
     #[clap(long)]
     pub copy_jobs: Option<usize>,
+    /// Seconds to wait for other protofetch processes using the cache. Overrides
+    /// PROTOFETCH_CACHE_LOCK_TIMEOUT / config.toml. Defaults to 300.
+    #[clap(long, value_name = "SECONDS")]
+    pub lock_timeout: Option<u64>,
+    /// Format of the results printed on stdout. Logs are always printed on stderr
+    #[clap(long, global = true, value_enum, default_value_t = Format::Text)]
+    pub format: Format,
 }
 
 #[derive(Debug, Parser)]
@@ -45,9 +55,28 @@ pub enum Command {
         /// forces re-creation of lock file
         #[clap(short, long, hide(true))]
         force_lock: bool,
+        /// fail when imports reached by pruning cannot be resolved
+        #[clap(long)]
+        strict_imports: bool,
+        /// compile the fetched proto files and write their FileDescriptorSet to this file
+        #[clap(long, value_name = "FILE")]
+        descriptor_set: Option<PathBuf>,
+        /// additional directory to resolve imports against when compiling (can be repeated)
+        #[clap(
+            short = 'I',
+            long = "include",
+            value_name = "DIR",
+            requires = "descriptor_set"
+        )]
+        includes: Vec<PathBuf>,
     },
     /// Creates a lock file based on toml configuration file
-    Lock,
+    Lock {
+        /// Print the changes to the lock file without writing it, exiting with code 2 if there
+        /// are any
+        #[clap(long)]
+        dry_run: bool,
+    },
     /// Updates the lock file
     Update {
         /// Dependencies to update
@@ -56,6 +85,54 @@ pub enum Command {
         /// Update the selected dependency to this exact commit
         #[clap(long)]
         precise: Option<String>,
+        /// Report breaking changes between the previously locked and the updated commits
+        #[clap(long)]
+        check_breaking: bool,
+        /// Fail without updating the lock file if there are breaking changes
+        #[clap(long)]
+        deny_breaking: bool,
+        /// Print the files of the output that change for every updated dependency
+        #[clap(
+            long,
+            value_enum,
+            value_name = "FORMAT",
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = "files"
+        )]
+        diff: Option<DiffFormat>,
+        /// Print the changes to the lock file without writing it, exiting with code 2 if there
+        /// are any
+        #[clap(long)]
+        dry_run: bool,
+    },
+    /// Adds a dependency to the toml configuration file and the lock file
+    Add {
+        /// Url of the dependency, e.g. github.com/org/repo
+        url: String,
+        /// Name of the dependency [default: the name of the repository]
+        #[clap(long)]
+        name: Option<String>,
+        /// Revision of the dependency, e.g. a tag
+        #[clap(long, conflicts_with = "branch")]
+        revision: Option<String>,
+        /// Branch of the dependency to follow
+        #[clap(long)]
+        branch: Option<String>,
+        /// Protocol to fetch the dependency with, https or ssh [default: from config.toml]
+        #[clap(long)]
+        protocol: Option<String>,
+        /// Only copy the files the root module imports and their imports
+        #[clap(long)]
+        prune: bool,
+        /// Only copy files matching this policy, e.g. /proto/* (can be repeated)
+        #[clap(long = "allow", value_name = "POLICY")]
+        allow_policies: Vec<String>,
+    },
+    /// Removes a dependency from the toml configuration file, the lock file and the output
+    Remove {
+        /// Name of the dependency
+        name: String,
     },
     /// Creates an init protofetch setup in provided directory and name
     Init {
@@ -71,16 +148,155 @@ pub enum Command {
         #[clap(short, long)]
         name: Option<String>,
     },
+    /// Compiles the fetched proto files without protoc to check that they are complete and valid
+    CheckCompile {
+        /// Write the FileDescriptorSet of the compiled files to this file
+        #[clap(long, value_name = "FILE")]
+        descriptor_set: Option<PathBuf>,
+        /// Additional directory to resolve imports against (can be repeated)
+        #[clap(short = 'I', long = "include", value_name = "DIR")]
+        includes: Vec<PathBuf>,
+    },
+    /// Prints the JSON Schema of a configuration file, for editors to complete and validate it
+    Schema {
+        #[clap(value_enum)]
+        file: SchemaFile,
+    },
     /// Cleans generated proto sources and lock file
     Clean,
     /// Clears cached dependencies.
     /// This will remove all cached dependencies and metadata hence making the next fetch operation slower.
     ClearCache,
+    /// Manages the protofetch cache
+    Cache {
+        #[clap(subcommand)]
+        cmd: CacheCommand,
+    },
+}
+
+#[derive(Debug, Parser)]
+pub enum CacheCommand {
+    /// Removes cached repositories that were not used recently and compacts the others
+    Gc {
+        /// Remove repositories and worktrees not used for this long, e.g. 12h, 30d or 4w
+        #[clap(long, default_value = "30d", value_parser = parse_duration)]
+        max_age: Duration,
+        /// Also remove cached repositories not referenced by the lock file of any of these
+        /// project directories (can be repeated)
+        #[clap(long = "project", value_name = "DIR")]
+        projects: Vec<String>,
+    },
+    /// Lists cached repositories with their size and last use
+    List,
+    /// Shows details about a cached repository
+    Info {
+        /// Dependency url (e.g. github.com/org/repo) or remote url of the repository
+        url: String,
+    },
+    /// Checks cached repositories and worktrees for corruption
+    Verify {
+        /// Quarantine broken repositories, so that they are re-cloned on the next fetch, and
+        /// remove orphaned worktrees
+        #[clap(long)]
+        repair: bool,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum DiffFormat {
+    /// List added, removed and changed files
+    Files,
+    /// Also print a unified diff of every file
+    Unified,
+}
+
+impl From<DiffFormat> for FileDiffMode {
+    fn from(format: DiffFormat) -> Self {
+        match format {
+            DiffFormat::Files => FileDiffMode::Files,
+            DiffFormat::Unified => FileDiffMode::Unified,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SchemaFile {
+    /// The module descriptor, protofetch.toml
+    Descriptor,
+    /// The lock file, protofetch.lock
+    Lock,
+    /// The user configuration, config.toml
+    Config,
+}
+
+impl From<SchemaFile> for SchemaKind {
+    fn from(file: SchemaFile) -> Self {
+        match file {
+            SchemaFile::Descriptor => SchemaKind::Descriptor,
+            SchemaFile::Lock => SchemaKind::LockFile,
+            SchemaFile::Config => SchemaKind::Config,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    Text,
+    Json,
+}
+
+impl From<Format> for OutputFormat {
+    fn from(format: Format) -> Self {
+        match format {
+            Format::Text => OutputFormat::Text,
+            Format::Json => OutputFormat::Json,
+        }
+    }
+}
+
+/// Exit code of a dry run that would change the lock file.
+const EXIT_LOCK_WOULD_CHANGE: i32 = 2;
+
+fn update(
+    protofetch: &Protofetch,
+    lock_update_mode: LockUpdateMode,
+    dry_run: bool,
+) -> Result<(), Box<dyn Error>> {
+    if !dry_run {
+        return protofetch.update(lock_update_mode);
+    }
+    if protofetch.update_dry_run(lock_update_mode)? {
+        std::process::exit(EXIT_LOCK_WOULD_CHANGE);
+    }
+    Ok(())
+}
+
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (amount, unit) = value.split_at(split);
+    let amount: u64 = amount
+        .parse()
+        .map_err(|_| format!("invalid duration {value:?}, expected e.g. 12h, 30d or 4w"))?;
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => {
+            return Err(format!(
+                "invalid duration unit in {value:?}, expected one of s, m, h, d, w"
+            ))
+        }
+    };
+    Ok(Duration::from_secs(amount.saturating_mul(seconds)))
 }
 
 fn main() {
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info"))
-        .target(Target::Stdout)
+        .target(Target::Stderr)
         .format(move |buf, record| {
             use std::io::Write;
 
@@ -102,18 +318,29 @@ fn main() {
         })
         .init();
 
-    if let Err(e) = run() {
+    let cli_args: CliArgs = CliArgs::parse();
+    let format = cli_args.format;
+
+    if let Err(e) = run(cli_args) {
         log::error!("{}", e);
+        if format == Format::Json {
+            let error = serde_json::json!({
+                "error": {
+                    "code": error_code(e.as_ref()),
+                    "message": e.to_string(),
+                }
+            });
+            println!("{:#}", error);
+        }
         std::process::exit(1);
     }
 }
 
-fn run() -> Result<(), Box<dyn Error>> {
-    let cli_args: CliArgs = CliArgs::parse();
-
+fn run(cli_args: CliArgs) -> Result<(), Box<dyn Error>> {
     let mut protofetch = Protofetch::builder()
         .module_file_name(&cli_args.module_location)
-        .lock_file_name(&cli_args.lockfile_location);
+        .lock_file_name(&cli_args.lockfile_location)
+        .output_format(cli_args.format.into());
 
     if let Some(output_directory_name) = &cli_args.output_proto_directory {
         protofetch = protofetch.output_directory_name(output_directory_name)
@@ -133,9 +360,18 @@ fn run() -> Result<(), Box<dyn Error>> {
         }
         protofetch = protofetch.copy_jobs(copy_jobs);
     }
+    if let Some(lock_timeout) = cli_args.lock_timeout {
+        protofetch = protofetch.lock_timeout(Duration::from_secs(lock_timeout));
+    }
 
     match cli_args.cmd {
-        Command::Fetch { locked, force_lock } => {
+        Command::Fetch {
+            locked,
+            force_lock,
+            strict_imports,
+            descriptor_set,
+            includes,
+        } => {
             let lock_mode = if force_lock {
                 warn!("Specifying --force-lock is deprecated, please use \"protofetch update\" instead");
                 LockMode::Recreate
@@ -145,16 +381,40 @@ fn run() -> Result<(), Box<dyn Error>> {
                 LockMode::Update
             };
 
-            protofetch.try_build()?.fetch(lock_mode)
+            let protofetch = protofetch.strict_imports(strict_imports).try_build()?;
+            protofetch.fetch(lock_mode)?;
+            match descriptor_set {
+                Some(descriptor_set) => protofetch.check_compile(&includes, Some(&descriptor_set)),
+                None => Ok(()),
+            }
         }
-        Command::Lock => protofetch.try_build()?.update(LockUpdateMode::Reconcile),
-        Command::Update { deps, precise } => {
+        Command::Lock { dry_run } => {
+            update(&protofetch.try_build()?, LockUpdateMode::Reconcile, dry_run)
+        }
+        Command::Update {
+            deps,
+            precise,
+            check_breaking,
+            deny_breaking,
+            diff,
+            dry_run,
+        } => {
             if precise.is_some() && deps.len() != 1 {
                 return Err("--precise requires exactly one DEP".into());
             }
 
+            let protofetch = protofetch
+                .breaking_changes(if deny_breaking {
+                    BreakingChangePolicy::Deny
+                } else if check_breaking {
+                    BreakingChangePolicy::Report
+                } else {
+                    BreakingChangePolicy::Ignore
+                })
+                .file_diff(diff.map_or(FileDiffMode::Off, Into::into));
+
             if deps.is_empty() {
-                protofetch.try_build()?.update(LockUpdateMode::Full)
+                update(&protofetch.try_build()?, LockUpdateMode::Full, dry_run)
             } else {
                 let updates = match precise {
                     Some(commit_hash) => vec![DependencyUpdate::Precise {
@@ -167,18 +427,59 @@ fn run() -> Result<(), Box<dyn Error>> {
                         .collect(),
                 };
 
-                protofetch
-                    .try_build()?
-                    .update(LockUpdateMode::ReconcileAndUpdate(updates))
+                update(
+                    &protofetch.try_build()?,
+                    LockUpdateMode::ReconcileAndUpdate(updates),
+                    dry_run,
+                )
             }
         }
+        Command::Add {
+            url,
+            name,
+            revision,
+            branch,
+            protocol,
+            prune,
+            allow_policies,
+        } => protofetch.try_build()?.add(NewDependency {
+            url,
+            name,
+            revision,
+            branch,
+            protocol,
+            prune,
+            allow_policies,
+        }),
+        Command::Remove { name } => protofetch.try_build()?.remove(&name),
         Command::Init { directory, name } => protofetch.root(directory).try_build()?.init(name),
         Command::Migrate { directory, name } => protofetch
             .root(&directory)
             .try_build()?
             .migrate(name, directory),
+        Command::CheckCompile {
+            descriptor_set,
+            includes,
+        } => protofetch
+            .try_build()?
+            .check_compile(&includes, descriptor_set.as_deref()),
+        Command::Schema { file } => {
+            println!("{}", json_schema(file.into()));
+            Ok(())
+        }
         Command::Clean => protofetch.try_build()?.clean(),
         Command::ClearCache => protofetch.try_build()?.clear_cache(),
+        Command::Cache { cmd } => match cmd {
+            CacheCommand::Gc { max_age, projects } => {
+                let projects = projects.into_iter().map(PathBuf::from).collect::<Vec<_>>();
+                protofetch.try_build()?.gc_cache(max_age, &projects)
+            }
+            CacheCommand::List => protofetch.try_build()?.list_cache(cli_args.format.into()),
+            CacheCommand::Info { url } => protofetch
+                .try_build()?
+                .cache_info(&url, cli_args.format.into()),
+            CacheCommand::Verify { repair } => protofetch.try_build()?.verify_cache(repair),
+        },
     }
 }
 
@@ -186,7 +487,9 @@ fn run() -> Result<(), Box<dyn Error>> {
 mod tests {
     use clap::Parser;
 
-    use super::{CliArgs, Command};
+    use std::{path::PathBuf, time::Duration};
+
+    use super::{parse_duration, CacheCommand, CliArgs, Command, DiffFormat, Format, SchemaFile};
 
     #[test]
     fn update_accepts_positional_specs_and_precise() {
@@ -198,6 +501,7 @@ mod tests {
             Command::Update {
                 deps: specs,
                 precise,
+                ..
             } => {
                 assert_eq!(specs, vec!["repo1"]);
                 assert_eq!(precise.as_deref(), Some("abc123"));
@@ -214,6 +518,7 @@ mod tests {
             Command::Update {
                 deps: specs,
                 precise,
+                ..
             } => {
                 assert_eq!(specs, vec!["repo1", "repo2"]);
                 assert_eq!(precise, None);
@@ -221,4 +526,179 @@ mod tests {
             other => panic!("unexpected command: {other:?}"),
         }
     }
+
+    #[test]
+    fn update_diff_defaults_to_files() {
+        let args = CliArgs::try_parse_from(["protofetch", "update", "--diff", "repo1"]).unwrap();
+
+        match args.cmd {
+            Command::Update { deps, diff, .. } => {
+                assert_eq!(deps, vec!["repo1"]);
+                assert_eq!(diff, Some(DiffFormat::Files));
+            }
+            other => panic!("unexpected command: {other:?}"),
+        }
+
+        let args = CliArgs::try_parse_from(["protofetch", "update", "--diff=unified"]).unwrap();
+
+        match args.cmd {
+            Command::Update { diff, .. } => assert_eq!(diff, Some(DiffFormat::Unified)),
+            other => panic!("unexpected command: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn add_accepts_either_revision_or_branch() {
+        let args = CliArgs::try_parse_from([
+            "protofetch",
+            "add",
+            "github.com/org/repo",
+            "--revision",
+            "v1.0.0",
+            "--allow",
+            "/proto/*",
+            "--allow",
+            "/api/*",
+        ])
+        .unwrap();
+
+        match args.cmd {
+            Command::Add {
+                url,
+                revision,
+                branch,
+                allow_policies,
+                ..
+            } => {
+                assert_eq!(url, "github.com/org/repo");
+                assert_eq!(revision.as_deref(), Some("v1.0.0"));
+                assert_eq!(branch, None);
+                assert_eq!(allow_policies, vec!["/proto/*", "/api/*"]);
+            }
+            other => panic!("unexpected command: {other:?}"),
+        }
+
+        assert!(CliArgs::try_parse_from([
+            "protofetch",
+            "add",
+            "github.com/org/repo",
+            "--revision",
+            "v1.0.0",
+            "--branch",
+            "main",
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn fetch_includes_require_a_descriptor_set() {
+        assert!(CliArgs::try_parse_from(["protofetch", "fetch", "-I", "third_party"]).is_err());
+
+        let args = CliArgs::try_parse_from([
+            "protofetch",
+            "fetch",
+            "--descriptor-set",
+            "out.binpb",
+            "-I",
+            "third_party",
+        ])
+        .unwrap();
+
+        match args.cmd {
+            Command::Fetch {
+                descriptor_set,
+                includes,
+                ..
+            } => {
+                assert_eq!(descriptor_set, Some(PathBuf::from("out.binpb")));
+                assert_eq!(includes, vec![PathBuf::from("third_party")]);
+            }
+            other => panic!("unexpected command: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn cache_gc_accepts_max_age_and_projects() {
+        let args = CliArgs::try_parse_from([
+            "protofetch",
+            "cache",
+            "gc",
+            "--max-age",
+            "2w",
+            "--project",
+            "a",
+            "--project",
+            "b",
+        ])
+        .unwrap();
+
+        match args.cmd {
+            Command::Cache {
+                cmd: CacheCommand::Gc { max_age, projects },
+            } => {
+                assert_eq!(max_age, Duration::from_secs(14 * 24 * 60 * 60));
+                assert_eq!(projects, vec!["a", "b"]);
+            }
+            other => panic!("unexpected command: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn parse_duration_rejects_unknown_units() {
+        assert_eq!(parse_duration("90s"), Ok(Duration::from_secs(90)));
+        assert_eq!(
+            parse_duration("3d"),
+            Ok(Duration::from_secs(3 * 24 * 60 * 60))
+        );
+        assert!(parse_duration("3").is_err());
+        assert!(parse_duration("3y").is_err());
+        assert!(parse_duration("d").is_err());
+    }
+
+    #[test]
+    fn cache_info_accepts_url_and_format() {
+        let args = CliArgs::try_parse_from([
+            "protofetch",
+            "cache",
+            "info",
+            "github.com/org/repo",
+            "--format",
+            "json",
+        ])
+        .unwrap();
+
+        match args.cmd {
+            Command::Cache {
+                cmd: CacheCommand::Info { url },
+            } => assert_eq!(url, "github.com/org/repo"),
+            other => panic!("unexpected command: {other:?}"),
+        }
+        assert_eq!(args.format, Format::Json);
+    }
+
+    #[test]
+    fn format_is_accepted_before_and_after_the_command() {
+        let args = CliArgs::try_parse_from(["protofetch", "--format", "json", "fetch"]).unwrap();
+        assert_eq!(args.format, Format::Json);
+
+        let args = CliArgs::try_parse_from(["protofetch", "update", "--format=json"]).unwrap();
+        assert_eq!(args.format, Format::Json);
+
+        let args = CliArgs::try_parse_from(["protofetch", "clean"]).unwrap();
+        assert_eq!(args.format, Format::Text);
+    }
+
+    #[test]
+    fn schema_requires_a_file() {
+        let args = CliArgs::try_parse_from(["protofetch", "schema", "lock"]).unwrap();
+        assert!(matches!(
+            args.cmd,
+            Command::Schema {
+                file: SchemaFile::Lock
+            }
+        ));
+
+        assert!(CliArgs::try_parse_from(["protofetch", "schema"]).is_err());
+        assert!(CliArgs::try_parse_from(["protofetch", "schema", "protofetch.toml"]).is_err());
+    }
 }
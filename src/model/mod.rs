@@ -1,4 +1,4 @@
-use std::num::ParseIntError;
+use std::{fmt::Display, num::ParseIntError, path::PathBuf};
 use thiserror::Error;
 
 pub mod protodep;
@@ -10,6 +10,8 @@ pub enum ParseError {
     IO(#[from] std::io::Error),
     #[error("TOML parsing error: {0}")]
     Toml(#[from] toml::de::Error),
+    #[error("TOML parsing error: {0}")]
+    TomlEdit(#[from] toml_edit::TomlError),
     #[error("Parse error")]
     Parse(#[from] ParseIntError),
     #[error("Invalid protocol: {0}")]
@@ -24,6 +26,57 @@ pub enum ParseError {
     UnsupportedLockFileVersion(toml::Value),
     #[error("Old lock file version {0}, consider running \"protofetch update\"")]
     OldLockFileVersion(i64),
+    #[error("Output prefix `{0}` must be a relative path without `.` or `..`")]
+    InvalidOutputPrefix(String),
     #[error("Regex error: {0}")]
     Regex(#[from] regex_lite::Error),
+    #[error("Unknown key `{key}` in {table}{}", suggestion_hint(.suggestion))]
+    UnknownKey {
+        key: String,
+        /// Description of the table the key is in, e.g. ``dependency `api` ``.
+        table: String,
+        suggestion: Option<String>,
+    },
+    #[error("{location}: {error}")]
+    Located {
+        location: Location,
+        error: Box<ParseError>,
+    },
+}
+
+pub(crate) fn suggestion_hint(suggestion: &Option<String>) -> String {
+    match suggestion {
+        Some(suggestion) => format!(", did you mean `{}`?", suggestion),
+        None => String::new(),
+    }
+}
+
+/// Where in a configuration file an error was found. Lines and columns start at 1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    pub file: Option<PathBuf>,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Location {
+    /// The location of the byte `offset` of `text`.
+    pub fn from_offset(file: Option<PathBuf>, text: &str, offset: usize) -> Location {
+        let before = &text[..offset.min(text.len())];
+        let line_start = before.rfind('\n').map_or(0, |index| index + 1);
+        Location {
+            file,
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+        }
+    }
+}
+
+impl Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.file {
+            Some(file) => write!(f, "{}:{}:{}", file.display(), self.line, self.column),
+            None => write!(f, "line {}, column {}", self.line, self.column),
+        }
+    }
 }
@@ -98,6 +98,8 @@ impl ProtodepDescriptor {
             name: ModuleName::from("generated"),
             description: Some("Generated from protodep file".to_string()),
             proto_out_dir: self.proto_out_dir.into(),
+            output_layout: Default::default(),
+            imports: Default::default(),
             dependencies,
         })
     }
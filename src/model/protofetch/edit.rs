@@ -0,0 +1,182 @@
+use std::str::FromStr;
+
+use toml_edit::{Array, DocumentMut, Item, Table};
+
+use crate::model::ParseError;
+
+use super::{Descriptor, DESCRIPTOR_KEYS};
+
+/// A module descriptor that is edited in place, keeping the formatting and comments of the parts
+/// that are not changed.
+pub struct DescriptorDocument {
+    document: DocumentMut,
+}
+
+/// The keys of a dependency table added with [`DescriptorDocument::add_dependency`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DependencyTable {
+    pub url: String,
+    pub protocol: Option<String>,
+    pub revision: Option<String>,
+    pub branch: Option<String>,
+    pub prune: bool,
+    pub allow_policies: Vec<String>,
+}
+
+impl DescriptorDocument {
+    pub fn from_toml_str(data: &str) -> Result<DescriptorDocument, ParseError> {
+        Ok(DescriptorDocument {
+            document: DocumentMut::from_str(data)?,
+        })
+    }
+
+    /// Parses the edited document.
+    pub fn descriptor(&self) -> Result<Descriptor, ParseError> {
+        Descriptor::from_toml_str(&self.document.to_string())
+    }
+
+    /// Whether `name` is a dependency or a module setting.
+    pub fn contains_key(&self, name: &str) -> bool {
+        self.document.contains_key(name) || DESCRIPTOR_KEYS.contains(&name)
+    }
+
+    /// Appends the table of a dependency named `name`, replacing an existing one.
+    pub fn add_dependency(&mut self, name: &str, dependency: &DependencyTable) {
+        let mut table = Table::new();
+        table.insert("url", dependency.url.as_str().into());
+        if let Some(protocol) = &dependency.protocol {
+            table.insert("protocol", protocol.as_str().into());
+        }
+        if let Some(revision) = &dependency.revision {
+            table.insert("revision", revision.as_str().into());
+        }
+        if let Some(branch) = &dependency.branch {
+            table.insert("branch", branch.as_str().into());
+        }
+        if dependency.prune {
+            table.insert("prune", true.into());
+        }
+        if !dependency.allow_policies.is_empty() {
+            let policies = dependency
+                .allow_policies
+                .iter()
+                .map(String::as_str)
+                .collect::<Array>();
+            table.insert("allow_policies", policies.into());
+        }
+        self.document.insert(name, Item::Table(table));
+    }
+
+    /// Removes the dependency named `name` along with the comments before it. Returns whether
+    /// there was such a dependency.
+    pub fn remove_dependency(&mut self, name: &str) -> bool {
+        if DESCRIPTOR_KEYS.contains(&name)
+            || !self.document.get(name).is_some_and(Item::is_table_like)
+        {
+            return false;
+        }
+        self.document.remove(name).is_some()
+    }
+}
+
+impl std::fmt::Display for DescriptorDocument {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.document)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn add_dependency_keeps_formatting_and_comments() {
+        let mut document = DescriptorDocument::from_toml_str(
+            r#"# The module
+name = "module"  # inline comment
+
+# Shared types
+[shared]
+url   = "github.com/org/shared"
+revision = "v1.0.0"
+"#,
+        )
+        .unwrap();
+
+        document.add_dependency(
+            "api",
+            &DependencyTable {
+                url: "github.com/org/api".to_owned(),
+                branch: Some("main".to_owned()),
+                prune: true,
+                allow_policies: vec!["/api/*".to_owned()],
+                ..Default::default()
+            },
+        );
+
+        assert_eq!(
+            document.to_string(),
+            r#"# The module
+name = "module"  # inline comment
+
+# Shared types
+[shared]
+url   = "github.com/org/shared"
+revision = "v1.0.0"
+
+[api]
+url = "github.com/org/api"
+branch = "main"
+prune = true
+allow_policies = ["/api/*"]
+"#
+        );
+        assert_eq!(document.descriptor().unwrap().dependencies.len(), 2);
+    }
+
+    #[test]
+    fn remove_dependency_keeps_the_other_tables() {
+        let mut document = DescriptorDocument::from_toml_str(
+            r#"name = "module"
+inline = { url = "github.com/org/inline" }
+
+# Shared types
+[shared]
+url = "github.com/org/shared"
+
+# The API
+[api]
+url   = "github.com/org/api"  # pinned below
+revision = "v2"
+"#,
+        )
+        .unwrap();
+
+        assert!(document.remove_dependency("shared"));
+        assert!(document.remove_dependency("inline"));
+        assert!(!document.remove_dependency("shared"));
+        assert!(!document.remove_dependency("name"));
+
+        assert_eq!(
+            document.to_string(),
+            r#"name = "module"
+
+# The API
+[api]
+url   = "github.com/org/api"  # pinned below
+revision = "v2"
+"#
+        );
+    }
+
+    #[test]
+    fn module_settings_are_not_dependency_names() {
+        let document = DescriptorDocument::from_toml_str("name = \"module\"\n").unwrap();
+
+        assert!(document.contains_key("name"));
+        assert!(document.contains_key("proto_out_dir"));
+        assert!(!document.contains_key("api"));
+    }
+}
@@ -1,20 +1,25 @@
 use std::{fmt::Display, path::Path};
 
+use schemars::{schema_for, JsonSchema, Schema};
 use serde::{Deserialize, Serialize};
 
 use crate::model::ParseError;
 
 use super::{Coordinate, ModuleName, Protocol, RevisionSpecification};
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
 pub struct LockFile {
     pub dependencies: Vec<LockedDependency>,
 }
 
 const VERSION: i64 = 2;
 
-#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+/// The lock file of a module, `protofetch.lock` by default.
+#[derive(Debug, Clone, Serialize, PartialEq, Eq, JsonSchema)]
+#[schemars(title = "protofetch.lock")]
 struct VersionedLockFile<'a> {
+    /// Version of the lock file format.
+    #[schemars(range(min = 2, max = 2))]
     pub version: i64,
     #[serde(flatten)]
     pub content: &'a LockFile,
@@ -40,9 +45,50 @@ impl LockFile {
             content: self,
         })
     }
+
+    /// Returns how the dependencies of `new` differ from the ones of this lock file, in the order
+    /// of this lock file followed by the added dependencies.
+    pub fn changes<'a>(&'a self, new: &'a LockFile) -> Vec<LockChange<'a>> {
+        let find = |lock_file: &'a LockFile, name: &ModuleName| {
+            lock_file
+                .dependencies
+                .iter()
+                .find(|dependency| &dependency.name == name)
+        };
+        let mut changes = Vec::new();
+        for old in &self.dependencies {
+            match find(new, &old.name) {
+                None => changes.push(LockChange::Removed(old)),
+                Some(new) if new != old => changes.push(LockChange::Updated { old, new }),
+                Some(_) => {}
+            }
+        }
+        for new in &new.dependencies {
+            if find(self, &new.name).is_none() {
+                changes.push(LockChange::Added(new));
+            }
+        }
+        changes
+    }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+/// The JSON Schema of the lock file.
+pub fn schema() -> Schema {
+    schema_for!(VersionedLockFile<'static>)
+}
+
+/// A difference between the entries of two lock files.
+#[derive(Debug, PartialEq, Eq)]
+pub enum LockChange<'a> {
+    Added(&'a LockedDependency),
+    Removed(&'a LockedDependency),
+    Updated {
+        old: &'a LockedDependency,
+        new: &'a LockedDependency,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, JsonSchema)]
 pub struct LockedDependency {
     pub name: ModuleName,
     #[serde(flatten)]
@@ -52,7 +98,7 @@ pub struct LockedDependency {
     pub commit_hash: String,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, JsonSchema)]
 pub struct LockedCoordinate {
     pub url: String,
     pub protocol: Option<Protocol>,
@@ -148,6 +194,46 @@ mod tests {
         assert_eq!(formatted, text);
     }
 
+    #[test]
+    fn changes_between_lock_files() {
+        let dependency = |name: &str, commit_hash: &str| LockedDependency {
+            name: ModuleName::from(name),
+            commit_hash: commit_hash.to_owned(),
+            coordinate: LockedCoordinate {
+                url: format!("example.com/org/{name}"),
+                protocol: None,
+            },
+            specification: RevisionSpecification::default(),
+        };
+        let old = LockFile {
+            dependencies: vec![
+                dependency("dep1", "hash1"),
+                dependency("dep2", "hash2"),
+                dependency("dep3", "hash3"),
+            ],
+        };
+        let new = LockFile {
+            dependencies: vec![
+                dependency("dep4", "hash4"),
+                dependency("dep3", "hash3"),
+                dependency("dep1", "hash5"),
+            ],
+        };
+
+        assert_eq!(
+            old.changes(&new),
+            vec![
+                LockChange::Updated {
+                    old: &old.dependencies[0],
+                    new: &new.dependencies[2],
+                },
+                LockChange::Removed(&old.dependencies[1]),
+                LockChange::Added(&new.dependencies[0]),
+            ]
+        );
+        assert!(new.changes(&new).is_empty());
+    }
+
     #[test]
     fn load_lock_file_v1() {
         let text = toml::to_string_pretty(&toml! {
@@ -1,10 +1,14 @@
+pub mod edit;
 pub mod lock;
+pub mod schema;
+mod validate;
 
 use regex_lite::Regex;
+use schemars::JsonSchema;
 use serde::{de::Visitor, Deserialize, Deserializer, Serialize, Serializer};
 use std::{
     fmt::{Debug, Display, Write},
-    path::{Path, PathBuf},
+    path::{Component, Path, PathBuf},
     str::FromStr,
 };
 
@@ -12,6 +16,7 @@ use crate::model::ParseError;
 use log::{debug, error};
 use std::{collections::BTreeSet, hash::Hash};
 use toml::{map::Map, Value};
+use validate::SourceMap;
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Ord, PartialOrd)]
 pub struct Coordinate {
@@ -135,7 +140,9 @@ impl Display for Coordinate {
     }
 }
 
-#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy, Serialize, Deserialize, Ord, PartialOrd)]
+#[derive(
+    PartialEq, Eq, Hash, Debug, Clone, Copy, Serialize, Deserialize, Ord, PartialOrd, JsonSchema,
+)]
 pub enum Protocol {
     #[serde(rename = "https")]
     Https,
@@ -254,7 +261,9 @@ impl<'de> Deserialize<'de> for Revision {
     }
 }
 
-#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[derive(
+    Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, JsonSchema,
+)]
 pub struct RevisionSpecification {
     #[serde(skip_serializing_if = "Revision::is_arbitrary", default)]
     pub revision: Revision,
@@ -284,6 +293,8 @@ pub struct Rules {
     pub content_roots: BTreeSet<ContentRoot>,
     pub allow_policies: AllowPolicies,
     pub deny_policies: DenyPolicies,
+    /// Directory of the output the files of the dependency are copied under.
+    pub output_prefix: Option<PathBuf>,
 }
 
 /// A content root path for a repository.
@@ -385,6 +396,15 @@ impl FromStr for FilePolicy {
     }
 }
 
+impl Display for FilePolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FilePolicy::Path(policy) => write!(f, "{}", policy),
+            FilePolicy::Regex(policy) => write!(f, "re://{}", policy),
+        }
+    }
+}
+
 #[derive(Ord, PartialOrd, PartialEq, Eq, Hash, Debug, Clone)]
 /// Describes a policy to filter files or directories based on a policy kind and a path.
 pub struct FilePathPolicy {
@@ -455,6 +475,16 @@ impl FilePathPolicy {
     }
 }
 
+impl Display for FilePathPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.kind {
+            FilePathPolicyKind::File => write!(f, "{}", self.path.display()),
+            FilePathPolicyKind::Prefix => write!(f, "{}", self.path.join("*").display()),
+            FilePathPolicyKind::SubPath => write!(f, "*{}/*", self.path.display()),
+        }
+    }
+}
+
 #[derive(Ord, PartialOrd, PartialEq, Eq, Hash, Debug, Clone)]
 pub enum FilePathPolicyKind {
     /// /path/to/file.proto
@@ -538,7 +568,9 @@ impl Display for FileRegexPolicy {
     }
 }
 
-#[derive(Clone, Hash, Deserialize, Serialize, Debug, PartialEq, Eq, Ord, PartialOrd)]
+#[derive(
+    Clone, Hash, Deserialize, Serialize, Debug, PartialEq, Eq, Ord, PartialOrd, JsonSchema,
+)]
 pub struct ModuleName(String);
 
 impl ModuleName {
@@ -573,11 +605,94 @@ pub struct Dependency {
     pub rules: Rules,
 }
 
+/// How fetched files are arranged in the output directory.
+#[derive(
+    PartialEq,
+    Eq,
+    Hash,
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    Serialize,
+    Deserialize,
+    Ord,
+    PartialOrd,
+    JsonSchema,
+)]
+pub enum OutputLayout {
+    /// Files of all modules are merged by their path relative to the content root.
+    #[default]
+    #[serde(rename = "flat")]
+    Flat,
+    /// Files of each module are placed under a directory named after the module.
+    #[serde(rename = "per-module")]
+    PerModule,
+}
+
+impl Display for OutputLayout {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            OutputLayout::Flat => f.write_str("flat"),
+            OutputLayout::PerModule => f.write_str("per-module"),
+        }
+    }
+}
+
+/// How the imports of fetched files are followed when pruning.
+#[derive(Default, PartialEq, Debug, PartialOrd, Ord, Eq, Clone)]
+pub struct ImportRules {
+    /// Imports provided by the toolchain, which are neither fetched nor reported as unresolved.
+    pub provided_imports: BTreeSet<FilePolicy>,
+    /// Whether `google/protobuf/` imports are fetched from dependencies like any other import.
+    pub fetch_wellknown: bool,
+    /// Whether imports that cannot be resolved fail the fetch instead of being reported.
+    pub strict_imports: bool,
+}
+
+#[allow(clippy::mutable_key_type)]
+impl ImportRules {
+    pub fn is_provided(&self, import: &Path) -> bool {
+        (!self.fetch_wellknown && import.starts_with("google/protobuf"))
+            || self
+                .provided_imports
+                .iter()
+                .any(|policy| policy.contains_file(import))
+    }
+}
+
+/// Top-level keys of the module descriptor that are module settings rather than dependencies.
+pub const DESCRIPTOR_KEYS: &[&str] = &[
+    "name",
+    "description",
+    "proto_out_dir",
+    "output_layout",
+    "provided_imports",
+    "fetch_wellknown",
+    "strict_imports",
+];
+
+/// Keys of a dependency table of the module descriptor.
+pub const DEPENDENCY_KEYS: &[&str] = &[
+    "url",
+    "protocol",
+    "revision",
+    "branch",
+    "prune",
+    "transitive",
+    "content_roots",
+    "allow_policies",
+    "deny_policies",
+    "output_prefix",
+];
+
 #[derive(PartialEq, Debug, PartialOrd, Ord, Eq, Clone)]
 pub struct Descriptor {
     pub name: ModuleName,
     pub description: Option<String>,
     pub proto_out_dir: Option<String>,
+    pub output_layout: OutputLayout,
+    pub imports: ImportRules,
     pub dependencies: Vec<Dependency>,
 }
 
@@ -589,7 +704,7 @@ impl Descriptor {
         );
         let contents = std::fs::read_to_string(path)?;
 
-        let descriptor = Descriptor::from_toml_str(&contents);
+        let descriptor = Descriptor::parse(&contents, Some(path), true);
         if let Err(err) = &descriptor {
             error!("Could not build a valid descriptor from a protofetch toml file: {err}")
         }
@@ -597,36 +712,66 @@ impl Descriptor {
     }
 
     pub fn from_toml_str(data: &str) -> Result<Descriptor, ParseError> {
-        let mut toml_value = toml::from_str::<Map<String, Value>>(data)?;
-
-        let name = toml_value
-            .remove("name")
-            .ok_or_else(|| ParseError::MissingKey("name".to_string()))
-            .and_then(|v| v.try_into::<ModuleName>().map_err(|e| e.into()))?;
+        Descriptor::parse(data, None, true)
+    }
 
-        let description = toml_value
-            .remove("description")
-            .map(|v| v.try_into::<String>())
-            .map_or(Ok(None), |v| v.map(Some))?;
+    /// Parses the descriptor of the fetched dependency `dependency`, only warning about unknown
+    /// keys, which may be misspelled or come from a newer version of protofetch.
+    pub fn from_dependency_toml_str(
+        data: &str,
+        dependency: &ModuleName,
+    ) -> Result<Descriptor, ParseError> {
+        let file = Path::new(&dependency.0).join("protofetch.toml");
+        Descriptor::parse(data, Some(&file), false)
+    }
 
-        let proto_out_dir = toml_value
-            .remove("proto_out_dir")
-            .map(|v| v.try_into::<String>())
-            .map_or(Ok(None), |v| v.map(Some))?;
+    /// Parses a descriptor, rejecting unknown dependency keys if `strict`. Errors carry their
+    /// location in `data`, read from `file`.
+    fn parse(data: &str, file: Option<&Path>, strict: bool) -> Result<Descriptor, ParseError> {
+        let source = SourceMap::parse(data, file)?;
+        source.check_keys(strict)?;
+        let toml_value = toml::from_str::<Map<String, Value>>(data)?;
+
+        let name = source
+            .field::<ModuleName>(&toml_value, &["name"])?
+            .ok_or_else(|| source.locate(&[], ParseError::MissingKey("name".to_string())))?;
+
+        let description = source.field::<String>(&toml_value, &["description"])?;
+
+        let proto_out_dir = source.field::<String>(&toml_value, &["proto_out_dir"])?;
+
+        let output_layout = source
+            .field::<OutputLayout>(&toml_value, &["output_layout"])?
+            .unwrap_or_default();
+
+        let imports = ImportRules {
+            provided_imports: parse_policies(&source, &toml_value, &["provided_imports"])?,
+            fetch_wellknown: source
+                .field::<bool>(&toml_value, &["fetch_wellknown"])?
+                .unwrap_or(false),
+            strict_imports: source
+                .field::<bool>(&toml_value, &["strict_imports"])?
+                .unwrap_or(false),
+        };
 
         let dependencies = toml_value
-            .into_iter()
-            .map(|(k, v)| parse_dependency(k, &v))
+            .iter()
+            .filter(|(k, v)| !DESCRIPTOR_KEYS.contains(&k.as_str()) && v.is_table())
+            .map(|(k, v)| parse_dependency(&source, k, v))
             .collect::<Result<Vec<_>, _>>()?;
 
         Ok(Descriptor {
             name,
             description,
             proto_out_dir,
+            output_layout,
+            imports,
             dependencies,
         })
     }
 
+    /// Serializes the descriptor back to its toml form, such that [`Descriptor::from_toml_str`]
+    /// returns an equal descriptor.
     pub fn into_toml(self) -> Value {
         let mut description = Map::new();
         description.insert("name".to_string(), Value::String(self.name.to_string()));
@@ -636,6 +781,24 @@ impl Descriptor {
         if let Some(proto_out) = self.proto_out_dir {
             description.insert("proto_out_dir".to_string(), Value::String(proto_out));
         }
+        if self.output_layout != OutputLayout::default() {
+            description.insert(
+                "output_layout".to_string(),
+                Value::String(self.output_layout.to_string()),
+            );
+        }
+        if !self.imports.provided_imports.is_empty() {
+            description.insert(
+                "provided_imports".to_string(),
+                policies_into_toml(&self.imports.provided_imports),
+            );
+        }
+        if self.imports.fetch_wellknown {
+            description.insert("fetch_wellknown".to_string(), Value::Boolean(true));
+        }
+        if self.imports.strict_imports {
+            description.insert("strict_imports".to_string(), Value::Boolean(true));
+        }
 
         for d in self.dependencies {
             let mut dependency = Map::new();
@@ -649,61 +812,112 @@ impl Descriptor {
             if let Some(branch) = d.specification.branch {
                 dependency.insert("branch".to_owned(), Value::String(branch));
             }
+            rules_into_toml(d.rules, &mut dependency);
             description.insert(d.name.to_string(), Value::Table(dependency));
         }
         Value::Table(description)
     }
 }
 
-fn parse_dependency(name: String, value: &toml::Value) -> Result<Dependency, ParseError> {
-    let protocol = match value.get("protocol") {
-        None => None,
-        Some(toml) => Some(toml.clone().try_into::<Protocol>()?),
+fn rules_into_toml(rules: Rules, dependency: &mut Map<String, Value>) {
+    if rules.prune {
+        dependency.insert("prune".to_owned(), Value::Boolean(true));
+    }
+    if rules.transitive {
+        dependency.insert("transitive".to_owned(), Value::Boolean(true));
+    }
+    if !rules.content_roots.is_empty() {
+        let content_roots = rules
+            .content_roots
+            .iter()
+            .map(|root| Value::String(root.value.to_string_lossy().into_owned()))
+            .collect();
+        dependency.insert("content_roots".to_owned(), Value::Array(content_roots));
+    }
+    if !rules.allow_policies.policies.is_empty() {
+        dependency.insert(
+            "allow_policies".to_owned(),
+            policies_into_toml(&rules.allow_policies.policies),
+        );
+    }
+    if !rules.deny_policies.policies.is_empty() {
+        dependency.insert(
+            "deny_policies".to_owned(),
+            policies_into_toml(&rules.deny_policies.policies),
+        );
+    }
+    if let Some(prefix) = rules.output_prefix {
+        dependency.insert(
+            "output_prefix".to_owned(),
+            Value::String(prefix.to_string_lossy().into_owned()),
+        );
+    }
+}
+
+#[allow(clippy::mutable_key_type)]
+fn policies_into_toml(policies: &BTreeSet<FilePolicy>) -> Value {
+    Value::Array(
+        policies
+            .iter()
+            .map(|policy| Value::String(policy.to_string()))
+            .collect(),
+    )
+}
+
+fn parse_dependency(
+    source: &SourceMap,
+    name: &str,
+    value: &Value,
+) -> Result<Dependency, ParseError> {
+    let Some(table) = value.as_table() else {
+        return Err(source.locate(&[name], ParseError::MissingKey("url".to_string())));
     };
 
-    let name = ModuleName::new(name);
+    let protocol = source.field::<Protocol>(table, &[name, "protocol"])?;
 
-    let branch = value
-        .get("branch")
-        .map(|v| v.clone().try_into::<String>())
-        .map_or(Ok(None), |v| v.map(Some))?;
+    let branch = source.field::<String>(table, &[name, "branch"])?;
 
-    let coordinate = value
-        .get("url")
-        .ok_or_else(|| ParseError::MissingKey("url".to_string()))
-        .and_then(|x| x.clone().try_into::<String>().map_err(|e| e.into()))
-        .and_then(|url| Coordinate::from_url_protocol(&url, protocol))?;
+    let coordinate = source
+        .field::<String>(table, &[name, "url"])?
+        .ok_or_else(|| source.locate(&[name], ParseError::MissingKey("url".to_string())))
+        .and_then(|url| {
+            Coordinate::from_url_protocol(&url, protocol)
+                .map_err(|error| source.locate(&[name, "url"], error))
+        })?;
 
-    let revision = match value.get("revision") {
-        Some(revision) => parse_revision(revision)?,
+    let revision = match source.field::<String>(table, &[name, "revision"])? {
+        Some(revision) => Revision::Pinned { revision },
         None => Revision::Arbitrary,
     };
 
     let specification = RevisionSpecification { revision, branch };
 
-    let prune = value
-        .get("prune")
-        .map(|v| v.clone().try_into::<bool>())
-        .map_or(Ok(None), |v| v.map(Some))?
+    let prune = source
+        .field::<bool>(table, &[name, "prune"])?
         .unwrap_or(false);
 
-    let content_roots = value
-        .get("content_roots")
-        .map(|v| v.clone().try_into::<Vec<String>>())
-        .map_or(Ok(None), |v| v.map(Some))?
+    let content_roots = source
+        .field::<Vec<String>>(table, &[name, "content_roots"])?
         .unwrap_or_default()
         .into_iter()
         .map(|str| ContentRoot::from_string(&str))
         .collect::<BTreeSet<_>>();
 
-    let transitive = value
-        .get("transitive")
-        .map(|v| v.clone().try_into::<bool>())
-        .map_or(Ok(None), |v| v.map(Some))?
+    let transitive = source
+        .field::<bool>(table, &[name, "transitive"])?
         .unwrap_or(false);
 
-    let allow_policies = AllowPolicies::new(parse_policies(value, "allow_policies")?);
-    let deny_policies = DenyPolicies::new(parse_policies(value, "deny_policies")?);
+    let allow_policies =
+        AllowPolicies::new(parse_policies(source, table, &[name, "allow_policies"])?);
+    let deny_policies = DenyPolicies::new(parse_policies(source, table, &[name, "deny_policies"])?);
+
+    let output_prefix = source
+        .field::<String>(table, &[name, "output_prefix"])?
+        .map(|prefix| {
+            parse_output_prefix(&prefix)
+                .map_err(|error| source.locate(&[name, "output_prefix"], error))
+        })
+        .transpose()?;
 
     let rules = Rules {
         prune,
@@ -711,32 +925,41 @@ fn parse_dependency(name: String, value: &toml::Value) -> Result<Dependency, Par
         content_roots,
         allow_policies,
         deny_policies,
+        output_prefix,
     };
 
     Ok(Dependency {
-        name,
+        name: ModuleName::from(name),
         coordinate,
         specification,
         rules,
     })
 }
 
-fn parse_policies(toml: &Value, source: &str) -> Result<BTreeSet<FilePolicy>, ParseError> {
-    toml.get(source)
-        .map(|v| v.clone().try_into::<Vec<String>>())
-        .map_or(Ok(None), |v| v.map(Some))?
+fn parse_policies(
+    source: &SourceMap,
+    table: &Map<String, Value>,
+    path: &[&str],
+) -> Result<BTreeSet<FilePolicy>, ParseError> {
+    source
+        .field::<Vec<String>>(table, path)?
         .unwrap_or_default()
         .into_iter()
-        .map(TryFrom::try_from)
+        .map(|policy| FilePolicy::try_from(policy).map_err(|error| source.locate(path, error)))
         .collect::<Result<BTreeSet<_>, _>>()
 }
 
-fn parse_revision(value: &toml::Value) -> Result<Revision, ParseError> {
-    let revstring = value.clone().try_into::<String>()?;
-
-    Ok(Revision::Pinned {
-        revision: revstring,
-    })
+fn parse_output_prefix(prefix: &str) -> Result<PathBuf, ParseError> {
+    let path = Path::new(prefix);
+    let path = path.strip_prefix("/").unwrap_or(path);
+    if path
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+    {
+        Ok(path.to_path_buf())
+    } else {
+        Err(ParseError::InvalidOutputPrefix(prefix.to_owned()))
+    }
 }
 
 #[cfg(test)]
@@ -744,7 +967,9 @@ mod tests {
     use std::str::FromStr;
 
     use super::*;
+    use crate::model::Location;
     use pretty_assertions::assert_eq;
+    use proptest::prelude::*;
 
     #[test]
     fn load_valid_file_one_dep() {
@@ -761,6 +986,8 @@ mod tests {
             name: ModuleName::from("test_file"),
             description: Some("this is a description".to_string()),
             proto_out_dir: Some("./path/to/proto_out".to_string()),
+            output_layout: OutputLayout::Flat,
+            imports: Default::default(),
             dependencies: vec![Dependency {
                 name: ModuleName::new("dependency1".to_string()),
                 coordinate: Coordinate {
@@ -793,6 +1020,8 @@ mod tests {
             name: ModuleName::from("test_file"),
             description: Some("this is a description".to_string()),
             proto_out_dir: Some("./path/to/proto_out".to_string()),
+            output_layout: OutputLayout::Flat,
+            imports: Default::default(),
             dependencies: vec![Dependency {
                 name: ModuleName::new("dependency1".to_string()),
                 coordinate: Coordinate {
@@ -833,6 +1062,8 @@ mod tests {
             name: ModuleName::from("test_file"),
             description: Some("this is a description".to_string()),
             proto_out_dir: Some("./path/to/proto_out".to_string()),
+            output_layout: OutputLayout::Flat,
+            imports: Default::default(),
             dependencies: vec![Dependency {
                 name: ModuleName::new("dependency1".to_string()),
                 coordinate: Coordinate {
@@ -849,6 +1080,7 @@ mod tests {
                     prune: true,
                     content_roots: BTreeSet::from([ContentRoot::from_string("src")]),
                     transitive: false,
+                    output_prefix: None,
                     allow_policies: AllowPolicies::new(BTreeSet::from([
                         FilePolicy::Path(FilePathPolicy::new(
                             FilePathPolicyKind::File,
@@ -869,6 +1101,11 @@ mod tests {
             }],
         };
         assert_eq!(Descriptor::from_toml_str(str).unwrap(), expected);
+        assert_eq!(
+            Descriptor::from_toml_str(&toml::to_string(&expected.clone().into_toml()).unwrap())
+                .unwrap(),
+            expected
+        );
     }
 
     #[test]
@@ -912,6 +1149,8 @@ mod tests {
             name: ModuleName::from("test_file"),
             description: None,
             proto_out_dir: Some("./path/to/proto_out".to_string()),
+            output_layout: OutputLayout::Flat,
+            imports: Default::default(),
             dependencies: vec![
                 Dependency {
                     name: ModuleName::new("dependency1".to_string()),
@@ -977,6 +1216,8 @@ mod tests {
             name: ModuleName::from("test_file"),
             description: None,
             proto_out_dir: Some("./path/to/proto_out".to_string()),
+            output_layout: OutputLayout::Flat,
+            imports: Default::default(),
             dependencies: vec![],
         };
         assert_eq!(Descriptor::from_toml_str(str).unwrap(), expected);
@@ -986,6 +1227,156 @@ mod tests {
         )
     }
 
+    #[test]
+    fn load_output_layout() {
+        let descriptor = Descriptor::from_toml_str(
+            r#"
+            name = "test_file"
+            output_layout = "per-module"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(descriptor.output_layout, OutputLayout::PerModule);
+        assert_eq!(
+            Descriptor::from_toml_str(&toml::to_string(&descriptor.clone().into_toml()).unwrap())
+                .unwrap(),
+            descriptor
+        );
+        assert!(Descriptor::from_toml_str(
+            r#"
+            name = "test_file"
+            output_layout = "nested"
+            "#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn load_import_rules() {
+        let descriptor = Descriptor::from_toml_str(
+            r#"
+            name = "test_file"
+            provided_imports = ["google/api/*", "validate/validate.proto"]
+            fetch_wellknown = true
+            strict_imports = true
+            "#,
+        )
+        .unwrap();
+
+        assert!(descriptor.imports.fetch_wellknown);
+        assert!(descriptor.imports.strict_imports);
+        assert!(descriptor
+            .imports
+            .is_provided(Path::new("google/api/annotations.proto")));
+        assert!(descriptor
+            .imports
+            .is_provided(Path::new("validate/validate.proto")));
+        assert!(!descriptor
+            .imports
+            .is_provided(Path::new("google/protobuf/any.proto")));
+        assert!(ImportRules::default().is_provided(Path::new("google/protobuf/any.proto")));
+    }
+
+    #[test]
+    fn load_output_prefix() {
+        let descriptor = Descriptor::from_toml_str(
+            r#"
+            name = "test_file"
+            [dependency1]
+              url = "github.com/org/repo"
+              output_prefix = "/vendor/acme"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            descriptor.dependencies[0].rules.output_prefix,
+            Some(PathBuf::from("vendor/acme"))
+        );
+        assert!(matches!(
+            Descriptor::from_toml_str(
+                r#"
+                name = "test_file"
+                [dependency1]
+                  url = "github.com/org/repo"
+                  output_prefix = "../outside"
+                "#,
+            ),
+            Err(ParseError::Located {
+                location: Location { line: 5, column: 19, .. },
+                error,
+            }) if matches!(*error, ParseError::InvalidOutputPrefix(_))
+        ));
+    }
+
+    #[test]
+    fn load_invalid_value_is_located() {
+        let error = Descriptor::parse(
+            r#"name = "test_file"
+
+[dependency1]
+url = "github.com/org/repo"
+prune = "yes"
+"#,
+            Some(Path::new("protofetch.toml")),
+            true,
+        )
+        .unwrap_err();
+
+        assert!(
+            error.to_string().starts_with("protofetch.toml:5:1: "),
+            "{error}"
+        );
+        assert!(matches!(
+            Descriptor::from_toml_str("[dependency1]\nurl = \"github.com/org/repo\"\n"),
+            Err(ParseError::Located { error, .. }) if matches!(*error, ParseError::MissingKey(_))
+        ));
+    }
+
+    #[test]
+    fn load_unknown_key() {
+        assert!(matches!(
+            Descriptor::from_toml_str(
+                r#"
+                name = "test_file"
+                [dependency1]
+                  url = "github.com/org/repo"
+                  revison = "1.0.0"
+                "#,
+            ),
+            Err(ParseError::Located { error, .. }) if matches!(
+                &*error,
+                ParseError::UnknownKey { suggestion: Some(suggestion), .. } if suggestion == "revision"
+            )
+        ));
+        assert!(matches!(
+            Descriptor::from_toml_str("name = \"test_file\"\nproto_outdir = \"proto\"\n"),
+            Err(ParseError::Located { error, .. }) if matches!(
+                &*error,
+                ParseError::UnknownKey { suggestion: Some(suggestion), .. } if suggestion == "proto_out_dir"
+            )
+        ));
+
+        let descriptor = Descriptor::from_dependency_toml_str(
+            r#"
+            name = "test_file"
+            proto_outdir = "proto"
+            [dependency1]
+              url = "github.com/org/repo"
+              revison = "1.0.0"
+            "#,
+            &ModuleName::from("upstream"),
+        )
+        .unwrap();
+        assert_eq!(descriptor.proto_out_dir, None);
+        assert_eq!(descriptor.dependencies.len(), 1);
+        assert_eq!(
+            descriptor.dependencies[0].specification.revision,
+            Revision::Arbitrary
+        );
+    }
+
     #[test]
     fn load_invalid_protocol() {
         let str = r#"
@@ -1162,4 +1553,144 @@ mod tests {
     fn test_file_policy_regex_parse_error() {
         FilePolicy::from_str(r"re://_(?:test").unwrap();
     }
+
+    #[test]
+    fn file_policy_display_is_parsable() {
+        for policy in [
+            "/foo/proto/file.proto",
+            "/foo/other/*",
+            "*/some/path/*",
+            r"re://_(?:test|unittest)\.proto",
+        ] {
+            assert_eq!(FilePolicy::from_str(policy).unwrap().to_string(), policy);
+        }
+        assert_eq!(
+            FilePolicy::from_str("foo/other/*").unwrap().to_string(),
+            "/foo/other/*"
+        );
+    }
+
+    fn segment() -> impl Strategy<Value = String> {
+        "[a-z0-9_]{1,8}"
+    }
+
+    fn relative_path() -> impl Strategy<Value = String> {
+        prop::collection::vec(segment(), 1..4).prop_map(|segments| segments.join("/"))
+    }
+
+    fn file_policy() -> impl Strategy<Value = FilePolicy> {
+        prop_oneof![
+            relative_path().prop_map(|path| format!("{path}.proto")),
+            relative_path().prop_map(|path| format!("/{path}.proto")),
+            relative_path().prop_map(|path| format!("/{path}/*")),
+            relative_path().prop_map(|path| format!("*/{path}/*")),
+            r"[a-z_/]{1,10}(\\\.proto)?".prop_map(|regex| format!("re://{regex}")),
+        ]
+        .prop_map(|policy| policy.parse().unwrap())
+    }
+
+    #[allow(clippy::mutable_key_type)]
+    fn rules() -> impl Strategy<Value = Rules> {
+        (
+            any::<bool>(),
+            any::<bool>(),
+            prop::collection::btree_set(relative_path(), 0..3),
+            prop::collection::btree_set(file_policy(), 0..3),
+            prop::collection::btree_set(file_policy(), 0..3),
+            prop::option::of(relative_path()),
+        )
+            .prop_map(
+                |(prune, transitive, content_roots, allow, deny, output_prefix)| Rules {
+                    prune,
+                    transitive,
+                    content_roots: content_roots
+                        .iter()
+                        .map(|root| ContentRoot::from_string(root))
+                        .collect(),
+                    allow_policies: AllowPolicies::new(allow),
+                    deny_policies: DenyPolicies::new(deny),
+                    output_prefix: output_prefix.map(PathBuf::from),
+                },
+            )
+    }
+
+    fn dependency() -> impl Strategy<Value = (Coordinate, RevisionSpecification, Rules)> {
+        let coordinate = (
+            "[a-z]{1,8}\\.com",
+            segment(),
+            segment(),
+            prop::option::of(prop_oneof![Just(Protocol::Https), Just(Protocol::Ssh)]),
+        )
+            .prop_map(|(forge, organization, repository, protocol)| Coordinate {
+                forge,
+                organization,
+                repository,
+                protocol,
+            });
+        let specification = (
+            prop::option::of(any::<String>()),
+            prop::option::of(any::<String>()),
+        )
+            .prop_map(|(revision, branch)| RevisionSpecification {
+                revision: revision.map_or(Revision::Arbitrary, Revision::pinned),
+                branch,
+            });
+        (coordinate, specification, rules())
+    }
+
+    #[allow(clippy::mutable_key_type)]
+    pub(super) fn descriptor() -> impl Strategy<Value = Descriptor> {
+        let name = "[a-z][a-z0-9_-]{0,10}".prop_filter("module setting", |name| {
+            !DESCRIPTOR_KEYS.contains(&name.as_str())
+        });
+        (
+            any::<String>(),
+            prop::option::of(any::<String>()),
+            prop::option::of(any::<String>()),
+            prop_oneof![Just(OutputLayout::Flat), Just(OutputLayout::PerModule)],
+            (
+                prop::collection::btree_set(file_policy(), 0..3),
+                any::<bool>(),
+                any::<bool>(),
+            ),
+            prop::collection::btree_map(name, dependency(), 0..4),
+        )
+            .prop_map(
+                |(
+                    name,
+                    description,
+                    proto_out_dir,
+                    output_layout,
+                    (provided_imports, fetch_wellknown, strict_imports),
+                    dependencies,
+                )| Descriptor {
+                    name: ModuleName::new(name),
+                    description,
+                    proto_out_dir,
+                    output_layout,
+                    imports: ImportRules {
+                        provided_imports,
+                        fetch_wellknown,
+                        strict_imports,
+                    },
+                    dependencies: dependencies
+                        .into_iter()
+                        .map(|(name, (coordinate, specification, rules))| Dependency {
+                            name: ModuleName::new(name),
+                            coordinate,
+                            specification,
+                            rules,
+                        })
+                        .collect(),
+                },
+            )
+    }
+
+    proptest! {
+        #[test]
+        fn into_toml_round_trips(descriptor in descriptor()) {
+            let toml = toml::to_string(&descriptor.clone().into_toml()).unwrap();
+            prop_assert_eq!(Descriptor::from_toml_str(&toml).unwrap(), descriptor);
+        }
+    }
 }
@@ -0,0 +1,231 @@
+//! JSON Schema of the module descriptor, for editors to complete and validate `protofetch.toml`.
+//!
+//! The descriptor is not deserialized with serde, so its schema is derived from mirror types of
+//! its toml form, which list the same keys as [`DESCRIPTOR_KEYS`](super::DESCRIPTOR_KEYS) and
+//! [`DEPENDENCY_KEYS`](super::DEPENDENCY_KEYS), the only keys the parser accepts. Toml has no
+//! null, so optional keys are described by the schema of their value.
+
+use std::{borrow::Cow, collections::BTreeMap};
+
+use schemars::{json_schema, schema_for, JsonSchema, Schema, SchemaGenerator};
+use serde::Serialize;
+
+use super::{Dependency, Descriptor, FilePolicy, OutputLayout, Protocol, Revision};
+
+/// Matches every policy rule [`FilePolicy::from_str`](std::str::FromStr::from_str) accepts.
+const POLICY_PATTERN: &str = r"^re://|/\*$|\.proto$";
+
+/// The JSON Schema of the module descriptor.
+pub fn schema() -> Schema {
+    schema_for!(DescriptorToml)
+}
+
+impl JsonSchema for Revision {
+    fn schema_name() -> Cow<'static, str> {
+        "Revision".into()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        json_schema!({
+            "type": "string",
+            "description": "A tag or a commit hash",
+        })
+    }
+}
+
+/// The module descriptor. Every table that is not a module setting is a dependency.
+#[derive(Serialize, JsonSchema)]
+#[schemars(rename = "Descriptor", title = "protofetch.toml")]
+struct DescriptorToml {
+    /// A name of the defined module
+    name: String,
+    /// A description of the module
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(with = "String")]
+    description: Option<String>,
+    /// Where to put fetched proto files, `proto_src` by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(with = "String")]
+    proto_out_dir: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(with = "OutputLayout")]
+    output_layout: Option<OutputLayout>,
+    /// Policy rules for imports provided by the toolchain, which pruning does not fetch
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    provided_imports: Vec<Policy>,
+    /// Whether pruning fetches `google/protobuf/` imports from dependencies
+    #[serde(default, skip_serializing_if = "is_false")]
+    fetch_wellknown: bool,
+    /// Whether imports that pruning cannot resolve fail the fetch
+    #[serde(default, skip_serializing_if = "is_false")]
+    strict_imports: bool,
+    #[serde(flatten)]
+    dependencies: BTreeMap<String, DependencyToml>,
+}
+
+#[derive(Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+#[schemars(rename = "Dependency")]
+struct DependencyToml {
+    /// An address of the repository to checkout protobuf files from, e.g. `github.com/org/repo`
+    url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(with = "Protocol")]
+    protocol: Option<Protocol>,
+    /// A tag or a commit hash
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(with = "String")]
+    revision: Option<String>,
+    /// A branch to checkout, fetches last commit
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(with = "String")]
+    branch: Option<String>,
+    /// Whether to follow proto imports instead of copying by module dependency alone
+    #[serde(default, skip_serializing_if = "is_false")]
+    prune: bool,
+    /// Flags this dependency as transitive
+    #[serde(default, skip_serializing_if = "is_false")]
+    transitive: bool,
+    /// Which subdirectories to import from
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    content_roots: Vec<String>,
+    /// Allow policy rules
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    allow_policies: Vec<Policy>,
+    /// Deny policy rules
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    deny_policies: Vec<Policy>,
+    /// A directory of the output to copy the files of this dependency under
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(with = "String")]
+    output_prefix: Option<String>,
+}
+
+/// A policy rule: `/prefix/*`, `*/subpath/*`, `/path/to/file.proto` or `re://<regex>`
+#[derive(Serialize, JsonSchema)]
+struct Policy(#[schemars(regex(pattern = POLICY_PATTERN))] String);
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+impl From<Descriptor> for DescriptorToml {
+    fn from(descriptor: Descriptor) -> Self {
+        let Descriptor {
+            name,
+            description,
+            proto_out_dir,
+            output_layout,
+            imports,
+            dependencies,
+        } = descriptor;
+        DescriptorToml {
+            name: name.to_string(),
+            description,
+            proto_out_dir,
+            output_layout: (output_layout != OutputLayout::default()).then_some(output_layout),
+            provided_imports: policies(imports.provided_imports),
+            fetch_wellknown: imports.fetch_wellknown,
+            strict_imports: imports.strict_imports,
+            dependencies: dependencies
+                .into_iter()
+                .map(|dependency| (dependency.name.to_string(), dependency.into()))
+                .collect(),
+        }
+    }
+}
+
+impl From<Dependency> for DependencyToml {
+    fn from(dependency: Dependency) -> Self {
+        let Dependency {
+            name: _,
+            coordinate,
+            specification,
+            rules,
+        } = dependency;
+        DependencyToml {
+            url: coordinate.to_string(),
+            protocol: coordinate.protocol,
+            revision: match specification.revision {
+                Revision::Pinned { revision } => Some(revision),
+                Revision::Arbitrary => None,
+            },
+            branch: specification.branch,
+            prune: rules.prune,
+            transitive: rules.transitive,
+            content_roots: rules
+                .content_roots
+                .into_iter()
+                .map(|root| root.value.to_string_lossy().into_owned())
+                .collect(),
+            allow_policies: policies(rules.allow_policies.policies),
+            deny_policies: policies(rules.deny_policies.policies),
+            output_prefix: rules
+                .output_prefix
+                .map(|prefix| prefix.to_string_lossy().into_owned()),
+        }
+    }
+}
+
+fn policies(policies: impl IntoIterator<Item = FilePolicy>) -> Vec<Policy> {
+    policies
+        .into_iter()
+        .map(|policy| Policy(policy.to_string()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use pretty_assertions::assert_eq;
+    use proptest::prelude::*;
+    use regex_lite::Regex;
+
+    use super::*;
+    use crate::model::protofetch::{tests::descriptor, DEPENDENCY_KEYS, DESCRIPTOR_KEYS};
+
+    fn property_names(schema: &serde_json::Value) -> BTreeSet<&str> {
+        schema["properties"]
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(String::as_str)
+            .collect()
+    }
+
+    #[test]
+    fn schema_lists_the_keys_the_parser_accepts() {
+        let schema = schema().to_value();
+
+        assert_eq!(
+            property_names(&schema),
+            DESCRIPTOR_KEYS.iter().copied().collect()
+        );
+        assert_eq!(
+            property_names(&schema["$defs"]["Dependency"]),
+            DEPENDENCY_KEYS.iter().copied().collect()
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn schema_types_serialize_like_the_descriptor(descriptor in descriptor()) {
+            prop_assert_eq!(
+                toml::Value::try_from(DescriptorToml::from(descriptor.clone())).unwrap(),
+                descriptor.into_toml()
+            );
+        }
+
+        #[test]
+        fn policy_pattern_matches_every_accepted_policy(
+            policy in r"(re://)?[*/]?[a-z./*\n]{0,8}(/\*|\.proto)?"
+        ) {
+            let schema = schema().to_value();
+            let pattern = Regex::new(schema["$defs"]["Policy"]["pattern"].as_str().unwrap()).unwrap();
+            if policy.parse::<FilePolicy>().is_ok() {
+                prop_assert!(pattern.is_match(&policy), "{:?} is not matched", policy);
+            }
+        }
+    }
+}
@@ -0,0 +1,245 @@
+use std::path::Path;
+
+use log::warn;
+use serde::de::DeserializeOwned;
+use toml::{de::DeTable, map::Map, Spanned, Value};
+
+use crate::model::{suggestion_hint, Location, ParseError};
+
+use super::{DEPENDENCY_KEYS, DESCRIPTOR_KEYS};
+
+/// The parsed text of a module descriptor, used to check its keys and to locate errors.
+pub(super) struct SourceMap<'a> {
+    file: Option<&'a Path>,
+    text: &'a str,
+    document: Spanned<DeTable<'a>>,
+}
+
+impl<'a> SourceMap<'a> {
+    pub fn parse(text: &'a str, file: Option<&'a Path>) -> Result<SourceMap<'a>, ParseError> {
+        match DeTable::parse(text) {
+            Ok(document) => Ok(SourceMap {
+                file,
+                text,
+                document,
+            }),
+            Err(error) => {
+                let offset = error.span().map_or(0, |span| span.start);
+                Err(ParseError::Located {
+                    location: Location::from_offset(file.map(Path::to_path_buf), text, offset),
+                    error: Box::new(error.into()),
+                })
+            }
+        }
+    }
+
+    /// Checks that every key is either a module setting or a dependency setting.
+    ///
+    /// Unknown keys are rejected if `strict`, and only warned about and ignored otherwise, as in
+    /// the descriptors of fetched dependencies. Module settings inside a dependency table, which
+    /// were most likely written after the first dependency table by mistake, count as unknown.
+    pub fn check_keys(&self, strict: bool) -> Result<(), ParseError> {
+        for (key, value) in self.document.get_ref() {
+            let key = key.get_ref().as_ref();
+            if DESCRIPTOR_KEYS.contains(&key) {
+                continue;
+            }
+            let Some(dependency) = value.get_ref().as_table() else {
+                self.unknown_key(
+                    strict,
+                    &[key],
+                    "the module".to_owned(),
+                    suggest(key, DESCRIPTOR_KEYS),
+                )?;
+                continue;
+            };
+            for field in dependency.keys() {
+                let field = field.get_ref().as_ref();
+                if DEPENDENCY_KEYS.contains(&field) {
+                    continue;
+                }
+                if DESCRIPTOR_KEYS.contains(&field) {
+                    self.unknown_key(
+                        strict,
+                        &[key, field],
+                        format!(
+                            "dependency `{}` (module settings must come before the first dependency table)",
+                            key
+                        ),
+                        None,
+                    )?;
+                    continue;
+                }
+                self.unknown_key(
+                    strict,
+                    &[key, field],
+                    format!("dependency `{}`", key),
+                    suggest(field, DEPENDENCY_KEYS),
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Rejects the unknown key at `path` if `strict`, and warns that it is ignored otherwise.
+    fn unknown_key(
+        &self,
+        strict: bool,
+        path: &[&str],
+        table: String,
+        suggestion: Option<String>,
+    ) -> Result<(), ParseError> {
+        let key = path.last().expect("path of a key");
+        if strict {
+            return Err(self.locate(
+                path,
+                ParseError::UnknownKey {
+                    key: key.to_string(),
+                    table,
+                    suggestion,
+                },
+            ));
+        }
+        warn!(
+            "{}: Ignoring unknown key `{}` in {}{}",
+            self.location(path),
+            key,
+            table,
+            suggestion_hint(&suggestion)
+        );
+        Ok(())
+    }
+
+    /// Converts the value of the key `path.last()` of `table`, which is the table at the rest of
+    /// `path`, attaching the location of the key to an error.
+    pub fn field<T: DeserializeOwned>(
+        &self,
+        table: &Map<String, Value>,
+        path: &[&str],
+    ) -> Result<Option<T>, ParseError> {
+        let key = path.last().expect("path of a field");
+        table
+            .get(*key)
+            .map(|value| {
+                value
+                    .clone()
+                    .try_into::<T>()
+                    .map_err(|error| self.locate(path, error))
+            })
+            .transpose()
+    }
+
+    /// Attaches the location of the key at `path`, or of the closest table containing it, to
+    /// `error`.
+    pub fn locate(&self, path: &[&str], error: impl Into<ParseError>) -> ParseError {
+        ParseError::Located {
+            location: self.location(path),
+            error: Box::new(error.into()),
+        }
+    }
+
+    fn location(&self, path: &[&str]) -> Location {
+        let mut offset = 0;
+        let mut table = Some(self.document.get_ref());
+        for name in path {
+            let Some((key, value)) = table.and_then(|table| {
+                table
+                    .iter()
+                    .find(|(key, _)| key.get_ref().as_ref() == *name)
+            }) else {
+                break;
+            };
+            offset = key.span().start;
+            table = value.get_ref().as_table();
+        }
+        Location::from_offset(self.file.map(Path::to_path_buf), self.text, offset)
+    }
+}
+
+/// The known key most similar to `key`, if any is similar enough.
+fn suggest(key: &str, known: &[&str]) -> Option<String> {
+    known
+        .iter()
+        .map(|candidate| (strsim::jaro_winkler(key, candidate), candidate))
+        .filter(|(similarity, _)| *similarity > 0.8)
+        .max_by(|(a, _), (b, _)| a.total_cmp(b))
+        .map(|(_, candidate)| candidate.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    fn check(text: &str) -> String {
+        SourceMap::parse(text, Some(Path::new("protofetch.toml")))
+            .and_then(|source| source.check_keys(true))
+            .unwrap_err()
+            .to_string()
+    }
+
+    #[test]
+    fn unknown_dependency_key_is_located_with_a_suggestion() {
+        assert_eq!(
+            check(
+                r#"name = "module"
+
+[api]
+url = "github.com/org/api"
+revison = "v1"
+"#
+            ),
+            "protofetch.toml:5:1: Unknown key `revison` in dependency `api`, did you mean `revision`?"
+        );
+        assert_eq!(
+            check(
+                r#"name = "module"
+api = { url = "github.com/org/api", allow_policy = ["/api/*"] }
+"#
+            ),
+            "protofetch.toml:2:37: Unknown key `allow_policy` in dependency `api`, did you mean `allow_policies`?"
+        );
+    }
+
+    #[test]
+    fn unknown_keys_are_allowed_when_not_strict() {
+        let source = SourceMap::parse(
+            r#"name = "module"
+proto_outdir = "proto"
+
+[api]
+url = "github.com/org/api"
+revison = "v1"
+"#,
+            None,
+        )
+        .unwrap();
+
+        source.check_keys(false).unwrap();
+    }
+
+    #[test]
+    fn unknown_module_keys_are_rejected() {
+        assert_eq!(
+            check(
+                r#"name = "module"
+proto_outdir = "proto"
+"#
+            ),
+            "protofetch.toml:2:1: Unknown key `proto_outdir` in the module, did you mean `proto_out_dir`?"
+        );
+        assert_eq!(
+            check(
+                r#"name = "module"
+
+[api]
+url = "github.com/org/api"
+proto_out_dir = "proto"
+"#
+            ),
+            "protofetch.toml:5:1: Unknown key `proto_out_dir` in dependency `api` (module settings must come before the first dependency table)"
+        );
+        assert_eq!(suggest("version", DESCRIPTOR_KEYS), None);
+    }
+}
@@ -0,0 +1,216 @@
+use std::ops::Range;
+
+use super::ProtoParseError;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Token {
+    /// An identifier or keyword. Dots are separate symbols.
+    Ident(String),
+    /// A string literal with escapes resolved.
+    String(String),
+    /// A numeric literal, kept as written.
+    Number(String),
+    /// Any other single character, e.g. `;`, `=`, `{` or `.`.
+    Symbol(char),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Spanned {
+    pub token: Token,
+    /// Byte range of the token in the source.
+    pub span: Range<usize>,
+}
+
+/// Splits protobuf source into tokens, skipping whitespace and comments.
+pub fn tokenize(source: &str) -> Result<Vec<Spanned>, ProtoParseError> {
+    Lexer {
+        source,
+        chars: source.char_indices().peekable(),
+    }
+    .collect()
+}
+
+struct Lexer<'a> {
+    source: &'a str,
+    chars: std::iter::Peekable<std::str::CharIndices<'a>>,
+}
+
+impl Lexer<'_> {
+    fn offset(&mut self) -> usize {
+        self.chars
+            .peek()
+            .map(|(offset, _)| *offset)
+            .unwrap_or(self.source.len())
+    }
+
+    fn line(&self, offset: usize) -> usize {
+        self.source[..offset].matches('\n').count() + 1
+    }
+
+    fn take_while(&mut self, predicate: impl Fn(char) -> bool) {
+        while self.chars.next_if(|(_, c)| predicate(*c)).is_some() {}
+    }
+
+    /// Skips whitespace and comments, returns an error for an unterminated block comment.
+    fn skip_trivia(&mut self) -> Result<(), ProtoParseError> {
+        loop {
+            self.take_while(char::is_whitespace);
+            let start = self.offset();
+            let rest = &self.source[start..];
+            if rest.starts_with("//") {
+                self.take_while(|c| c != '\n');
+            } else if let Some(comment) = rest.strip_prefix("/*") {
+                let end =
+                    comment
+                        .find("*/")
+                        .ok_or_else(|| ProtoParseError::UnterminatedComment {
+                            line: self.line(start),
+                        })?;
+                let end = start + 2 + end + 2;
+                while self.chars.next_if(|(offset, _)| *offset < end).is_some() {}
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    fn string(&mut self, start: usize, quote: char) -> Result<String, ProtoParseError> {
+        let unterminated = |lexer: &Self| ProtoParseError::UnterminatedString {
+            line: lexer.line(start),
+        };
+        let mut value = String::new();
+        loop {
+            let (_, c) = self.chars.next().ok_or_else(|| unterminated(self))?;
+            match c {
+                '\n' => return Err(unterminated(self)),
+                c if c == quote => return Ok(value),
+                '\\' => {
+                    let (_, escaped) = self.chars.next().ok_or_else(|| unterminated(self))?;
+                    match escaped {
+                        'n' => value.push('\n'),
+                        't' => value.push('\t'),
+                        'r' => value.push('\r'),
+                        'a' => value.push('\x07'),
+                        'b' => value.push('\x08'),
+                        'f' => value.push('\x0c'),
+                        'v' => value.push('\x0b'),
+                        'x' | 'X' => value.push(self.escaped_char(16, 2)),
+                        '0'..='7' => {
+                            let mut code = escaped.to_digit(8).unwrap();
+                            for _ in 0..2 {
+                                match self.chars.next_if(|(_, c)| c.is_digit(8)) {
+                                    Some((_, digit)) => {
+                                        code = code * 8 + digit.to_digit(8).unwrap()
+                                    }
+                                    None => break,
+                                }
+                            }
+                            value.push(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER));
+                        }
+                        'u' => value.push(self.escaped_char(16, 4)),
+                        'U' => value.push(self.escaped_char(16, 8)),
+                        other => value.push(other),
+                    }
+                }
+                c => value.push(c),
+            }
+        }
+    }
+
+    fn escaped_char(&mut self, radix: u32, max_digits: usize) -> char {
+        let mut code = 0;
+        for _ in 0..max_digits {
+            match self.chars.next_if(|(_, c)| c.is_digit(radix)) {
+                Some((_, digit)) => code = code * radix + digit.to_digit(radix).unwrap(),
+                None => break,
+            }
+        }
+        char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER)
+    }
+}
+
+impl Iterator for Lexer<'_> {
+    type Item = Result<Spanned, ProtoParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Err(error) = self.skip_trivia() {
+            // Nothing sensible can follow
+            while self.chars.next().is_some() {}
+            return Some(Err(error));
+        }
+        let (start, c) = self.chars.next()?;
+        let token = match c {
+            '"' | '\'' => match self.string(start, c) {
+                Ok(value) => Token::String(value),
+                Err(error) => {
+                    while self.chars.next().is_some() {}
+                    return Some(Err(error));
+                }
+            },
+            c if c.is_alphabetic() || c == '_' => {
+                self.take_while(|c| c.is_alphanumeric() || c == '_');
+                Token::Ident(self.source[start..self.offset()].to_owned())
+            }
+            c if c.is_ascii_digit() => {
+                self.take_while(|c| c.is_alphanumeric() || c == '.' || c == '_');
+                Token::Number(self.source[start..self.offset()].to_owned())
+            }
+            c => Token::Symbol(c),
+        };
+        let end = self.offset();
+        Some(Ok(Spanned {
+            token,
+            span: start..end,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(source: &str) -> Vec<Token> {
+        tokenize(source)
+            .unwrap()
+            .into_iter()
+            .map(|spanned| spanned.token)
+            .collect()
+    }
+
+    #[test]
+    fn comments_and_whitespace_are_skipped() {
+        assert_eq!(
+            tokens("  // line\n/* block\n import \"x\"; */ package a.b;"),
+            vec![
+                Token::Ident("package".to_owned()),
+                Token::Ident("a".to_owned()),
+                Token::Symbol('.'),
+                Token::Ident("b".to_owned()),
+                Token::Symbol(';'),
+            ]
+        );
+    }
+
+    #[test]
+    fn string_escapes_are_resolved() {
+        assert_eq!(
+            tokens(r#""a\"b\\c\x41\101\n" 'd'"#),
+            vec![
+                Token::String("a\"b\\cAA\n".to_owned()),
+                Token::String("d".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn unterminated_tokens_are_errors() {
+        assert!(matches!(
+            tokenize("import \"x;\nfoo"),
+            Err(ProtoParseError::UnterminatedString { line: 1 })
+        ));
+        assert!(matches!(
+            tokenize("\n/* foo"),
+            Err(ProtoParseError::UnterminatedComment { line: 2 })
+        ));
+    }
+}
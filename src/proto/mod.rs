@@ -0,0 +1,247 @@
+//! Just enough of a protobuf parser to follow and rewrite the imports of fetched files.
+
+mod lexer;
+
+use std::ops::Range;
+
+use thiserror::Error;
+
+use lexer::{tokenize, Spanned, Token};
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum ProtoParseError {
+    #[error("unterminated string on line {line}")]
+    UnterminatedString { line: usize },
+    #[error("unterminated block comment starting on line {line}")]
+    UnterminatedComment { line: usize },
+    #[error("malformed import statement on line {line}")]
+    MalformedImport { line: usize },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportKind {
+    Default,
+    /// Re-exports the imported file to everything importing this one.
+    Public,
+    /// The imported file may be missing.
+    Weak,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Import {
+    pub path: String,
+    pub kind: ImportKind,
+    /// Byte range of the path literal, including quotes, in the source.
+    pub span: Range<usize>,
+}
+
+/// Finds all `import` statements of a proto file.
+pub fn parse_imports(source: &str) -> Result<Vec<Import>, ProtoParseError> {
+    let tokens = tokenize(source)?;
+    let line = |token: &Spanned| source[..token.span.start].matches('\n').count() + 1;
+
+    let mut imports = Vec::new();
+    let mut statement_start = true;
+    let mut index = 0;
+    while let Some(token) = tokens.get(index) {
+        index += 1;
+        if !(statement_start && token.token == Token::Ident("import".to_owned())) {
+            statement_start = matches!(token.token, Token::Symbol(';' | '{' | '}'));
+            continue;
+        }
+
+        let malformed = || ProtoParseError::MalformedImport { line: line(token) };
+        let kind = match tokens.get(index).map(|token| &token.token) {
+            Some(Token::Ident(modifier)) if modifier == "public" => ImportKind::Public,
+            Some(Token::Ident(modifier)) if modifier == "weak" => ImportKind::Weak,
+            _ => ImportKind::Default,
+        };
+        if kind != ImportKind::Default {
+            index += 1;
+        }
+
+        // Adjacent string literals are concatenated
+        let mut path = String::new();
+        let mut span: Option<Range<usize>> = None;
+        while let Some(Spanned {
+            token: Token::String(part),
+            span: part_span,
+        }) = tokens.get(index)
+        {
+            path.push_str(part);
+            span = Some(span.map_or(part_span.clone(), |span| span.start..part_span.end));
+            index += 1;
+        }
+        let span = span.ok_or_else(malformed)?;
+        match tokens.get(index) {
+            Some(Spanned {
+                token: Token::Symbol(';'),
+                ..
+            }) => index += 1,
+            _ => return Err(malformed()),
+        }
+
+        imports.push(Import { path, kind, span });
+    }
+    Ok(imports)
+}
+
+/// The package and the top-level messages, enums and services of a proto file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Declarations {
+    pub package: Option<String>,
+    pub names: Vec<String>,
+}
+
+impl Declarations {
+    pub fn qualified_names(&self) -> impl Iterator<Item = String> + '_ {
+        self.names.iter().map(|name| match &self.package {
+            Some(package) => format!("{package}.{name}"),
+            None => name.clone(),
+        })
+    }
+}
+
+/// Finds the package and the top-level declarations of a proto file.
+pub fn parse_declarations(source: &str) -> Result<Declarations, ProtoParseError> {
+    let tokens = tokenize(source)?;
+
+    let mut declarations = Declarations::default();
+    let mut depth = 0usize;
+    let mut statement_start = true;
+    let mut index = 0;
+    while let Some(token) = tokens.get(index) {
+        index += 1;
+        match &token.token {
+            Token::Symbol('{') => depth += 1,
+            Token::Symbol('}') => depth = depth.saturating_sub(1),
+            Token::Ident(keyword) if depth == 0 && statement_start => match keyword.as_str() {
+                "package" => {
+                    let mut package = String::new();
+                    while let Some(Token::Ident(_) | Token::Symbol('.')) =
+                        tokens.get(index).map(|token| &token.token)
+                    {
+                        if let Token::Ident(part) = &tokens[index].token {
+                            package.push_str(part);
+                        } else {
+                            package.push('.');
+                        }
+                        index += 1;
+                    }
+                    declarations.package = Some(package);
+                }
+                "message" | "enum" | "service" => {
+                    if let Some(Token::Ident(name)) = tokens.get(index).map(|token| &token.token) {
+                        declarations.names.push(name.clone());
+                        index += 1;
+                    }
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+        statement_start = matches!(tokens[index - 1].token, Token::Symbol(';' | '{' | '}'));
+    }
+    Ok(declarations)
+}
+
+/// Replaces the paths of imports for which `rewrite` returns a new path.
+pub fn rewrite_imports(
+    source: &str,
+    rewrite: impl Fn(&str) -> Option<String>,
+) -> Result<String, ProtoParseError> {
+    let mut rewritten = String::with_capacity(source.len());
+    let mut copied = 0;
+    for import in parse_imports(source)? {
+        if let Some(path) = rewrite(&import.path) {
+            rewritten.push_str(&source[copied..import.span.start]);
+            rewritten.push('"');
+            rewritten.push_str(&path.replace('\\', "\\\\").replace('"', "\\\""));
+            rewritten.push('"');
+            copied = import.span.end;
+        }
+    }
+    rewritten.push_str(&source[copied..]);
+    Ok(rewritten)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths(imports: &[Import]) -> Vec<(&str, ImportKind)> {
+        imports
+            .iter()
+            .map(|import| (import.path.as_str(), import.kind))
+            .collect()
+    }
+
+    #[test]
+    fn imports_with_modifiers_are_found() {
+        let source = r#"
+            syntax = "proto3";
+              import public "a.proto";
+            import weak 'b.proto'; import "c" "/d.proto";
+            // import "commented.proto";
+            /* import "block.proto"; */
+            message M { string import = 1; }
+        "#;
+
+        let imports = parse_imports(source).unwrap();
+
+        assert_eq!(
+            paths(&imports),
+            vec![
+                ("a.proto", ImportKind::Public),
+                ("b.proto", ImportKind::Weak),
+                ("c/d.proto", ImportKind::Default),
+            ]
+        );
+    }
+
+    #[test]
+    fn malformed_import_is_reported_with_its_line() {
+        assert_eq!(
+            parse_imports("syntax = \"proto3\";\nimport foo;"),
+            Err(ProtoParseError::MalformedImport { line: 2 })
+        );
+    }
+
+    #[test]
+    fn top_level_declarations_are_found() {
+        let source = r#"
+            syntax = "proto3";
+            package acme.common.v1;
+            option java_package = "com.acme";
+            message Money { message Nested {} enum Currency { EUR = 0; } }
+            enum Status { OK = 0; }
+            service Billing { rpc Charge(Money) returns (Money); }
+            extend Money { string note = 100; }
+        "#;
+
+        let declarations = parse_declarations(source).unwrap();
+
+        assert_eq!(
+            declarations.qualified_names().collect::<Vec<_>>(),
+            vec![
+                "acme.common.v1.Money",
+                "acme.common.v1.Status",
+                "acme.common.v1.Billing",
+            ]
+        );
+    }
+
+    #[test]
+    fn rewriting_keeps_the_rest_of_the_source() {
+        let source = "import public \"a.proto\"; // a\nimport \"b.proto\";\n";
+
+        let rewritten = rewrite_imports(source, |path| {
+            (path == "a.proto").then(|| "x/a.proto".to_owned())
+        });
+
+        assert_eq!(
+            rewritten.unwrap(),
+            "import public \"x/a.proto\"; // a\nimport \"b.proto\";\n"
+        );
+    }
+}
@@ -1,4 +1,5 @@
 use crate::{
+    flock::LockKind,
     git::cache::ProtofetchGitCache,
     model::protofetch::{Coordinate, ModuleName, RevisionSpecification},
 };
@@ -13,13 +14,13 @@ impl ModuleResolver for ProtofetchGitCache {
         commit_hash: Option<&str>,
         name: &ModuleName,
     ) -> anyhow::Result<CommitAndDescriptor> {
+        let _lock = self.lock_coordinate(coordinate, LockKind::Exclusive)?;
         let repository = self.repository(coordinate)?;
         let commit_hash = if let Some(commit_hash) = commit_hash {
             repository.fetch_commit(specification, commit_hash)?;
             commit_hash.to_owned()
         } else {
-            repository.fetch(specification)?;
-            repository.resolve_commit_hash(specification)?
+            repository.fetch_and_resolve(specification)?
         };
         let descriptor = repository.extract_descriptor(name, &commit_hash)?;
         Ok(CommitAndDescriptor {
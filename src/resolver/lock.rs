@@ -136,6 +136,8 @@ mod tests {
                     name: name.clone(),
                     description: None,
                     proto_out_dir: None,
+                    output_layout: Default::default(),
+                    imports: Default::default(),
                     dependencies: Vec::new(),
                 },
             })
@@ -3,8 +3,9 @@
 mod infra;
 
 use infra::{
-    assert_output_contains, assert_output_excludes, run, run_locked, run_update_selected,
-    run_update_selected_error, FetchResult,
+    assert_output_contains, assert_output_excludes, run, run_error, run_locked, run_remove,
+    run_remove_with_output_files, run_update_selected, run_update_selected_breaking_error,
+    run_update_selected_dry_run, run_update_selected_error, FetchResult,
 };
 
 /// Fetch a single dependency with one proto file and assert the output tree.
@@ -157,6 +158,29 @@ fn revision_pin() {
     assert_output_excludes(&result, &["proto/v2.proto"]);
 }
 
+/// A commit hash pinned together with a branch is fetched even if no remote ref points to it,
+/// and rejected because it is not on the branch.
+#[test]
+fn revision_pin_off_branch() {
+    let error = run_error("revision_pin_off_branch");
+
+    assert!(
+        error.contains("does not belong to the branch main"),
+        "unexpected error: {error}"
+    );
+}
+
+/// The project root cannot be the output directory, whose files a fetch replaces.
+#[test]
+fn output_dir_project_root() {
+    let error = run_error("output_dir_project_root");
+
+    assert!(
+        error.contains("contains the project and cannot be used as the output directory"),
+        "unexpected error: {error}"
+    );
+}
+
 /// `transitive = true` on a dep makes it visible as a transitive dep for the
 /// prune import-graph walk of *all other* deps, even if those deps do not list
 /// it in their own `protofetch.toml`.
@@ -296,6 +320,55 @@ fn selected_precise_lock_update_rejects_revision_mismatch() {
     );
 }
 
+#[test]
+fn selected_lock_update_dry_run_keeps_the_lock_file() {
+    let (changed, result) = run_update_selected_dry_run("selected_lock_update", "repo1");
+
+    assert!(changed);
+    assert_lockfile_dependency_commit(&result, "repo1", "<commit:main:1>");
+    assert_lockfile_dependency_commit(&result, "repo2", "<commit:main:3>");
+}
+
+#[test]
+fn selected_lock_update_denies_breaking_changes() {
+    let error = run_update_selected_breaking_error("deny_breaking_changes", "repo1");
+
+    assert!(
+        error.contains("Found 1 breaking changes"),
+        "unexpected error: {error}"
+    );
+}
+
+#[test]
+fn remove_drops_the_lock_entry_and_output_files() {
+    let result = run_remove("two_repos_transitive_dep", "repo2");
+
+    assert_output_contains(&result, &["proto/v1.proto"]);
+    assert_output_excludes(&result, &["proto/b.proto"]);
+    assert!(
+        !result.snapshot_lockfile().contains("name = \"repo2\""),
+        "{}",
+        result.snapshot_lockfile()
+    );
+}
+
+/// Only the files protofetch wrote are removed from the output, files put there by other means
+/// are left alone.
+#[test]
+fn remove_keeps_files_protofetch_did_not_write() {
+    let result = run_remove_with_output_files(
+        "two_repos_transitive_dep",
+        "repo2",
+        &[
+            ("vendor/local.proto", "syntax = \"proto3\";\n"),
+            ("BUILD", ""),
+        ],
+    );
+
+    assert_output_contains(&result, &["proto/v1.proto", "vendor/local.proto", "BUILD"]);
+    assert_output_excludes(&result, &["proto/b.proto"]);
+}
+
 fn assert_lockfile_dependency_commit(result: &FetchResult, name: &str, commit: &str) {
     let snapshot = result.snapshot_lockfile();
     let dependency = snapshot
@@ -395,3 +468,126 @@ fn duplicate_dep_same_file_under_different_content_roots() {
 
     assert_output_contains(&result, &["consumer.proto", "nested/shared.proto"]);
 }
+
+/// With `output_layout = "per-module"` each module gets its own directory, so both
+/// dependencies keep their own `common/types.proto`, and the include paths are listed.
+#[test]
+fn per_module_layout() {
+    let result = run("per_module_layout");
+
+    assert_output_contains(
+        &result,
+        &[
+            "repo1/a.proto",
+            "repo1/common/types.proto",
+            "repo2/common/types.proto",
+            "include_paths.txt",
+        ],
+    );
+}
+
+/// `output_prefix` relocates the files of a dependency, so that two vendors shipping
+/// `api/v1/common.proto` can be used together. Imports of relocated files are rewritten,
+/// while the other vendor keeps importing its own file.
+#[test]
+fn output_prefix() {
+    let result = run("output_prefix");
+
+    assert_output_contains(
+        &result,
+        &[
+            "vendor/acme/api/v1/common.proto",
+            "vendor/acme/api/v1/service.proto",
+            "api/v1/common.proto",
+            "api/v1/service.proto",
+        ],
+    );
+}
+
+/// Pruning follows `import public` and `import weak` statements wherever they are on a line,
+/// and ignores imports inside comments.
+#[test]
+fn prune_follows_public_and_indented_imports() {
+    let result = run("prune_follows_public_and_indented_imports");
+
+    assert_output_contains(
+        &result,
+        &[
+            "public/service.proto",
+            "internal/common.proto",
+            "shared.proto",
+        ],
+    );
+    assert_output_excludes(&result, &["internal/admin.proto", "unused.proto"]);
+}
+
+/// With fetch_wellknown = true well-known types are fetched from the dependency while imports
+/// matching provided_imports are left to the toolchain.
+#[test]
+fn provided_imports() {
+    let result = run("provided_imports");
+
+    assert_output_contains(
+        &result,
+        &[
+            "public/service.proto",
+            "internal/common.proto",
+            "google/protobuf/any.proto",
+        ],
+    );
+    assert_output_excludes(
+        &result,
+        &["internal/admin.proto", "google/protobuf/empty.proto"],
+    );
+}
+
+/// A file whose imports cannot be read is still copied, but pruning does not follow its imports.
+#[test]
+fn unparsable_imports() {
+    let result = run("unparsable_imports");
+
+    assert_output_contains(
+        &result,
+        &[
+            "public/service.proto",
+            "internal/common.proto",
+            "internal/broken.proto",
+        ],
+    );
+    assert_output_excludes(&result, &["internal/only_via_broken.proto"]);
+}
+
+/// With strict_imports = true an import that pruning cannot find fails the fetch, while weak
+/// imports may still be missing.
+#[test]
+fn strict_imports() {
+    let error = run_error("strict_imports");
+
+    assert!(
+        error.contains(
+            "missing/gone.proto imported by public/service.proto in dep_parent, \
+             searched dep_parent -> dep_child"
+        ),
+        "unexpected error: {error}"
+    );
+    assert!(
+        !error.contains("missing/optional.proto"),
+        "unexpected error: {error}"
+    );
+}
+
+/// Files of non-pruned dependencies are checked too: an import that allow_policies kept out of
+/// the output fails the fetch with strict_imports = true.
+#[test]
+fn dangling_imports() {
+    let error = run_error("dangling_imports");
+
+    assert!(
+        error.contains("internal/common.proto imported by public/service.proto"),
+        "unexpected error: {error}"
+    );
+    assert!(
+        !error.contains("google/protobuf"),
+        "unexpected error: {error}"
+    );
+}
@@ -11,7 +11,7 @@ use std::{
 
 use git2::{build::CheckoutBuilder, IndexAddOption, Repository, Signature};
 use insta::{assert_snapshot, Settings};
-use protofetch::{DependencyUpdate, LockMode, LockUpdateMode, Protofetch};
+use protofetch::{BreakingChangePolicy, DependencyUpdate, LockMode, LockUpdateMode, Protofetch};
 use tempfile::TempDir;
 
 /// A local git repository created by [`TestWorld::create_repo`].
@@ -95,6 +95,8 @@ pub struct TestWorld {
     cache: TempDir,
     /// All repos created by [`TestWorld::create_repo`], in creation order.
     repos: Vec<TestRepo>,
+    /// Breaking change policy used by updates.
+    breaking_changes: BreakingChangePolicy,
 }
 
 impl TestWorld {
@@ -104,6 +106,7 @@ impl TestWorld {
             project: TempDir::new().expect("project TempDir"),
             cache: TempDir::new().expect("cache TempDir"),
             repos: Vec::new(),
+            breaking_changes: BreakingChangePolicy::Ignore,
         }
     }
 
@@ -158,11 +161,16 @@ impl TestWorld {
         result
     }
 
-    fn run_update_error(name: &str, lock_update_mode: LockUpdateMode) -> String {
+    fn run_update_error(
+        name: &str,
+        lock_update_mode: LockUpdateMode,
+        breaking_changes: BreakingChangePolicy,
+    ) -> String {
         let fixture = Path::new(env!("CARGO_MANIFEST_DIR"))
             .join("tests/e2e")
             .join(name);
         let mut world = Self::new();
+        world.breaking_changes = breaking_changes;
         world.load_fixture_repos(&fixture);
 
         let lock_update_mode =
@@ -178,6 +186,68 @@ impl TestWorld {
         }
     }
 
+    fn run_update_dry_run(name: &str, lock_update_mode: LockUpdateMode) -> (bool, FetchResult) {
+        let fixture = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/e2e")
+            .join(name);
+        let mut world = Self::new();
+        world.load_fixture_repos(&fixture);
+
+        let lock_update_mode =
+            resolve_lock_update_mode_labels(lock_update_mode, world.remotes.path(), &world.repos);
+
+        let manifest = fs::read_to_string(fixture.join("protofetch.toml"))
+            .expect("read fixture protofetch.toml");
+        let initial_lock = fs::read_to_string(fixture.join("protofetch.lock")).ok();
+        world.write_project(&manifest, initial_lock.as_deref());
+
+        let changed = world
+            .protofetch()
+            .update_dry_run(lock_update_mode)
+            .expect("protofetch update --dry-run");
+        (changed, world.snapshot_project())
+    }
+
+    /// Fetches with `output_files` already in the output directory, then removes `dependency`.
+    fn run_remove(name: &str, dependency: &str, output_files: &[(&str, &str)]) -> FetchResult {
+        let fixture = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/e2e")
+            .join(name);
+        let mut world = Self::new();
+        world.load_fixture_repos(&fixture);
+
+        let manifest = fs::read_to_string(fixture.join("protofetch.toml"))
+            .expect("read fixture protofetch.toml");
+        for (path, content) in output_files {
+            let path = world.project.path().join("proto_src").join(path);
+            fs::create_dir_all(path.parent().unwrap()).expect("create output directory");
+            fs::write(path, content).expect("write output file");
+        }
+        world.fetch_files(&manifest, None, LockMode::Update);
+        world
+            .protofetch()
+            .remove(dependency)
+            .expect("protofetch remove");
+        world.snapshot_project()
+    }
+
+    fn run_error(name: &str, lock_mode: LockMode) -> String {
+        let fixture = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/e2e")
+            .join(name);
+        let mut world = Self::new();
+        world.load_fixture_repos(&fixture);
+
+        let manifest = fs::read_to_string(fixture.join("protofetch.toml"))
+            .expect("read fixture protofetch.toml");
+        let initial_lock = fs::read_to_string(fixture.join("protofetch.lock")).ok();
+
+        match world.fetch_files_result(&manifest, initial_lock.as_deref(), lock_mode) {
+            Ok(_) => panic!("protofetch fetch should fail"),
+            Err(error) => error.to_string(),
+        }
+    }
+
     fn load_fixture_repos(&mut self, fixture: &Path) {
         let mut commits = Vec::new();
         collect_fixture_commits(fixture, fixture, &mut commits);
@@ -291,25 +361,19 @@ impl TestWorld {
         initial_lock: Option<&str>,
         lock_mode: LockMode,
     ) -> FetchResult {
-        fs::write(
-            self.project.path().join("protofetch.toml"),
-            resolve_labels(
-                &prepare_manifest(manifest, self.remotes.path()),
-                self.remotes.path(),
-                &self.repos,
-            ),
-        )
-        .expect("write protofetch.toml");
-
-        if let Some(initial_lock) = initial_lock {
-            fs::write(
-                self.project.path().join("protofetch.lock"),
-                resolve_labels(initial_lock, self.remotes.path(), &self.repos),
-            )
-            .expect("write initial protofetch.lock");
-        }
+        self.fetch_files_result(manifest, initial_lock, lock_mode)
+            .expect("protofetch fetch")
+    }
 
-        self.fetch_project(lock_mode)
+    fn fetch_files_result(
+        &self,
+        manifest: &str,
+        initial_lock: Option<&str>,
+        lock_mode: LockMode,
+    ) -> Result<FetchResult, Box<dyn Error>> {
+        self.write_project(manifest, initial_lock);
+        self.protofetch().fetch(lock_mode)?;
+        Ok(self.snapshot_project())
     }
 
     fn update_files(
@@ -328,6 +392,12 @@ impl TestWorld {
         initial_lock: Option<&str>,
         lock_update_mode: LockUpdateMode,
     ) -> Result<FetchResult, Box<dyn Error>> {
+        self.write_project(manifest, initial_lock);
+        self.protofetch().update(lock_update_mode)?;
+        Ok(self.snapshot_project())
+    }
+
+    fn write_project(&self, manifest: &str, initial_lock: Option<&str>) {
         fs::write(
             self.project.path().join("protofetch.toml"),
             resolve_labels(
@@ -345,16 +415,6 @@ impl TestWorld {
             )
             .expect("write initial protofetch.lock");
         }
-
-        self.protofetch().update(lock_update_mode)?;
-        Ok(self.snapshot_project())
-    }
-
-    fn fetch_project(&self, lock_mode: LockMode) -> FetchResult {
-        let pf = self.protofetch();
-
-        pf.fetch(lock_mode).expect("protofetch fetch");
-        self.snapshot_project()
     }
 
     fn protofetch(&self) -> Protofetch {
@@ -363,6 +423,7 @@ impl TestWorld {
             .cache_directory(self.cache.path().to_path_buf())
             .jobs(4)
             .copy_jobs(2)
+            .breaking_changes(self.breaking_changes)
             .try_build()
             .expect("build Protofetch")
     }
@@ -388,12 +449,32 @@ pub fn run(name: &str) -> FetchResult {
     TestWorld::run(name, LockMode::Update)
 }
 
+pub fn run_error(name: &str) -> String {
+    TestWorld::run_error(name, LockMode::Update)
+}
+
 pub fn run_update_selected(name: &str, dep: &str, precise: Option<&str>) -> FetchResult {
     TestWorld::run_update(name, selected_update_mode(dep, precise))
 }
 
 pub fn run_update_selected_error(name: &str, dep: &str, precise: &str) -> String {
-    TestWorld::run_update_error(name, selected_update_mode(dep, Some(precise)))
+    TestWorld::run_update_error(
+        name,
+        selected_update_mode(dep, Some(precise)),
+        BreakingChangePolicy::Ignore,
+    )
+}
+
+pub fn run_update_selected_dry_run(name: &str, dep: &str) -> (bool, FetchResult) {
+    TestWorld::run_update_dry_run(name, selected_update_mode(dep, None))
+}
+
+pub fn run_update_selected_breaking_error(name: &str, dep: &str) -> String {
+    TestWorld::run_update_error(
+        name,
+        selected_update_mode(dep, None),
+        BreakingChangePolicy::Deny,
+    )
 }
 
 fn selected_update_mode(dep: &str, precise: Option<&str>) -> LockUpdateMode {
@@ -410,6 +491,18 @@ fn selected_update_mode(dep: &str, precise: Option<&str>) -> LockUpdateMode {
     LockUpdateMode::ReconcileAndUpdate(updates)
 }
 
+pub fn run_remove(name: &str, dep: &str) -> FetchResult {
+    TestWorld::run_remove(name, dep, &[])
+}
+
+pub fn run_remove_with_output_files(
+    name: &str,
+    dep: &str,
+    output_files: &[(&str, &str)],
+) -> FetchResult {
+    TestWorld::run_remove(name, dep, output_files)
+}
+
 pub fn run_locked(name: &str) -> FetchResult {
     TestWorld::run(name, LockMode::Locked)
 }
@@ -649,6 +742,13 @@ fn collect_entries(base: &Path, dir: &Path, entries: &mut BTreeMap<String, Strin
     children.sort();
 
     for path in children {
+        // The manifest of written files would be repeated in every snapshot
+        if path
+            .file_name()
+            .is_some_and(|name| name == ".protofetch-manifest")
+        {
+            continue;
+        }
         if path.is_dir() {
             collect_entries(base, &path, entries);
         } else {